@@ -39,7 +39,7 @@ fn main() {
     };
     game.connect(
         Box::new(simon),
-        Rect::new(0.5, 0.5, -1.0, -1.0),
+        Rect::new(400.0, 400.0, -200.0, -200.0),
         "nature.png",
     )
     .expect("Could not load nature.png");
@@ -0,0 +1,697 @@
+// Loader for Aseprite's binary .aseprite/.ase format.
+//
+// Visible layers are flattened into one RGBA8 image per frame, all frames are packed into an
+// Atlas and tags become AnimationClips with the durations of the frames they cover.
+// Format reference: https://github.com/aseprite/aseprite/blob/main/docs/ase-file-specs.md
+
+use crate::asset::{Atlas, Region};
+
+use std::time::Duration;
+
+const HEADER_MAGIC: u16 = 0xA5E0;
+const FRAME_MAGIC: u16 = 0xF1FA;
+
+const CHUNK_OLD_PALETTE: u16 = 0x0004;
+const CHUNK_LAYER: u16 = 0x2004;
+const CHUNK_CEL: u16 = 0x2005;
+const CHUNK_TAGS: u16 = 0x2018;
+const CHUNK_PALETTE: u16 = 0x2019;
+
+const LAYER_VISIBLE: u16 = 1;
+const LAYER_BACKGROUND: u16 = 8;
+const LAYER_TYPE_GROUP: u16 = 1;
+
+#[derive(Debug, Clone)]
+pub struct Aseprite {
+    pub width: u32,
+    pub height: u32,
+    pub atlas: Atlas,
+    pub frames: Vec<Frame>,
+    pub clips: Vec<AnimationClip>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Frame {
+    pub region: Region,
+    pub duration: Duration,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    Forward,
+    Reverse,
+    PingPong,
+    PingPongReverse,
+}
+
+// An animation built from an Aseprite tag.
+// frames holds (frame index, duration) pairs in the order they play for one loop.
+#[derive(Debug, Clone)]
+pub struct AnimationClip {
+    pub name: String,
+    pub direction: Direction,
+    // 0 means loop forever
+    pub repeat: u16,
+    pub frames: Vec<(usize, Duration)>,
+}
+
+impl AnimationClip {
+    pub fn length(&self) -> Duration {
+        self.frames.iter().map(|f| f.1).sum()
+    }
+
+    // Returns the frame index that should be shown after elapsed time of playback.
+    pub fn frame_at(&self, elapsed: Duration) -> usize {
+        let total = self.length().as_millis();
+        if total == 0 || self.frames.is_empty() {
+            return self.frames.first().map_or(0, |f| f.0);
+        }
+
+        let mut t = elapsed.as_millis();
+        if self.repeat != 0 && t >= total * self.repeat as u128 {
+            return self.frames[self.frames.len() - 1].0;
+        }
+        t %= total;
+        for (index, duration) in &self.frames {
+            if t < duration.as_millis() {
+                return *index;
+            }
+            t -= duration.as_millis();
+        }
+        self.frames[self.frames.len() - 1].0
+    }
+}
+
+impl Aseprite {
+    pub fn clip(&self, name: &str) -> Option<&AnimationClip> {
+        self.clips.iter().find(|c| c.name == name)
+    }
+
+    // Returns a single frame as a tightly packed RGBA8 image.
+    pub fn frame_pixels(&self, frame: usize) -> Vec<u8> {
+        self.atlas.crop(self.frames[frame].region)
+    }
+}
+
+struct Layer {
+    visible: bool,
+    background: bool,
+    group: bool,
+    opacity: u8,
+}
+
+#[derive(Clone)]
+struct Cel {
+    layer: usize,
+    x: i32,
+    y: i32,
+    z_index: i32,
+    opacity: u8,
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+}
+
+struct Tag {
+    from: usize,
+    to: usize,
+    direction: Direction,
+    repeat: u16,
+    name: String,
+}
+
+pub fn load(path: &str) -> Result<Aseprite, String> {
+    let bytes = std::fs::read(path).map_err(|e| format!("Could not open {}: {}", path, e))?;
+    parse(&bytes).map_err(|e| format!("Could not parse {}: {}", path, e))
+}
+
+pub fn parse(bytes: &[u8]) -> Result<Aseprite, String> {
+    let mut r = Reader::new(bytes);
+
+    // Header
+    r.u32()?;
+    if r.u16()? != HEADER_MAGIC {
+        return Err(String::from("not an aseprite file"));
+    }
+    let frame_count = r.u16()? as usize;
+    let width = r.u16()? as u32;
+    let height = r.u16()? as u32;
+    let depth = r.u16()?;
+    let flags = r.u32()?;
+    r.u16()?;
+    r.skip(8)?;
+    let transparent_index = r.u8()?;
+    r.skip(128 - 29)?;
+
+    if frame_count == 0 {
+        return Err(String::from("file has no frames"));
+    }
+    if depth != 32 && depth != 16 && depth != 8 {
+        return Err(format!("unsupported color depth {}", depth));
+    }
+    let layer_opacity_valid = flags & 1 != 0;
+
+    let mut layers: Vec<Layer> = Vec::new();
+    let mut groups: Vec<bool> = Vec::new();
+    let mut palette: Vec<[u8; 4]> = Vec::new();
+    let mut has_new_palette = false;
+    let mut tags: Vec<Tag> = Vec::new();
+    let mut durations = Vec::with_capacity(frame_count);
+    let mut cels: Vec<Vec<Cel>> = Vec::with_capacity(frame_count);
+
+    for frame in 0..frame_count {
+        let frame_start = r.pos;
+        let frame_len = r.u32()? as usize;
+        if r.u16()? != FRAME_MAGIC {
+            return Err(format!("bad magic number in frame {}", frame));
+        }
+        let old_chunks = r.u16()? as usize;
+        durations.push(Duration::from_millis(r.u16()? as u64));
+        r.skip(2)?;
+        let chunks = match r.u32()? as usize {
+            0 => old_chunks,
+            n => n,
+        };
+
+        let mut frame_cels = Vec::new();
+        for _ in 0..chunks {
+            let chunk_start = r.pos;
+            let chunk_len = r.u32()? as usize;
+            let chunk_type = r.u16()?;
+            let end = chunk_start + chunk_len;
+
+            match chunk_type {
+                CHUNK_LAYER => {
+                    let flags = r.u16()?;
+                    let kind = r.u16()?;
+                    let level = r.u16()? as usize;
+                    r.skip(6)?;
+                    let opacity = r.u8()?;
+
+                    // A layer is only visible if every group above it is
+                    groups.truncate(level);
+                    let visible = flags & LAYER_VISIBLE != 0 && groups.iter().all(|g| *g);
+                    if kind == LAYER_TYPE_GROUP {
+                        groups.push(visible);
+                    }
+
+                    layers.push(Layer {
+                        visible: visible,
+                        background: flags & LAYER_BACKGROUND != 0,
+                        group: kind != 0,
+                        opacity: if layer_opacity_valid { opacity } else { 255 },
+                    });
+                }
+                CHUNK_CEL => {
+                    let layer = r.u16()? as usize;
+                    let x = r.i16()? as i32;
+                    let y = r.i16()? as i32;
+                    let opacity = r.u8()?;
+                    let kind = r.u16()?;
+                    let z_index = r.i16()? as i32;
+                    r.skip(5)?;
+
+                    let mut cel = Cel {
+                        layer: layer,
+                        x: x,
+                        y: y,
+                        z_index: z_index,
+                        opacity: opacity,
+                        width: 0,
+                        height: 0,
+                        data: Vec::new(),
+                    };
+                    match kind {
+                        0 | 2 => {
+                            cel.width = r.u16()? as u32;
+                            cel.height = r.u16()? as u32;
+                            let raw = r.bytes(end.saturating_sub(r.pos))?;
+                            cel.data = if kind == 2 {
+                                inflate::inflate_bytes_zlib(raw)?
+                            } else {
+                                raw.to_vec()
+                            };
+                            let expected = (cel.width as usize)
+                                .checked_mul(cel.height as usize)
+                                .and_then(|n| n.checked_mul(depth as usize / 8))
+                                .ok_or_else(|| format!("cel in frame {} is too large", frame))?;
+                            if cel.data.len() < expected {
+                                return Err(format!("cel in frame {} is truncated", frame));
+                            }
+                        }
+                        1 => {
+                            let linked = r.u16()? as usize;
+                            match cels
+                                .get(linked)
+                                .and_then(|f| f.iter().find(|c| c.layer == layer))
+                            {
+                                Some(c) => cel = c.clone(),
+                                None => {
+                                    return Err(format!("broken linked cel in frame {}", frame))
+                                }
+                            }
+                        }
+                        // Tilemap cels
+                        _ => {}
+                    }
+                    if !cel.data.is_empty() {
+                        frame_cels.push(cel);
+                    }
+                }
+                CHUNK_TAGS => {
+                    let count = r.u16()?;
+                    r.skip(8)?;
+                    for _ in 0..count {
+                        let from = r.u16()? as usize;
+                        let to = r.u16()? as usize;
+                        let direction = match r.u8()? {
+                            1 => Direction::Reverse,
+                            2 => Direction::PingPong,
+                            3 => Direction::PingPongReverse,
+                            _ => Direction::Forward,
+                        };
+                        let repeat = r.u16()?;
+                        r.skip(10)?;
+                        tags.push(Tag {
+                            from: from,
+                            to: to,
+                            direction: direction,
+                            repeat: repeat,
+                            name: r.string()?,
+                        });
+                    }
+                }
+                CHUNK_PALETTE => {
+                    has_new_palette = true;
+                    let size = r.u32()? as usize;
+                    let first = r.u32()? as usize;
+                    let last = r.u32()? as usize;
+                    r.skip(8)?;
+                    palette.resize(size.max(palette.len()), [0, 0, 0, 0]);
+                    for i in first..=last {
+                        let entry_flags = r.u16()?;
+                        let color = [r.u8()?, r.u8()?, r.u8()?, r.u8()?];
+                        if entry_flags & 1 != 0 {
+                            r.string()?;
+                        }
+                        if i < palette.len() {
+                            palette[i] = color;
+                        }
+                    }
+                }
+                CHUNK_OLD_PALETTE if !has_new_palette => {
+                    let packets = r.u16()?;
+                    let mut index = 0;
+                    for _ in 0..packets {
+                        index += r.u8()? as usize;
+                        let count = match r.u8()? {
+                            0 => 256,
+                            n => n as usize,
+                        };
+                        if palette.len() < index + count {
+                            palette.resize(index + count, [0, 0, 0, 0]);
+                        }
+                        for _ in 0..count {
+                            palette[index] = [r.u8()?, r.u8()?, r.u8()?, 255];
+                            index += 1;
+                        }
+                    }
+                }
+                _ => {}
+            }
+            r.seek(end)?;
+        }
+        cels.push(frame_cels);
+        r.seek(frame_start + frame_len)?;
+    }
+
+    let images = cels
+        .iter()
+        .map(|frame_cels| {
+            flatten(
+                frame_cels,
+                &layers,
+                &palette,
+                depth,
+                transparent_index,
+                width,
+                height,
+            )
+        })
+        .collect::<Vec<_>>();
+    let (atlas, regions) = Atlas::pack(&images, width, height);
+
+    let frames = regions
+        .into_iter()
+        .zip(durations.into_iter())
+        .map(|(region, duration)| Frame {
+            region: region,
+            duration: duration,
+        })
+        .collect::<Vec<_>>();
+
+    let clips = tags
+        .into_iter()
+        .filter(|t| t.from <= t.to && t.to < frames.len())
+        .map(|t| {
+            let forward = (t.from..=t.to).collect::<Vec<_>>();
+            let backward = forward.iter().rev().cloned().collect::<Vec<_>>();
+            let order = match t.direction {
+                Direction::Forward => forward,
+                Direction::Reverse => backward,
+                // Ping-pong doesn't repeat the frames at either end
+                Direction::PingPong => {
+                    let back = backward[1..backward.len().max(2) - 1].to_vec();
+                    forward.into_iter().chain(back).collect()
+                }
+                Direction::PingPongReverse => {
+                    let back = forward[1..forward.len().max(2) - 1].to_vec();
+                    backward.into_iter().chain(back).collect()
+                }
+            };
+            AnimationClip {
+                name: t.name,
+                direction: t.direction,
+                repeat: t.repeat,
+                frames: order.into_iter().map(|i| (i, frames[i].duration)).collect(),
+            }
+        })
+        .collect();
+
+    Ok(Aseprite {
+        width: width,
+        height: height,
+        atlas: atlas,
+        frames: frames,
+        clips: clips,
+    })
+}
+
+// Composites the visible cels of one frame onto a transparent canvas.
+// Every layer is blended with the normal blend mode.
+fn flatten(
+    cels: &[Cel],
+    layers: &[Layer],
+    palette: &[[u8; 4]],
+    depth: u16,
+    transparent_index: u8,
+    width: u32,
+    height: u32,
+) -> Vec<u8> {
+    let mut canvas = vec![0u8; width as usize * height as usize * 4];
+
+    let mut order = cels
+        .iter()
+        .filter(|c| c.layer < layers.len() && layers[c.layer].visible && !layers[c.layer].group)
+        .collect::<Vec<_>>();
+    order.sort_by_key(|c| (c.layer as i32 + c.z_index, c.z_index));
+
+    for cel in order {
+        let layer = &layers[cel.layer];
+        let opacity = cel.opacity as u32 * layer.opacity as u32 / 255;
+
+        for cy in 0..cel.height {
+            for cx in 0..cel.width {
+                let (x, y) = (cel.x + cx as i32, cel.y + cy as i32);
+                if x < 0 || y < 0 || x >= width as i32 || y >= height as i32 {
+                    continue;
+                }
+                let i = cy as usize * cel.width as usize + cx as usize;
+                let src = match depth {
+                    32 => [
+                        cel.data[i * 4],
+                        cel.data[i * 4 + 1],
+                        cel.data[i * 4 + 2],
+                        cel.data[i * 4 + 3],
+                    ],
+                    16 => {
+                        let v = cel.data[i * 2];
+                        [v, v, v, cel.data[i * 2 + 1]]
+                    }
+                    _ => {
+                        let index = cel.data[i];
+                        if index == transparent_index && !layer.background {
+                            continue;
+                        }
+                        palette.get(index as usize).cloned().unwrap_or([0, 0, 0, 0])
+                    }
+                };
+
+                let dst = (y as usize * width as usize + x as usize) * 4;
+                blend_over(&mut canvas[dst..dst + 4], src, opacity);
+            }
+        }
+    }
+    canvas
+}
+
+// Non-premultiplied source-over blending.
+fn blend_over(dst: &mut [u8], src: [u8; 4], opacity: u32) {
+    let sa = src[3] as u32 * opacity / 255;
+    if sa == 0 {
+        return;
+    }
+    let da = dst[3] as u32;
+    let out_a = sa + da * (255 - sa) / 255;
+    for c in 0..3 {
+        let blended = (src[c] as u32 * sa + dst[c] as u32 * da * (255 - sa) / 255) / out_a;
+        dst[c] = blended as u8;
+    }
+    dst[3] = out_a as u8;
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Reader {
+            bytes: bytes,
+            pos: 0,
+        }
+    }
+
+    fn bytes(&mut self, n: usize) -> Result<&'a [u8], String> {
+        if self.pos + n > self.bytes.len() {
+            return Err(String::from("unexpected end of file"));
+        }
+        let out = &self.bytes[self.pos..self.pos + n];
+        self.pos += n;
+        Ok(out)
+    }
+
+    fn skip(&mut self, n: usize) -> Result<(), String> {
+        self.bytes(n).map(|_| ())
+    }
+
+    fn seek(&mut self, pos: usize) -> Result<(), String> {
+        if pos > self.bytes.len() {
+            return Err(String::from("unexpected end of file"));
+        }
+        self.pos = pos;
+        Ok(())
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_le_bytes([b[0], b[1]]))
+    }
+
+    fn i16(&mut self) -> Result<i16, String> {
+        Ok(self.u16()? as i16)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    }
+
+    fn string(&mut self) -> Result<String, String> {
+        let len = self.u16()? as usize;
+        Ok(String::from_utf8_lossy(self.bytes(len)?).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: [u8; 4] = [255, 0, 0, 255];
+    const BLUE: [u8; 4] = [0, 0, 255, 255];
+
+    // Builds a file of 2x1 pixel RGBA frames out of (duration, chunks) pairs, where a chunk is
+    // its type and data.
+    fn file(frames: &[(u16, Vec<(u16, Vec<u8>)>)]) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&0u32.to_le_bytes());
+        out.extend_from_slice(&HEADER_MAGIC.to_le_bytes());
+        out.extend_from_slice(&(frames.len() as u16).to_le_bytes());
+        out.extend_from_slice(&2u16.to_le_bytes());
+        out.extend_from_slice(&1u16.to_le_bytes());
+        out.extend_from_slice(&32u16.to_le_bytes());
+        // Layer opacity is valid
+        out.extend_from_slice(&1u32.to_le_bytes());
+        out.resize(128, 0);
+
+        for (duration, chunks) in frames {
+            let mut frame = Vec::new();
+            for (kind, data) in chunks {
+                frame.extend_from_slice(&(data.len() as u32 + 6).to_le_bytes());
+                frame.extend_from_slice(&kind.to_le_bytes());
+                frame.extend_from_slice(data);
+            }
+            out.extend_from_slice(&(frame.len() as u32 + 16).to_le_bytes());
+            out.extend_from_slice(&FRAME_MAGIC.to_le_bytes());
+            out.extend_from_slice(&(chunks.len() as u16).to_le_bytes());
+            out.extend_from_slice(&duration.to_le_bytes());
+            out.extend_from_slice(&[0; 6]);
+            out.extend_from_slice(&frame);
+        }
+        out
+    }
+
+    fn layer(opacity: u8) -> (u16, Vec<u8>) {
+        let mut data = Vec::new();
+        data.extend_from_slice(&LAYER_VISIBLE.to_le_bytes());
+        data.extend_from_slice(&[0; 10]);
+        data.push(opacity);
+        (CHUNK_LAYER, data)
+    }
+
+    fn cel(layer: u16, opacity: u8, pixels: &[[u8; 4]]) -> (u16, Vec<u8>) {
+        let mut data = Vec::new();
+        data.extend_from_slice(&layer.to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data.push(opacity);
+        data.extend_from_slice(&[0; 9]);
+        data.extend_from_slice(&(pixels.len() as u16).to_le_bytes());
+        data.extend_from_slice(&1u16.to_le_bytes());
+        for pixel in pixels {
+            data.extend_from_slice(pixel);
+        }
+        (CHUNK_CEL, data)
+    }
+
+    fn linked_cel(layer: u16, frame: u16) -> (u16, Vec<u8>) {
+        let mut data = Vec::new();
+        data.extend_from_slice(&layer.to_le_bytes());
+        data.extend_from_slice(&[0; 4]);
+        data.push(255);
+        data.extend_from_slice(&1u16.to_le_bytes());
+        data.extend_from_slice(&[0; 7]);
+        data.extend_from_slice(&frame.to_le_bytes());
+        (CHUNK_CEL, data)
+    }
+
+    fn tags(tags: &[(u16, u16, u8, &str)]) -> (u16, Vec<u8>) {
+        let mut data = Vec::new();
+        data.extend_from_slice(&(tags.len() as u16).to_le_bytes());
+        data.extend_from_slice(&[0; 8]);
+        for (from, to, direction, name) in tags {
+            data.extend_from_slice(&from.to_le_bytes());
+            data.extend_from_slice(&to.to_le_bytes());
+            data.push(*direction);
+            data.extend_from_slice(&[0; 12]);
+            data.extend_from_slice(&(name.len() as u16).to_le_bytes());
+            data.extend_from_slice(name.as_bytes());
+        }
+        (CHUNK_TAGS, data)
+    }
+
+    #[test]
+    fn header() {
+        let bytes = file(&[(100, vec![layer(255), cel(0, 255, &[RED, BLUE])])]);
+        let sprite = parse(&bytes).unwrap();
+        assert_eq!((sprite.width, sprite.height), (2, 1));
+        assert_eq!(sprite.frames.len(), 1);
+        assert_eq!(sprite.frames[0].duration, Duration::from_millis(100));
+        assert_eq!(sprite.frame_pixels(0), [RED, BLUE].concat());
+    }
+
+    #[test]
+    fn no_frames() {
+        assert!(parse(&file(&[])).is_err());
+        let mut bytes = file(&[(100, vec![layer(255)])]);
+        bytes[6] = 0;
+        assert!(parse(&bytes).is_err());
+    }
+
+    #[test]
+    fn truncated_cel() {
+        let (kind, mut data) = cel(0, 255, &[RED, BLUE]);
+        data.truncate(data.len() - 4);
+        let bytes = file(&[(100, vec![layer(255), (kind, data)])]);
+        assert!(parse(&bytes).is_err());
+    }
+
+    #[test]
+    fn linked_cels() {
+        let bytes = file(&[
+            (100, vec![layer(255), cel(0, 255, &[RED, BLUE])]),
+            (100, vec![linked_cel(0, 0)]),
+        ]);
+        let sprite = parse(&bytes).unwrap();
+        assert_eq!(sprite.frame_pixels(1), sprite.frame_pixels(0));
+        assert_ne!(sprite.frames[1].region, sprite.frames[0].region);
+
+        let broken = file(&[(100, vec![layer(255), linked_cel(0, 1)])]);
+        assert!(parse(&broken).is_err());
+    }
+
+    #[test]
+    fn tags_become_clips() {
+        let bytes = file(&[
+            (
+                100,
+                vec![
+                    layer(255),
+                    cel(0, 255, &[RED, RED]),
+                    tags(&[(0, 2, 0, "walk"), (0, 2, 2, "bounce"), (1, 5, 0, "broken")]),
+                ],
+            ),
+            (200, vec![cel(0, 255, &[BLUE, BLUE])]),
+            (300, vec![cel(0, 255, &[RED, BLUE])]),
+        ]);
+        let sprite = parse(&bytes).unwrap();
+        assert_eq!(sprite.clips.len(), 2);
+
+        let walk = sprite.clip("walk").unwrap();
+        assert_eq!(walk.direction, Direction::Forward);
+        assert_eq!(walk.length(), Duration::from_millis(600));
+        assert_eq!(walk.frame_at(Duration::from_millis(150)), 1);
+        assert_eq!(walk.frame_at(Duration::from_millis(650)), 0);
+
+        let bounce = sprite.clip("bounce").unwrap();
+        let order: Vec<usize> = bounce.frames.iter().map(|f| f.0).collect();
+        assert_eq!(order, vec![0, 1, 2, 1]);
+        assert!(sprite.clip("broken").is_none());
+    }
+
+    #[test]
+    fn opacity_blending() {
+        let bytes = file(&[(
+            100,
+            vec![
+                layer(255),
+                layer(128),
+                cel(0, 255, &[RED, RED]),
+                cel(1, 255, &[BLUE, [0, 0, 255, 0]]),
+            ],
+        )]);
+        let sprite = parse(&bytes).unwrap();
+        assert_eq!(sprite.frame_pixels(0), [[127, 0, 128, 255], RED].concat());
+
+        let faded = file(&[(100, vec![layer(255), cel(0, 51, &[RED, BLUE])])]);
+        let sprite = parse(&faded).unwrap();
+        assert_eq!(
+            sprite.frame_pixels(0),
+            [[255, 0, 0, 51], [0, 0, 255, 51]].concat()
+        );
+    }
+}
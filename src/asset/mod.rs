@@ -0,0 +1,67 @@
+pub mod aseprite;
+pub mod tiled;
+
+// A packed RGBA8 image holding several sprite frames side by side.
+#[derive(Debug, Clone)]
+pub struct Atlas {
+    pub pixels: Vec<u8>,
+    pub width: u32,
+    pub height: u32,
+}
+
+// Region of a single frame inside an Atlas, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Region {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Atlas {
+    // Packs equally sized RGBA8 frames into a roughly square grid.
+    pub fn pack(frames: &[Vec<u8>], width: u32, height: u32) -> (Atlas, Vec<Region>) {
+        let columns = (frames.len() as f32).sqrt().ceil().max(1.0) as u32;
+        let rows = (frames.len() as u32 + columns - 1) / columns;
+
+        let mut atlas = Atlas {
+            pixels: vec![0; (columns * width * rows.max(1) * height * 4) as usize],
+            width: columns * width,
+            height: rows.max(1) * height,
+        };
+
+        let mut regions = Vec::with_capacity(frames.len());
+        for (i, frame) in frames.iter().enumerate() {
+            let region = Region {
+                x: (i as u32 % columns) * width,
+                y: (i as u32 / columns) * height,
+                width: width,
+                height: height,
+            };
+            atlas.blit(frame, region);
+            regions.push(region);
+        }
+        (atlas, regions)
+    }
+
+    // Copies the pixels of a frame into the atlas at region.
+    fn blit(&mut self, frame: &[u8], region: Region) {
+        let row_len = (region.width * 4) as usize;
+        for row in 0..region.height {
+            let src = (row * region.width * 4) as usize;
+            let dst = (((region.y + row) * self.width + region.x) * 4) as usize;
+            self.pixels[dst..dst + row_len].copy_from_slice(&frame[src..src + row_len]);
+        }
+    }
+
+    // Returns a copy of the pixels inside region, as a tightly packed RGBA8 image.
+    pub fn crop(&self, region: Region) -> Vec<u8> {
+        let row_len = (region.width * 4) as usize;
+        let mut out = Vec::with_capacity(row_len * region.height as usize);
+        for row in 0..region.height {
+            let src = (((region.y + row) * self.width + region.x) * 4) as usize;
+            out.extend_from_slice(&self.pixels[src..src + row_len]);
+        }
+        out
+    }
+}
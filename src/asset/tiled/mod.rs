@@ -0,0 +1,570 @@
+// Loader for maps made with the Tiled editor, in its XML (.tmx, .tsx) and JSON (.tmj, .tsj)
+// formats. Only orthogonal, finite maps are supported.
+//
+// Tile layers become layers of a TileMap, drawn on layers 0, 1, 2... in the order they're
+// listed in Tiled unless they have an int property named "layer". Objects are collected with
+// their properties so a game can spawn entities from them, see Game::spawn_objects.
+// Format reference: https://doc.mapeditor.org/en/stable/reference/tmx-map-format/
+
+mod tmj;
+mod tmx;
+
+use crate::entity::{Entity, Rect, SpriteStyle};
+use crate::graphics::{Color, Sampling};
+use crate::lighting::Occluder;
+use crate::tilemap::{Tile, TileMap, Tileset};
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use base64::Engine;
+
+const FLIPPED_HORIZONTALLY: u32 = 0x8000_0000;
+const FLIPPED_VERTICALLY: u32 = 0x4000_0000;
+const FLIPPED_DIAGONALLY: u32 = 0x2000_0000;
+// Also set on hexagonal maps for 120 degree rotations, which aren't supported
+const ROTATED_HEXAGONAL: u32 = 0x1000_0000;
+
+pub struct TiledMap {
+    pub map: TileMap,
+    pub properties: Properties,
+    // Objects of every object layer, in the order they're listed
+    pub objects: Vec<Object>,
+    // Collision shapes drawn on tiles in Tiled's tile collision editor
+    pub tile_shapes: HashMap<(usize, u32), Vec<Object>>,
+    // Custom properties of tiles, by tileset index and tile index
+    pub tile_properties: HashMap<(usize, u32), Properties>,
+}
+
+impl TiledMap {
+    // Occluders from the collision shapes of the tiles on a layer of the map, see
+    // lighting::Occluder. Rectangles, ellipses and polygons block light, points and polylines
+    // don't.
+    pub fn occluders(&self, layer: usize) -> Vec<Occluder> {
+        let map = &self.map;
+        let mut occluders = Vec::new();
+        for y in 0..map.height {
+            for x in 0..map.width {
+                let tile = match map.tile(layer, x, y) {
+                    Some(tile) => tile,
+                    None => continue,
+                };
+                let shapes = match self.tile_shapes.get(&(tile.tileset, tile.index)) {
+                    Some(shapes) => shapes,
+                    None => continue,
+                };
+                let tileset = &map.tilesets[tile.tileset];
+                let (w, h) = (tileset.tile_width as f32, tileset.tile_height as f32);
+                // Tiles larger than a cell stick out to the top, see TileMap
+                let origin_x = map.position_x + x as f32 * map.tile_width;
+                let origin_y = map.position_y + (y + 1) as f32 * map.tile_height - h;
+                let to_world = |(px, py): (f32, f32)| {
+                    let (px, py) = if tile.flip_diagonal {
+                        (py, px)
+                    } else {
+                        (px, py)
+                    };
+                    let px = if tile.flip_x { w - px } else { px };
+                    let py = if tile.flip_y { h - py } else { py };
+                    (origin_x + px, origin_y + py)
+                };
+
+                for shape in shapes {
+                    let points = shape_outline(shape);
+                    if points.len() >= 3 {
+                        occluders.push(Occluder::polygon(
+                            points.into_iter().map(to_world).collect(),
+                        ));
+                    }
+                }
+            }
+        }
+        occluders
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Property {
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    String(String),
+    Color(Color),
+    // Path relative to the map's directory
+    File(String),
+    // Id of another object
+    Object(u32),
+}
+
+pub type Properties = HashMap<String, Property>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Shape {
+    Rectangle,
+    Ellipse,
+    Point,
+    // Points relative to the object's position
+    Polygon(Vec<(f32, f32)>),
+    Polyline(Vec<(f32, f32)>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Object {
+    pub id: u32,
+    pub name: String,
+    // The object's type, called class since Tiled 1.9. Picks the factory that spawns it
+    pub kind: String,
+    // Name and draw layer of the object layer it's on
+    pub layer_name: String,
+    pub layer: i32,
+    // Top-left corner in world units, or the bottom-left corner for tile objects as in Tiled
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+    // Degrees clockwise around (x, y)
+    pub rotation: f32,
+    pub visible: bool,
+    pub shape: Shape,
+    // Set for objects placed as tiles
+    pub tile: Option<Tile>,
+    pub properties: Properties,
+}
+
+impl Object {
+    // The area the object covers as a Rect, tile objects included.
+    pub fn rect(&self) -> Rect {
+        let y = if self.tile.is_some() {
+            self.y - self.height
+        } else {
+            self.y
+        };
+        Rect::new(self.width, self.height, self.x, y)
+    }
+
+    // A SpriteStyle drawing on the object's layer.
+    pub fn style(&self) -> SpriteStyle {
+        SpriteStyle::default().on_layer(self.layer)
+    }
+
+    pub fn property(&self, name: &str) -> Option<&Property> {
+        self.properties.get(name)
+    }
+}
+
+// What a factory makes out of an object, see Game::register_factory.
+pub struct Spawn {
+    pub entity: Box<Entity + Send + Sync>,
+    pub rect: Rect,
+    pub style: SpriteStyle,
+    pub image: String,
+    // None uses the game's default sampling
+    pub sampling: Option<Sampling>,
+}
+
+pub type Factory = Box<Fn(&Object) -> Result<Spawn, String> + Send + Sync>;
+
+pub fn load(path: &str) -> Result<TiledMap, String> {
+    let def = match extension(path).as_ref().map(|e| e.as_str()) {
+        Some("tmj") | Some("json") => tmj::load_map(path)?,
+        _ => tmx::load_map(path)?,
+    };
+    build(def)
+}
+
+// Loads an external tileset referenced from a map, in either format.
+fn load_tileset(path: &Path, first_gid: u32) -> Result<TilesetDef, String> {
+    let path_str = path.to_string_lossy();
+    match extension(&path_str).as_ref().map(|e| e.as_str()) {
+        Some("tsj") | Some("json") => tmj::load_tileset(path, first_gid),
+        _ => tmx::load_tileset(path, first_gid),
+    }
+}
+
+fn extension(path: &str) -> Option<String> {
+    Path::new(path)
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(|e| e.to_lowercase())
+}
+
+fn read(path: &Path) -> Result<String, String> {
+    std::fs::read_to_string(path).map_err(|e| format!("Could not open {}: {}", path.display(), e))
+}
+
+// Paths in Tiled files are relative to the file they're in.
+fn relative_to(file: &Path, path: &str) -> PathBuf {
+    file.parent().unwrap_or_else(|| Path::new("")).join(path)
+}
+
+// Outline of a collision shape relative to its tile, rotated like the object. Empty for
+// shapes without an area.
+fn shape_outline(object: &Object) -> Vec<(f32, f32)> {
+    let (w, h) = (object.width, object.height);
+    let local = match object.shape {
+        Shape::Rectangle => vec![(0.0, 0.0), (w, 0.0), (w, h), (0.0, h)],
+        Shape::Ellipse => (0..16)
+            .map(|i| {
+                let (sin, cos) = (i as f32 / 16.0 * std::f32::consts::PI * 2.0).sin_cos();
+                (w / 2.0 + cos * w / 2.0, h / 2.0 + sin * h / 2.0)
+            })
+            .collect(),
+        Shape::Polygon(ref points) => points.clone(),
+        Shape::Point | Shape::Polyline(_) => Vec::new(),
+    };
+    let (sin, cos) = object.rotation.to_radians().sin_cos();
+    local
+        .into_iter()
+        .map(|(x, y)| (object.x + x * cos - y * sin, object.y + x * sin + y * cos))
+        .collect()
+}
+
+// The map as read from either format, before gids are resolved to tilesets.
+struct MapDef {
+    width: u32,
+    height: u32,
+    tile_width: u32,
+    tile_height: u32,
+    properties: Properties,
+    tilesets: Vec<TilesetDef>,
+    layers: Vec<LayerDef>,
+}
+
+struct TilesetDef {
+    first_gid: u32,
+    image: PathBuf,
+    tile_width: u32,
+    tile_height: u32,
+    margin: u32,
+    spacing: u32,
+    tiles: Vec<TileDef>,
+}
+
+struct TileDef {
+    id: u32,
+    properties: Properties,
+    shapes: Vec<Object>,
+}
+
+enum LayerDef {
+    Tiles {
+        name: String,
+        visible: bool,
+        opacity: f32,
+        tint: Color,
+        properties: Properties,
+        gids: Vec<u32>,
+    },
+    // Objects come with their raw gid, 0 for objects that aren't tiles
+    Objects {
+        name: String,
+        visible: bool,
+        properties: Properties,
+        objects: Vec<(Object, u32)>,
+    },
+}
+
+fn build(def: MapDef) -> Result<TiledMap, String> {
+    let mut map = TileMap::new(
+        def.width,
+        def.height,
+        def.tile_width as f32,
+        def.tile_height as f32,
+    );
+    let mut tile_shapes = HashMap::new();
+    let mut tile_properties = HashMap::new();
+
+    let mut tilesets = def.tilesets;
+    tilesets.sort_by_key(|t| t.first_gid);
+    for (i, tileset) in tilesets.iter().enumerate() {
+        let path = tileset.image.to_string_lossy();
+        let loaded = Tileset::load(&path, tileset.tile_width, tileset.tile_height)?
+            .with_spacing(tileset.margin, tileset.spacing);
+        map.add_tileset(loaded);
+
+        for tile in &tileset.tiles {
+            if !tile.properties.is_empty() {
+                tile_properties.insert((i, tile.id), tile.properties.clone());
+            }
+            if !tile.shapes.is_empty() {
+                tile_shapes.insert((i, tile.id), tile.shapes.clone());
+            }
+        }
+    }
+
+    let mut objects = Vec::new();
+    for (i, layer) in def.layers.into_iter().enumerate() {
+        match layer {
+            LayerDef::Tiles {
+                name,
+                visible,
+                opacity,
+                tint,
+                properties,
+                gids,
+            } => {
+                let index = map.add_layer(&name, draw_layer(&properties, i));
+                let tiles = gids
+                    .into_iter()
+                    .map(|gid| decode_gid(&tilesets, gid))
+                    .collect::<Result<Vec<_>, _>>()?;
+                map.set_tiles(index, tiles)
+                    .map_err(|e| format!("Layer {}: {}", name, e))?;
+
+                let layer = map.layer_mut(index);
+                layer.visible = visible;
+                layer.tint = tint.with_alpha(tint.a * opacity);
+            }
+            LayerDef::Objects {
+                name,
+                visible,
+                properties,
+                objects: layer_objects,
+            } => {
+                let layer = draw_layer(&properties, i);
+                for (mut object, gid) in layer_objects {
+                    object.layer_name = name.clone();
+                    object.layer = layer;
+                    object.visible = object.visible && visible;
+                    object.tile = decode_gid(&tilesets, gid)?;
+                    objects.push(object);
+                }
+            }
+        }
+    }
+
+    Ok(TiledMap {
+        map: map,
+        properties: def.properties,
+        objects: objects,
+        tile_shapes: tile_shapes,
+        tile_properties: tile_properties,
+    })
+}
+
+fn draw_layer(properties: &Properties, index: usize) -> i32 {
+    match properties.get("layer") {
+        Some(Property::Int(layer)) => *layer as i32,
+        _ => index as i32,
+    }
+}
+
+// Splits a global tile id into its tileset, its index in the tileset and its flip flags.
+fn decode_gid(tilesets: &[TilesetDef], gid: u32) -> Result<Option<Tile>, String> {
+    let flags = FLIPPED_HORIZONTALLY | FLIPPED_VERTICALLY | FLIPPED_DIAGONALLY | ROTATED_HEXAGONAL;
+    let id = gid & !flags;
+    if id == 0 {
+        return Ok(None);
+    }
+    let tileset = tilesets
+        .iter()
+        .rposition(|t| t.first_gid <= id)
+        .ok_or_else(|| format!("Tile {} doesn't belong to a tileset", id))?;
+
+    Ok(Some(
+        Tile::new(id - tilesets[tileset].first_gid)
+            .from_tileset(tileset)
+            .flip(
+                gid & FLIPPED_HORIZONTALLY != 0,
+                gid & FLIPPED_VERTICALLY != 0,
+                gid & FLIPPED_DIAGONALLY != 0,
+            ),
+    ))
+}
+
+// Reads the gids of a tile layer's data, which is either comma separated or base64 encoded
+// little endian u32s, optionally compressed.
+fn decode_data(text: &str, encoding: &str, compression: &str) -> Result<Vec<u32>, String> {
+    match encoding {
+        "csv" => text
+            .split(',')
+            .map(|s| s.trim())
+            .filter(|s| !s.is_empty())
+            .map(|s| s.parse().map_err(|_| format!("Invalid tile id {}", s)))
+            .collect(),
+        "base64" => {
+            // Tiled puts the data on a line of its own
+            let text: String = text.split_whitespace().collect();
+            let bytes = base64::engine::general_purpose::STANDARD
+                .decode(&text)
+                .map_err(|e| format!("Invalid base64 data: {}", e))?;
+            let bytes = match compression {
+                "" => bytes,
+                "zlib" => inflate::inflate_bytes_zlib(&bytes)?,
+                "gzip" => gunzip(&bytes)?,
+                other => return Err(format!("Unsupported compression {}", other)),
+            };
+            Ok(bytes
+                .chunks(4)
+                .filter(|c| c.len() == 4)
+                .map(|c| {
+                    u32::from(c[0])
+                        | u32::from(c[1]) << 8
+                        | u32::from(c[2]) << 16
+                        | u32::from(c[3]) << 24
+                })
+                .collect())
+        }
+        other => Err(format!("Unsupported encoding {}", other)),
+    }
+}
+
+// Strips the gzip header and inflates the deflate stream after it.
+fn gunzip(bytes: &[u8]) -> Result<Vec<u8>, String> {
+    const FEXTRA: u8 = 4;
+    const FNAME: u8 = 8;
+    const FCOMMENT: u8 = 16;
+    const FHCRC: u8 = 2;
+
+    if bytes.len() < 18 || bytes[0] != 0x1f || bytes[1] != 0x8b {
+        return Err("Invalid gzip data".to_string());
+    }
+    let flags = bytes[3];
+    let mut pos = 10;
+    if flags & FEXTRA != 0 {
+        let len = bytes.get(pos..pos + 2).ok_or("Invalid gzip data")?;
+        pos += 2 + (usize::from(len[0]) | usize::from(len[1]) << 8);
+    }
+    for &flag in &[FNAME, FCOMMENT] {
+        if flags & flag != 0 {
+            while bytes.get(pos).ok_or("Invalid gzip data")? != &0 {
+                pos += 1;
+            }
+            pos += 1;
+        }
+    }
+    if flags & FHCRC != 0 {
+        pos += 2;
+    }
+    let end = bytes.len() - 8;
+    if pos > end {
+        return Err("Invalid gzip data".to_string());
+    }
+    inflate::inflate_bytes(&bytes[pos..end])
+}
+
+// Tiled writes colors as #RRGGBB or #AARRGGBB.
+fn parse_color(text: &str) -> Result<Color, String> {
+    let hex = text.trim_start_matches('#');
+    let value = u32::from_str_radix(hex, 16).map_err(|_| format!("Invalid color {}", text))?;
+    let channel = |shift: u32| ((value >> shift) & 0xff) as f32 / 255.0;
+    match hex.len() {
+        6 => Ok(Color::rgb(channel(16), channel(8), channel(0))),
+        8 => Ok(Color::rgba(
+            channel(16),
+            channel(8),
+            channel(0),
+            channel(24),
+        )),
+        _ => Err(format!("Invalid color {}", text)),
+    }
+}
+
+fn parse_property(kind: &str, value: &str) -> Result<Property, String> {
+    let invalid = || format!("Invalid {} property {}", kind, value);
+    Ok(match kind {
+        "bool" => Property::Bool(value == "true"),
+        "int" => Property::Int(value.parse().map_err(|_| invalid())?),
+        "float" => Property::Float(value.parse().map_err(|_| invalid())?),
+        "color" if value.is_empty() => Property::Color(Color::TRANSPARENT),
+        "color" => Property::Color(parse_color(value)?),
+        "file" => Property::File(value.to_string()),
+        "object" => Property::Object(value.parse().map_err(|_| invalid())?),
+        _ => Property::String(value.to_string()),
+    })
+}
+
+fn default_object() -> Object {
+    Object {
+        id: 0,
+        name: String::new(),
+        kind: String::new(),
+        layer_name: String::new(),
+        layer: 0,
+        x: 0.0,
+        y: 0.0,
+        width: 0.0,
+        height: 0.0,
+        rotation: 0.0,
+        visible: true,
+        shape: Shape::Rectangle,
+        tile: None,
+        properties: Properties::new(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 1, 2 flipped horizontally, an empty cell and 3 flipped vertically and diagonally
+    const GIDS: [u32; 4] = [1, 0x8000_0002, 0, 0x6000_0003];
+
+    fn tileset(first_gid: u32) -> TilesetDef {
+        TilesetDef {
+            first_gid: first_gid,
+            image: PathBuf::new(),
+            tile_width: 16,
+            tile_height: 16,
+            margin: 0,
+            spacing: 0,
+            tiles: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn csv_data() {
+        let data = "\n1,2147483650,\n0,1610612739\n";
+        assert_eq!(decode_data(data, "csv", "").unwrap(), GIDS);
+    }
+
+    #[test]
+    fn base64_data() {
+        let data = "\n   AQAAAAIAAIAAAAAAAwAAYA==\n  ";
+        assert_eq!(decode_data(data, "base64", "").unwrap(), GIDS);
+    }
+
+    #[test]
+    fn zlib_data() {
+        let data = "eJxjZGBgYGJgaABSDMwMDAkABSQA5w==";
+        assert_eq!(decode_data(data, "base64", "zlib").unwrap(), GIDS);
+    }
+
+    #[test]
+    fn gzip_data() {
+        let data = "H4sIAAAAAAACA2NkYGBgYmBoAFIMzAwMCQCiirqXEAAAAA==";
+        assert_eq!(decode_data(data, "base64", "gzip").unwrap(), GIDS);
+    }
+
+    #[test]
+    fn invalid_data() {
+        assert!(decode_data("AQAA*AAA", "base64", "").is_err());
+        assert!(decode_data("AQAAAA==", "base64", "zstd").is_err());
+        assert!(decode_data("AQAAAA==", "base64", "gzip").is_err());
+        assert!(decode_data("1,x", "csv", "").is_err());
+    }
+
+    #[test]
+    fn flip_flags() {
+        let tilesets = [tileset(1), tileset(3)];
+        let tiles: Vec<Option<Tile>> = GIDS
+            .iter()
+            .map(|&gid| decode_gid(&tilesets, gid).unwrap())
+            .collect();
+        assert_eq!(
+            tiles,
+            vec![
+                Some(Tile::new(0)),
+                Some(Tile::new(1).flip(true, false, false)),
+                None,
+                Some(Tile::new(0).from_tileset(1).flip(false, true, true)),
+            ]
+        );
+    }
+
+    #[test]
+    fn hexagonal_rotation_is_ignored() {
+        let tile = decode_gid(&[tileset(1)], ROTATED_HEXAGONAL | 1).unwrap();
+        assert_eq!(tile, Some(Tile::new(0)));
+    }
+}
@@ -0,0 +1,293 @@
+// The JSON formats, .tmj maps and .tsj tilesets.
+
+use super::{
+    decode_data, default_object, load_tileset as load_external, parse_color, parse_property, read,
+    relative_to, LayerDef, MapDef, Object, Properties, Shape, TileDef, TilesetDef,
+};
+use crate::graphics::Color;
+
+use std::path::Path;
+
+use serde_json::Value;
+
+// Shorthands for optional fields, falling back to a default when missing
+trait Fields {
+    fn str_or<'a>(&'a self, key: &str, default: &'a str) -> &'a str;
+    fn f64_or(&self, key: &str, default: f64) -> f64;
+    fn bool_or(&self, key: &str, default: bool) -> bool;
+    fn array_or_empty(&self, key: &str) -> &[Value];
+}
+
+impl Fields for Value {
+    fn str_or<'a>(&'a self, key: &str, default: &'a str) -> &'a str {
+        self.get(key).and_then(Value::as_str).unwrap_or(default)
+    }
+
+    fn f64_or(&self, key: &str, default: f64) -> f64 {
+        self.get(key).and_then(Value::as_f64).unwrap_or(default)
+    }
+
+    fn bool_or(&self, key: &str, default: bool) -> bool {
+        self.get(key).and_then(Value::as_bool).unwrap_or(default)
+    }
+
+    fn array_or_empty(&self, key: &str) -> &[Value] {
+        match self.get(key) {
+            Some(Value::Array(ref items)) => items,
+            _ => &[],
+        }
+    }
+}
+
+fn parse(path: &Path) -> Result<Value, String> {
+    serde_json::from_str(&read(path)?)
+        .map_err(|e| format!("Could not parse {}: {}", path.display(), e))
+}
+
+pub(super) fn load_map(path: &str) -> Result<MapDef, String> {
+    let path = Path::new(path);
+    let root = parse(path)?;
+    if root.str_or("type", "map") != "map" {
+        return Err(format!("{} is not a Tiled map", path.display()));
+    }
+    let orientation = root.str_or("orientation", "orthogonal");
+    if orientation != "orthogonal" {
+        return Err(format!("Unsupported map orientation {}", orientation));
+    }
+    if root.bool_or("infinite", false) {
+        return Err("Infinite maps are not supported".to_string());
+    }
+
+    let mut tilesets = Vec::new();
+    for tileset in root.array_or_empty("tilesets") {
+        let first_gid = tileset.f64_or("firstgid", 1.0) as u32;
+        tilesets.push(match tileset.get("source").and_then(Value::as_str) {
+            Some(source) => load_external(&relative_to(path, source), first_gid)?,
+            None => tileset_def(tileset, path, first_gid)?,
+        });
+    }
+
+    let mut layers = Vec::new();
+    read_layers(&root, true, 1.0, &mut layers)?;
+
+    Ok(MapDef {
+        width: root.f64_or("width", 0.0) as u32,
+        height: root.f64_or("height", 0.0) as u32,
+        tile_width: root.f64_or("tilewidth", 0.0) as u32,
+        tile_height: root.f64_or("tileheight", 0.0) as u32,
+        properties: properties(&root)?,
+        tilesets: tilesets,
+        layers: layers,
+    })
+}
+
+pub(super) fn load_tileset(path: &Path, first_gid: u32) -> Result<TilesetDef, String> {
+    let root = parse(path)?;
+    tileset_def(&root, path, first_gid)
+}
+
+// file is the path of the file the tileset is in, the image path is relative to it.
+fn tileset_def(tileset: &Value, file: &Path, first_gid: u32) -> Result<TilesetDef, String> {
+    let image = tileset
+        .get("image")
+        .and_then(Value::as_str)
+        .ok_or_else(|| {
+            format!(
+                "Tileset {} has no image, image collections are not supported",
+                tileset.str_or("name", "")
+            )
+        })?;
+
+    let mut tiles = Vec::new();
+    for tile in tileset.array_or_empty("tiles") {
+        let shapes = match tile.get("objectgroup") {
+            Some(group) => objects(group)?.into_iter().map(|o| o.0).collect(),
+            None => Vec::new(),
+        };
+        tiles.push(TileDef {
+            id: tile.f64_or("id", 0.0) as u32,
+            properties: properties(tile)?,
+            shapes: shapes,
+        });
+    }
+
+    Ok(TilesetDef {
+        first_gid: first_gid,
+        image: relative_to(file, image),
+        tile_width: tileset.f64_or("tilewidth", 0.0) as u32,
+        tile_height: tileset.f64_or("tileheight", 0.0) as u32,
+        margin: tileset.f64_or("margin", 0.0) as u32,
+        spacing: tileset.f64_or("spacing", 0.0) as u32,
+        tiles: tiles,
+    })
+}
+
+// Layers inside groups are flattened, inheriting the group's visibility and opacity.
+fn read_layers(
+    parent: &Value,
+    visible: bool,
+    opacity: f32,
+    layers: &mut Vec<LayerDef>,
+) -> Result<(), String> {
+    for layer in parent.array_or_empty("layers") {
+        let name = layer.str_or("name", "").to_string();
+        let visible = visible && layer.bool_or("visible", true);
+        let opacity = opacity * layer.f64_or("opacity", 1.0) as f32;
+
+        match layer.str_or("type", "") {
+            "tilelayer" => {
+                let gids = match layer.get("data") {
+                    Some(Value::String(ref data)) => decode_data(
+                        data,
+                        layer.str_or("encoding", "base64"),
+                        layer.str_or("compression", ""),
+                    )?,
+                    Some(Value::Array(ref data)) => data
+                        .iter()
+                        .map(|gid| gid.as_u64().map(|g| g as u32))
+                        .collect::<Option<_>>()
+                        .ok_or("Tile layer data has to be numbers")?,
+                    _ => return Err(format!("Tile layer {} has no data", name)),
+                };
+                let tint = match layer.get("tintcolor").and_then(Value::as_str) {
+                    Some(color) => parse_color(color)?,
+                    None => Color::WHITE,
+                };
+                layers.push(LayerDef::Tiles {
+                    name: name,
+                    visible: visible,
+                    opacity: opacity,
+                    tint: tint,
+                    properties: properties(layer)?,
+                    gids: gids,
+                });
+            }
+            "objectgroup" => layers.push(LayerDef::Objects {
+                name: name,
+                visible: visible,
+                properties: properties(layer)?,
+                objects: objects(layer)?,
+            }),
+            "group" => read_layers(layer, visible, opacity, layers)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn properties(value: &Value) -> Result<Properties, String> {
+    let mut properties = Properties::new();
+    for property in value.array_or_empty("properties") {
+        let text = match property.get("value") {
+            Some(Value::String(ref s)) => s.clone(),
+            Some(Value::Number(ref n)) => n.to_string(),
+            Some(Value::Bool(b)) => b.to_string(),
+            _ => String::new(),
+        };
+        properties.insert(
+            property.str_or("name", "").to_string(),
+            parse_property(property.str_or("type", "string"), &text)?,
+        );
+    }
+    Ok(properties)
+}
+
+fn objects(group: &Value) -> Result<Vec<(Object, u32)>, String> {
+    let mut objects = Vec::new();
+    for value in group.array_or_empty("objects") {
+        let shape = if value.bool_or("ellipse", false) {
+            Shape::Ellipse
+        } else if value.bool_or("point", false) {
+            Shape::Point
+        } else if let Some(polygon) = value.get("polygon") {
+            Shape::Polygon(points(polygon)?)
+        } else if let Some(polyline) = value.get("polyline") {
+            Shape::Polyline(points(polyline)?)
+        } else {
+            Shape::Rectangle
+        };
+
+        let object = Object {
+            id: value.f64_or("id", 0.0) as u32,
+            name: value.str_or("name", "").to_string(),
+            kind: value
+                .get("class")
+                .or_else(|| value.get("type"))
+                .and_then(Value::as_str)
+                .unwrap_or("")
+                .to_string(),
+            x: value.f64_or("x", 0.0) as f32,
+            y: value.f64_or("y", 0.0) as f32,
+            width: value.f64_or("width", 0.0) as f32,
+            height: value.f64_or("height", 0.0) as f32,
+            rotation: value.f64_or("rotation", 0.0) as f32,
+            visible: value.bool_or("visible", true),
+            shape: shape,
+            properties: properties(value)?,
+            ..default_object()
+        };
+        objects.push((object, value.f64_or("gid", 0.0) as u32));
+    }
+    Ok(objects)
+}
+
+// Points are written as [{"x": 0, "y": 0}, ...].
+fn points(value: &Value) -> Result<Vec<(f32, f32)>, String> {
+    value
+        .as_array()
+        .ok_or("Points have to be an array")?
+        .iter()
+        .map(|p| {
+            match (
+                p.get("x").and_then(Value::as_f64),
+                p.get("y").and_then(Value::as_f64),
+            ) {
+                (Some(x), Some(y)) => Ok((x as f32, y as f32)),
+                _ => Err("Invalid point".to_string()),
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asset::tiled::Property;
+
+    #[test]
+    fn objects_and_properties() {
+        let group: Value = serde_json::from_str(
+            r##"{"objects": [
+                {"id": 4, "name": "door", "class": "Door", "x": 32, "y": 16.5, "gid": 2147483655,
+                 "properties": [
+                    {"name": "locked", "type": "bool", "value": true},
+                    {"name": "key", "type": "int", "value": 3},
+                    {"name": "tint", "type": "color", "value": "#ff0000ff"}
+                 ]},
+                {"id": 5, "polygon": [{"x": 0, "y": 0}, {"x": 8, "y": 0}, {"x": 0, "y": 8}]}
+            ]}"##,
+        )
+        .unwrap();
+        let objects = objects(&group).unwrap();
+        assert_eq!(objects.len(), 2);
+
+        let (door, gid) = &objects[0];
+        assert_eq!(
+            (door.id, door.name.as_str(), door.kind.as_str()),
+            (4, "door", "Door")
+        );
+        assert_eq!((door.x, door.y), (32.0, 16.5));
+        assert_eq!(*gid, 0x8000_0007);
+        assert_eq!(door.properties["locked"], Property::Bool(true));
+        assert_eq!(door.properties["key"], Property::Int(3));
+        assert_eq!(
+            door.properties["tint"],
+            Property::Color(Color::rgb(0.0, 0.0, 1.0))
+        );
+
+        assert_eq!(
+            objects[1].0.shape,
+            Shape::Polygon(vec![(0.0, 0.0), (8.0, 0.0), (0.0, 8.0)])
+        );
+    }
+}
@@ -0,0 +1,278 @@
+// The XML formats, .tmx maps and .tsx tilesets.
+
+use super::{
+    decode_data, default_object, load_tileset as load_external, parse_color, parse_property, read,
+    relative_to, LayerDef, MapDef, Object, Properties, Shape, TileDef, TilesetDef,
+};
+use crate::graphics::Color;
+
+use std::path::Path;
+use std::str::FromStr;
+
+use xml::reader::{EventReader, XmlEvent};
+
+struct Element {
+    name: String,
+    attributes: Vec<(String, String)>,
+    children: Vec<Element>,
+    text: String,
+}
+
+impl Element {
+    fn attr(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|a| a.0 == name)
+            .map(|a| a.1.as_str())
+    }
+
+    fn str_or<'a>(&'a self, name: &str, default: &'a str) -> &'a str {
+        self.attr(name).unwrap_or(default)
+    }
+
+    fn parse_or<T: FromStr>(&self, name: &str, default: T) -> Result<T, String> {
+        match self.attr(name) {
+            None => Ok(default),
+            Some(value) => value
+                .parse()
+                .map_err(|_| format!("Invalid {} \"{}\" on <{}>", name, value, self.name)),
+        }
+    }
+
+    fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Element> + 'a {
+        self.children.iter().filter(move |c| c.name == name)
+    }
+
+    fn child(&self, name: &str) -> Option<&Element> {
+        self.children.iter().find(|c| c.name == name)
+    }
+}
+
+fn parse(text: &str) -> Result<Element, String> {
+    let mut stack: Vec<Element> = Vec::new();
+    for event in EventReader::from_str(text) {
+        match event.map_err(|e| format!("XML error: {}", e))? {
+            XmlEvent::StartElement {
+                name, attributes, ..
+            } => stack.push(Element {
+                name: name.local_name,
+                attributes: attributes
+                    .into_iter()
+                    .map(|a| (a.name.local_name, a.value))
+                    .collect(),
+                children: Vec::new(),
+                text: String::new(),
+            }),
+            XmlEvent::EndElement { .. } => {
+                let element = stack.pop().ok_or("XML error: unbalanced elements")?;
+                match stack.last_mut() {
+                    Some(parent) => parent.children.push(element),
+                    None => return Ok(element),
+                }
+            }
+            XmlEvent::Characters(text) | XmlEvent::CData(text) => {
+                if let Some(element) = stack.last_mut() {
+                    element.text.push_str(&text);
+                }
+            }
+            _ => {}
+        }
+    }
+    Err("XML error: no root element".to_string())
+}
+
+pub(super) fn load_map(path: &str) -> Result<MapDef, String> {
+    let path = Path::new(path);
+    let root = parse(&read(path)?)?;
+    if root.name != "map" {
+        return Err(format!("{} is not a Tiled map", path.display()));
+    }
+    let orientation = root.str_or("orientation", "orthogonal");
+    if orientation != "orthogonal" {
+        return Err(format!("Unsupported map orientation {}", orientation));
+    }
+    if root.str_or("infinite", "0") != "0" {
+        return Err("Infinite maps are not supported".to_string());
+    }
+
+    let mut tilesets = Vec::new();
+    for tileset in root.children("tileset") {
+        let first_gid = tileset.parse_or("firstgid", 1)?;
+        tilesets.push(match tileset.attr("source") {
+            Some(source) => load_external(&relative_to(path, source), first_gid)?,
+            None => tileset_def(tileset, path, first_gid)?,
+        });
+    }
+
+    let mut layers = Vec::new();
+    read_layers(&root, true, 1.0, &mut layers)?;
+
+    Ok(MapDef {
+        width: root.parse_or("width", 0)?,
+        height: root.parse_or("height", 0)?,
+        tile_width: root.parse_or("tilewidth", 0)?,
+        tile_height: root.parse_or("tileheight", 0)?,
+        properties: properties(&root)?,
+        tilesets: tilesets,
+        layers: layers,
+    })
+}
+
+pub(super) fn load_tileset(path: &Path, first_gid: u32) -> Result<TilesetDef, String> {
+    let root = parse(&read(path)?)?;
+    if root.name != "tileset" {
+        return Err(format!("{} is not a Tiled tileset", path.display()));
+    }
+    tileset_def(&root, path, first_gid)
+}
+
+// file is the path of the file the tileset is in, the image path is relative to it.
+fn tileset_def(tileset: &Element, file: &Path, first_gid: u32) -> Result<TilesetDef, String> {
+    let image = tileset.child("image").ok_or_else(|| {
+        format!(
+            "Tileset {} has no image, image collections are not supported",
+            tileset.str_or("name", "")
+        )
+    })?;
+    let source = image.attr("source").ok_or("Tileset image has no source")?;
+
+    let mut tiles = Vec::new();
+    for tile in tileset.children("tile") {
+        let shapes = match tile.child("objectgroup") {
+            Some(group) => objects(group)?.into_iter().map(|o| o.0).collect(),
+            None => Vec::new(),
+        };
+        tiles.push(TileDef {
+            id: tile.parse_or("id", 0)?,
+            properties: properties(tile)?,
+            shapes: shapes,
+        });
+    }
+
+    Ok(TilesetDef {
+        first_gid: first_gid,
+        image: relative_to(file, source),
+        tile_width: tileset.parse_or("tilewidth", 0)?,
+        tile_height: tileset.parse_or("tileheight", 0)?,
+        margin: tileset.parse_or("margin", 0)?,
+        spacing: tileset.parse_or("spacing", 0)?,
+        tiles: tiles,
+    })
+}
+
+// Layers inside groups are flattened, inheriting the group's visibility and opacity.
+fn read_layers(
+    parent: &Element,
+    visible: bool,
+    opacity: f32,
+    layers: &mut Vec<LayerDef>,
+) -> Result<(), String> {
+    for element in &parent.children {
+        let name = element.str_or("name", "").to_string();
+        let visible = visible && element.str_or("visible", "1") != "0";
+        let opacity = opacity * element.parse_or("opacity", 1.0)?;
+
+        match element.name.as_str() {
+            "layer" => {
+                let data = element.child("data").ok_or("Tile layer has no data")?;
+                let gids = match data.attr("encoding") {
+                    Some(encoding) => {
+                        decode_data(&data.text, encoding, data.str_or("compression", ""))?
+                    }
+                    // Plain XML, one element per tile
+                    None => data
+                        .children("tile")
+                        .map(|t| t.parse_or("gid", 0))
+                        .collect::<Result<_, _>>()?,
+                };
+                let tint = match element.attr("tintcolor") {
+                    Some(color) => parse_color(color)?,
+                    None => Color::WHITE,
+                };
+                layers.push(LayerDef::Tiles {
+                    name: name,
+                    visible: visible,
+                    opacity: opacity,
+                    tint: tint,
+                    properties: properties(element)?,
+                    gids: gids,
+                });
+            }
+            "objectgroup" => layers.push(LayerDef::Objects {
+                name: name,
+                visible: visible,
+                properties: properties(element)?,
+                objects: objects(element)?,
+            }),
+            "group" => read_layers(element, visible, opacity, layers)?,
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn properties(element: &Element) -> Result<Properties, String> {
+    let mut properties = Properties::new();
+    if let Some(list) = element.child("properties") {
+        for property in list.children("property") {
+            // Multi-line strings are stored as text instead of in the value attribute
+            let value = property.attr("value").unwrap_or(&property.text);
+            properties.insert(
+                property.str_or("name", "").to_string(),
+                parse_property(property.str_or("type", "string"), value)?,
+            );
+        }
+    }
+    Ok(properties)
+}
+
+fn objects(group: &Element) -> Result<Vec<(Object, u32)>, String> {
+    let mut objects = Vec::new();
+    for element in group.children("object") {
+        let shape = if element.child("ellipse").is_some() {
+            Shape::Ellipse
+        } else if element.child("point").is_some() {
+            Shape::Point
+        } else if let Some(polygon) = element.child("polygon") {
+            Shape::Polygon(points(polygon.str_or("points", ""))?)
+        } else if let Some(polyline) = element.child("polyline") {
+            Shape::Polyline(points(polyline.str_or("points", ""))?)
+        } else {
+            Shape::Rectangle
+        };
+
+        let object = Object {
+            id: element.parse_or("id", 0)?,
+            name: element.str_or("name", "").to_string(),
+            kind: element
+                .attr("class")
+                .or_else(|| element.attr("type"))
+                .unwrap_or("")
+                .to_string(),
+            x: element.parse_or("x", 0.0)?,
+            y: element.parse_or("y", 0.0)?,
+            width: element.parse_or("width", 0.0)?,
+            height: element.parse_or("height", 0.0)?,
+            rotation: element.parse_or("rotation", 0.0)?,
+            visible: element.str_or("visible", "1") != "0",
+            shape: shape,
+            properties: properties(element)?,
+            ..default_object()
+        };
+        objects.push((object, element.parse_or("gid", 0)?));
+    }
+    Ok(objects)
+}
+
+// Points are written as "x,y x,y ...".
+fn points(text: &str) -> Result<Vec<(f32, f32)>, String> {
+    text.split_whitespace()
+        .map(|point| {
+            let mut coords = point.split(',').map(|c| c.parse::<f32>());
+            match (coords.next(), coords.next()) {
+                (Some(Ok(x)), Some(Ok(y))) => Ok((x, y)),
+                _ => Err(format!("Invalid point {}", point)),
+            }
+        })
+        .collect()
+}
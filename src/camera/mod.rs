@@ -0,0 +1,229 @@
+// World space is measured in pixels: x grows to the right, y grows downwards and a Rect's
+// position is its top-left corner. A Camera2D decides which part of the world ends up on
+// screen, at zoom 1.0 one world unit covers one pixel of the window.
+
+use crate::graphics::Color;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Camera2D {
+    // The world position shown at the center of the viewport
+    pub position_x: f32,
+    pub position_y: f32,
+    pub zoom: f32,
+    // Radians, rotating the camera clockwise turns the world counter-clockwise on screen
+    pub rotation: f32,
+    pub viewport: Viewport,
+    // The draw layers the camera shows, screen space canvas drawing is always shown
+    pub layers: Layers,
+    // Fills the viewport before the camera draws, None draws over what earlier cameras drew
+    pub clear_color: Option<Color>,
+}
+
+// Part of the window a camera draws to, as fractions of the window size.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Viewport {
+    pub fn full() -> Self {
+        Viewport {
+            x: 0.0,
+            y: 0.0,
+            width: 1.0,
+            height: 1.0,
+        }
+    }
+
+    // Returns the viewport's origin and size in pixels for a window of the given size.
+    pub fn to_pixels(&self, window: [u32; 2]) -> ([f32; 2], [f32; 2]) {
+        let (w, h) = (window[0] as f32, window[1] as f32);
+        (
+            [self.x * w, self.y * h],
+            [(self.width * w).max(1.0), (self.height * h).max(1.0)],
+        )
+    }
+}
+
+// Which draw layers a view shows.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Layers {
+    All,
+    Only(Vec<i32>),
+    Except(Vec<i32>),
+}
+
+impl Layers {
+    pub fn contains(&self, layer: i32) -> bool {
+        match *self {
+            Layers::All => true,
+            Layers::Only(ref layers) => layers.contains(&layer),
+            Layers::Except(ref layers) => !layers.contains(&layer),
+        }
+    }
+}
+
+impl Default for Camera2D {
+    fn default() -> Self {
+        Camera2D {
+            position_x: 0.0,
+            position_y: 0.0,
+            zoom: 1.0,
+            rotation: 0.0,
+            viewport: Viewport::full(),
+            layers: Layers::All,
+            clear_color: Some(Color::BLACK),
+        }
+    }
+}
+
+impl Camera2D {
+    pub fn new() -> Self {
+        Camera2D::default()
+    }
+
+    pub fn looking_at(mut self, x: f32, y: f32) -> Self {
+        self.position_x = x;
+        self.position_y = y;
+        self
+    }
+
+    pub fn with_zoom(mut self, zoom: f32) -> Self {
+        self.zoom = zoom;
+        self
+    }
+
+    pub fn with_rotation(mut self, radians: f32) -> Self {
+        self.rotation = radians;
+        self
+    }
+
+    pub fn with_viewport(mut self, viewport: Viewport) -> Self {
+        self.viewport = viewport;
+        self
+    }
+
+    pub fn with_layers(mut self, layers: Layers) -> Self {
+        self.layers = layers;
+        self
+    }
+
+    pub fn with_clear_color(mut self, color: Option<Color>) -> Self {
+        self.clear_color = color;
+        self
+    }
+
+    // Column-major matrix taking world positions to normalized device coordinates.
+    pub fn view_projection(&self, window: [u32; 2]) -> [[f32; 4]; 4] {
+        let (_, [vw, vh]) = self.viewport.to_pixels(window);
+        let (sx, sy) = (2.0 * self.zoom / vw, 2.0 * self.zoom / vh);
+        let (s, c) = self.rotation.sin_cos();
+        let (px, py) = (self.position_x, self.position_y);
+
+        [
+            [sx * c, -sy * s, 0.0, 0.0],
+            [sx * s, sy * c, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [sx * (-c * px - s * py), sy * (s * px - c * py), 0.0, 1.0],
+        ]
+    }
+
+    // Converts a position in window pixels, such as the mouse cursor, to world space.
+    pub fn screen_to_world(&self, x: f32, y: f32, window: [u32; 2]) -> (f32, f32) {
+        let ([ox, oy], [vw, vh]) = self.viewport.to_pixels(window);
+        let (dx, dy) = (
+            (x - ox - vw / 2.0) / self.zoom,
+            (y - oy - vh / 2.0) / self.zoom,
+        );
+        let (s, c) = self.rotation.sin_cos();
+        (
+            dx * c - dy * s + self.position_x,
+            dx * s + dy * c + self.position_y,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::f32::consts::FRAC_PI_2;
+
+    const WINDOW: [u32; 2] = [800, 600];
+
+    // Where the camera shows a world position, in window pixels
+    fn world_to_screen(camera: &Camera2D, x: f32, y: f32) -> (f32, f32) {
+        let m = camera.view_projection(WINDOW);
+        let ndc_x = m[0][0] * x + m[1][0] * y + m[3][0];
+        let ndc_y = m[0][1] * x + m[1][1] * y + m[3][1];
+        let ([ox, oy], [vw, vh]) = camera.viewport.to_pixels(WINDOW);
+        (ox + (ndc_x + 1.0) / 2.0 * vw, oy + (ndc_y + 1.0) / 2.0 * vh)
+    }
+
+    fn assert_near((x, y): (f32, f32), (ex, ey): (f32, f32)) {
+        assert!(
+            (x - ex).abs() < 1e-3 && (y - ey).abs() < 1e-3,
+            "({}, {}) != ({}, {})",
+            x,
+            y,
+            ex,
+            ey
+        );
+    }
+
+    #[test]
+    fn center_shows_position() {
+        let camera = Camera2D::new().looking_at(120.0, -40.0);
+        assert_near(world_to_screen(&camera, 120.0, -40.0), (400.0, 300.0));
+        assert_near(camera.screen_to_world(400.0, 300.0, WINDOW), (120.0, -40.0));
+        assert_near(camera.screen_to_world(0.0, 0.0, WINDOW), (-280.0, -340.0));
+    }
+
+    #[test]
+    fn zoom() {
+        let camera = Camera2D::new().with_zoom(2.0);
+        assert_near(camera.screen_to_world(600.0, 400.0, WINDOW), (100.0, 50.0));
+        assert_near(world_to_screen(&camera, 100.0, 50.0), (600.0, 400.0));
+    }
+
+    #[test]
+    fn rotation() {
+        // A quarter turn clockwise shows what's right of the camera above the center
+        let camera = Camera2D::new().with_rotation(FRAC_PI_2);
+        assert_near(world_to_screen(&camera, 100.0, 0.0), (400.0, 200.0));
+        assert_near(camera.screen_to_world(400.0, 200.0, WINDOW), (100.0, 0.0));
+    }
+
+    #[test]
+    fn viewport() {
+        let camera = Camera2D::new().with_viewport(Viewport {
+            x: 0.5,
+            y: 0.5,
+            width: 0.5,
+            height: 0.5,
+        });
+        assert_near(camera.screen_to_world(600.0, 450.0, WINDOW), (0.0, 0.0));
+        assert_near(world_to_screen(&camera, 200.0, 150.0), (800.0, 600.0));
+    }
+
+    #[test]
+    fn round_trip() {
+        let camera = Camera2D::new()
+            .looking_at(35.0, 70.0)
+            .with_zoom(1.5)
+            .with_rotation(0.7)
+            .with_viewport(Viewport {
+                x: 0.25,
+                y: 0.0,
+                width: 0.5,
+                height: 1.0,
+            });
+        for &(x, y) in &[(0.0, 0.0), (-250.0, 13.0), (400.0, -90.0)] {
+            let (sx, sy) = world_to_screen(&camera, x, y);
+            assert_near(camera.screen_to_world(sx, sy, WINDOW), (x, y));
+        }
+    }
+}
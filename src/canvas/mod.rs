@@ -0,0 +1,168 @@
+// Immediate-mode drawing. Entities describe what to draw every update through a Canvas,
+// and the commands are drawn alongside sprites in layer order.
+
+mod shapes;
+
+use crate::graphics::Color;
+use crate::text::{Font, FontId, TextStyle};
+
+use std::sync::Arc;
+
+// Coordinates of a command, either in the world as seen by the camera or in pixels of the
+// screen (or virtual resolution) with the origin at the top-left corner.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Space {
+    World,
+    Screen,
+}
+
+#[derive(Clone)]
+pub enum Command {
+    Text {
+        font: Arc<Font>,
+        text: String,
+        x: f32,
+        y: f32,
+        style: TextStyle,
+        layer: i32,
+        space: Space,
+    },
+    // Untextured triangles, three points each
+    Shape {
+        triangles: Vec<[f32; 2]>,
+        color: Color,
+        layer: i32,
+        space: Space,
+    },
+}
+
+impl Command {
+    pub fn layer(&self) -> i32 {
+        match *self {
+            Command::Text { layer, .. } | Command::Shape { layer, .. } => layer,
+        }
+    }
+
+    pub fn space(&self) -> Space {
+        match *self {
+            Command::Text { space, .. } | Command::Shape { space, .. } => space,
+        }
+    }
+}
+
+pub struct Canvas {
+    fonts: Arc<Vec<Arc<Font>>>,
+    layer: i32,
+    space: Space,
+    commands: Vec<Command>,
+}
+
+impl Canvas {
+    pub(crate) fn new(fonts: Arc<Vec<Arc<Font>>>) -> Self {
+        Canvas {
+            fonts: fonts,
+            layer: 0,
+            space: Space::World,
+            commands: Vec::new(),
+        }
+    }
+
+    // Layer used by the following commands, see SpriteStyle::layer.
+    pub fn set_layer(&mut self, layer: i32) {
+        self.layer = layer;
+    }
+
+    pub fn set_space(&mut self, space: Space) {
+        self.space = space;
+    }
+
+    pub fn font(&self, font: FontId) -> &Font {
+        &self.fonts[font.0]
+    }
+
+    // Draws text with its top-left corner at x, y. Text that's wrapped or aligned is aligned
+    // within its wrap width, or within its widest line.
+    pub fn text(&mut self, font: FontId, text: &str, x: f32, y: f32, style: &TextStyle) {
+        self.commands.push(Command::Text {
+            font: self.fonts[font.0].clone(),
+            text: text.to_string(),
+            x: x,
+            y: y,
+            style: style.clone(),
+            layer: self.layer,
+            space: self.space,
+        });
+    }
+
+    pub fn measure_text(&self, font: FontId, text: &str, style: &TextStyle) -> (f32, f32) {
+        self.font(font).measure(text, style)
+    }
+
+    pub fn fill_rect(&mut self, x: f32, y: f32, width: f32, height: f32, color: Color) {
+        self.shape(shapes::rect(x, y, width, height), color);
+    }
+
+    // Outlines are centered on the shape's edges.
+    pub fn stroke_rect(
+        &mut self,
+        x: f32,
+        y: f32,
+        width: f32,
+        height: f32,
+        thickness: f32,
+        color: Color,
+    ) {
+        let corners = [
+            (x, y),
+            (x + width, y),
+            (x + width, y + height),
+            (x, y + height),
+        ];
+        self.shape(shapes::polyline(&corners, thickness, true), color);
+    }
+
+    pub fn fill_circle(&mut self, x: f32, y: f32, radius: f32, color: Color) {
+        self.shape(shapes::convex(&shapes::circle(x, y, radius)), color);
+    }
+
+    pub fn stroke_circle(&mut self, x: f32, y: f32, radius: f32, thickness: f32, color: Color) {
+        let points = shapes::circle(x, y, radius);
+        self.shape(shapes::polyline(&points, thickness, true), color);
+    }
+
+    pub fn line(&mut self, x0: f32, y0: f32, x1: f32, y1: f32, thickness: f32, color: Color) {
+        self.shape(
+            shapes::polyline(&[(x0, y0), (x1, y1)], thickness, false),
+            color,
+        );
+    }
+
+    pub fn polyline(&mut self, points: &[(f32, f32)], thickness: f32, color: Color) {
+        self.shape(shapes::polyline(points, thickness, false), color);
+    }
+
+    // The polygon has to be convex, in either winding order.
+    pub fn fill_polygon(&mut self, points: &[(f32, f32)], color: Color) {
+        self.shape(shapes::convex(points), color);
+    }
+
+    pub fn stroke_polygon(&mut self, points: &[(f32, f32)], thickness: f32, color: Color) {
+        self.shape(shapes::polyline(points, thickness, true), color);
+    }
+
+    fn shape(&mut self, triangles: Vec<[f32; 2]>, color: Color) {
+        if triangles.is_empty() {
+            return;
+        }
+        self.commands.push(Command::Shape {
+            triangles: triangles,
+            color: color,
+            layer: self.layer,
+            space: self.space,
+        });
+    }
+
+    pub(crate) fn into_commands(self) -> Vec<Command> {
+        self.commands
+    }
+}
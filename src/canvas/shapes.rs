@@ -0,0 +1,203 @@
+// Tessellation of shapes into triangle lists.
+
+// Longer miters than this many times half the thickness are cut short at sharp corners
+const MITER_LIMIT: f32 = 4.0;
+
+pub fn rect(x: f32, y: f32, w: f32, h: f32) -> Vec<[f32; 2]> {
+    vec![
+        [x, y],
+        [x, y + h],
+        [x + w, y],
+        [x + w, y],
+        [x, y + h],
+        [x + w, y + h],
+    ]
+}
+
+// Points on a circle, with more segments for larger circles.
+pub fn circle(x: f32, y: f32, radius: f32) -> Vec<(f32, f32)> {
+    let segments = ((radius.abs().sqrt() * 4.0).ceil() as usize)
+        .max(12)
+        .min(128);
+    (0..segments)
+        .map(|i| {
+            let angle = i as f32 / segments as f32 * std::f32::consts::PI * 2.0;
+            (x + radius * angle.cos(), y + radius * angle.sin())
+        })
+        .collect()
+}
+
+// Triangle fan of a convex polygon.
+pub fn convex(points: &[(f32, f32)]) -> Vec<[f32; 2]> {
+    let mut triangles = Vec::with_capacity(points.len().saturating_sub(2) * 3);
+    for i in 1..points.len().saturating_sub(1) {
+        for &(x, y) in &[points[0], points[i], points[i + 1]] {
+            triangles.push([x, y]);
+        }
+    }
+    triangles
+}
+
+// A line of the given thickness through points, centered on them, with mitered corners.
+// A closed line also joins the last point to the first.
+pub fn polyline(points: &[(f32, f32)], thickness: f32, closed: bool) -> Vec<[f32; 2]> {
+    let n = points.len();
+    if n < 2 {
+        return Vec::new();
+    }
+    let half = thickness / 2.0;
+
+    // Unit normal of the segment from a to b, zero for segments without a length
+    let normal = |a: (f32, f32), b: (f32, f32)| {
+        let (dx, dy) = (b.0 - a.0, b.1 - a.1);
+        let len = (dx * dx + dy * dy).sqrt();
+        if len == 0.0 {
+            (0.0, 0.0)
+        } else {
+            (-dy / len, dx / len)
+        }
+    };
+
+    // Offset of the line's edges from each point
+    let offsets: Vec<(f32, f32)> = (0..n)
+        .map(|i| {
+            let prev = if i > 0 {
+                Some(normal(points[i - 1], points[i]))
+            } else if closed {
+                Some(normal(points[n - 1], points[0]))
+            } else {
+                None
+            };
+            let next = if i + 1 < n {
+                Some(normal(points[i], points[i + 1]))
+            } else if closed {
+                Some(normal(points[n - 1], points[0]))
+            } else {
+                None
+            };
+
+            match (prev, next) {
+                (Some(a), Some(b)) => {
+                    let (mx, my) = (a.0 + b.0, a.1 + b.1);
+                    let len = (mx * mx + my * my).sqrt();
+                    if len == 0.0 {
+                        (a.0 * half, a.1 * half)
+                    } else {
+                        // The miter is longer the sharper the corner
+                        let (mx, my) = (mx / len, my / len);
+                        let cos = mx * b.0 + my * b.1;
+                        let length = half / cos.max(1.0 / MITER_LIMIT);
+                        (mx * length, my * length)
+                    }
+                }
+                (Some(a), None) | (None, Some(a)) => (a.0 * half, a.1 * half),
+                (None, None) => (0.0, 0.0),
+            }
+        })
+        .collect();
+
+    let segments = if closed { n } else { n - 1 };
+    let mut triangles = Vec::with_capacity(segments * 6);
+    for i in 0..segments {
+        let j = (i + 1) % n;
+        let (a, b) = (points[i], points[j]);
+        let (oa, ob) = (offsets[i], offsets[j]);
+        let (a0, a1) = ([a.0 + oa.0, a.1 + oa.1], [a.0 - oa.0, a.1 - oa.1]);
+        let (b0, b1) = ([b.0 + ob.0, b.1 + ob.1], [b.0 - ob.0, b.1 - ob.1]);
+        triangles.extend_from_slice(&[a0, a1, b0, b0, a1, b1]);
+    }
+    triangles
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Offset of the line's edge from the point a segment starts at, the first of its vertices
+    fn offset(triangles: &[[f32; 2]], segment: usize, point: (f32, f32)) -> (f32, f32) {
+        let v = triangles[segment * 6];
+        (v[0] - point.0, v[1] - point.1)
+    }
+
+    fn assert_near(a: (f32, f32), b: (f32, f32)) {
+        assert!(
+            (a.0 - b.0).abs() < 1e-4 && (a.1 - b.1).abs() < 1e-4,
+            "{:?} != {:?}",
+            a,
+            b
+        );
+    }
+
+    #[test]
+    fn too_few_points() {
+        assert!(polyline(&[], 2.0, false).is_empty());
+        assert!(polyline(&[(1.0, 1.0)], 2.0, true).is_empty());
+    }
+
+    #[test]
+    fn straight_line() {
+        let triangles = polyline(&[(0.0, 0.0), (10.0, 0.0)], 2.0, false);
+        assert_eq!(
+            triangles,
+            vec![
+                [0.0, 1.0],
+                [0.0, -1.0],
+                [10.0, 1.0],
+                [10.0, 1.0],
+                [0.0, -1.0],
+                [10.0, -1.0],
+            ]
+        );
+    }
+
+    #[test]
+    fn right_angle_miter() {
+        let points = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0)];
+        let triangles = polyline(&points, 2.0, false);
+        assert_eq!(triangles.len(), 12);
+        // Both segments meet at the corner of their edges
+        assert_near(offset(&triangles, 1, points[1]), (-1.0, 1.0));
+        let end = triangles[2];
+        assert_near((end[0], end[1]), (9.0, 1.0));
+        let end = triangles[5];
+        assert_near((end[0], end[1]), (11.0, -1.0));
+    }
+
+    #[test]
+    fn miter_limit() {
+        let points = [(0.0, 0.0), (10.0, 0.0), (0.0, 1.0)];
+        let (x, y) = offset(&polyline(&points, 2.0, false), 1, points[1]);
+        assert!(((x * x + y * y).sqrt() - MITER_LIMIT).abs() < 1e-4);
+    }
+
+    #[test]
+    fn turning_back() {
+        let points = [(0.0, 0.0), (10.0, 0.0), (0.0, 0.0)];
+        let triangles = polyline(&points, 2.0, false);
+        assert!(triangles
+            .iter()
+            .all(|v| v[0].is_finite() && v[1].is_finite()));
+        assert_near(offset(&triangles, 1, points[1]), (0.0, 1.0));
+    }
+
+    #[test]
+    fn repeated_point() {
+        let points = [(0.0, 0.0), (0.0, 0.0), (10.0, 0.0)];
+        let triangles = polyline(&points, 2.0, false);
+        assert!(triangles
+            .iter()
+            .all(|v| v[0].is_finite() && v[1].is_finite()));
+        assert_near(offset(&triangles, 1, points[1]), (0.0, 1.0));
+    }
+
+    #[test]
+    fn closed_square() {
+        let points = [(0.0, 0.0), (10.0, 0.0), (10.0, 10.0), (0.0, 10.0)];
+        assert_eq!(polyline(&points, 2.0, false).len(), 18);
+        let triangles = polyline(&points, 2.0, true);
+        assert_eq!(triangles.len(), 24);
+        // The first point is a corner too once the line is closed
+        assert_near(offset(&triangles, 0, points[0]), (1.0, 1.0));
+        assert_near(offset(&triangles, 3, points[3]), (1.0, -1.0));
+    }
+}
@@ -0,0 +1,70 @@
+// Saving presented frames as PNG files, one at a time or every frame while recording. Frames
+// are saved as they appear in the window, letterboxing included, and written to disk on a
+// thread of their own.
+
+#[derive(Debug, Clone)]
+pub struct Capture {
+    screenshots: Vec<String>,
+    // Directory frames are saved to while recording
+    recording: Option<String>,
+    // Number of the next recorded frame, counted from 0 for every recording
+    next_frame: u32,
+}
+
+impl Capture {
+    pub fn new() -> Self {
+        Capture {
+            screenshots: Vec::new(),
+            recording: None,
+            next_frame: 0,
+        }
+    }
+
+    // Saves the next presented frame to path.
+    pub fn screenshot(&mut self, path: &str) {
+        self.screenshots.push(path.to_string());
+    }
+
+    // Saves every presented frame to directory as 000000.png, 000001.png and so on, until
+    // stop_recording. Recording slows the game down, the GPU is waited on after every frame.
+    pub fn start_recording(&mut self, directory: &str) {
+        self.recording = Some(directory.to_string());
+        self.next_frame = 0;
+    }
+
+    pub fn stop_recording(&mut self) {
+        self.recording = None;
+    }
+
+    pub fn is_recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    // Paths the frame about to be presented is saved to. They stay pending until frame_saved,
+    // a frame that's dropped before it's presented leaves them for the next one.
+    pub(crate) fn paths(&self) -> Vec<String> {
+        let mut paths = self.screenshots.clone();
+        if let Some(path) = self.recorded_path() {
+            paths.push(path);
+        }
+        paths
+    }
+
+    // Called once the frame paths were taken for has been presented, and is being saved.
+    pub(crate) fn frame_saved(&mut self, paths: &[String]) {
+        // Screenshots asked for in the meantime are left for the next frame
+        self.screenshots.retain(|s| !paths.contains(s));
+        if let Some(path) = self.recorded_path() {
+            if paths.contains(&path) {
+                self.next_frame += 1;
+            }
+        }
+    }
+
+    fn recorded_path(&self) -> Option<String> {
+        self.recording.as_ref().map(|directory| {
+            let frame = std::path::Path::new(directory).join(format!("{:06}.png", self.next_frame));
+            frame.to_string_lossy().into_owned()
+        })
+    }
+}
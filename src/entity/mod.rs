@@ -1,5 +1,18 @@
+use crate::asset::aseprite::{AnimationClip, Frame};
+use crate::asset::Region;
+use crate::camera::Camera2D;
+use crate::canvas::Canvas;
+use crate::capture::Capture;
+use crate::graphics::{BlendMode, Color, Sampling, SpriteMemory, SurfaceFormat};
+use crate::lighting::Lighting;
+use crate::material::{Material, MaterialId};
+use crate::particles::Emitter;
+use crate::rendertexture::{RenderTexture, RenderTextureId};
+use crate::tilemap::TileMap;
 use crate::Game;
 
+use std::time::Duration;
+
 #[derive(Debug, Clone, PartialEq)]
 pub struct Rect {
     pub width: f32,
@@ -19,14 +32,243 @@ impl Rect {
     }
 }
 
+// How a sprite is drawn, kept next to its Rect. The default style draws the texture as it is,
+// on layer 0.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpriteStyle {
+    // Draw order, higher layers are drawn on top of lower ones
+    pub layer: i32,
+    pub transform: Transform,
+    // Multiplied with the texture, the alpha channel acts as opacity
+    pub tint: Color,
+    pub blend: BlendMode,
+    // Draws the sprite with a fragment shader of the game's instead of the default one
+    pub material: Option<MaterialId>,
+    // Shows what a render texture's camera sees instead of the sprite's own texture
+    pub render_texture: Option<RenderTextureId>,
+    // Part of the texture shown, in pixels. None shows all of it.
+    pub region: Option<Region>,
+    // Name of the clip playing on a sprite loaded from an Aseprite file, which sets region
+    // every update. None keeps showing the current frame.
+    pub animation: Option<String>,
+}
+
+impl Default for SpriteStyle {
+    fn default() -> Self {
+        SpriteStyle {
+            layer: 0,
+            transform: Transform::default(),
+            tint: Color::WHITE,
+            blend: BlendMode::Alpha,
+            material: None,
+            render_texture: None,
+            region: None,
+            animation: None,
+        }
+    }
+}
+
+impl SpriteStyle {
+    pub fn new() -> Self {
+        SpriteStyle::default()
+    }
+
+    pub fn with_tint(mut self, tint: Color) -> Self {
+        self.tint = tint;
+        self
+    }
+
+    pub fn with_blend(mut self, blend: BlendMode) -> Self {
+        self.blend = blend;
+        self
+    }
+
+    pub fn with_material(mut self, material: MaterialId) -> Self {
+        self.material = Some(material);
+        self
+    }
+
+    pub fn with_render_texture(mut self, texture: RenderTextureId) -> Self {
+        self.render_texture = Some(texture);
+        self
+    }
+
+    pub fn on_layer(mut self, layer: i32) -> Self {
+        self.layer = layer;
+        self
+    }
+
+    pub fn with_transform(mut self, transform: Transform) -> Self {
+        self.transform = transform;
+        self
+    }
+
+    pub fn with_region(mut self, region: Region) -> Self {
+        self.region = Some(region);
+        self
+    }
+
+    // Plays an Aseprite tag from its first frame, or keeps playing it if it already is.
+    pub fn play(&mut self, clip: &str) {
+        self.animation = Some(clip.to_string());
+    }
+}
+
+// Rotation, scale and mirroring applied to a sprite's Rect around its origin.
+// The origin is relative to the Rect, (0, 0) being the top-left corner and (1, 1) the
+// bottom-right one. The default transform leaves the Rect untouched.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Transform {
+    // Radians, clockwise on screen
+    pub rotation: f32,
+    pub scale_x: f32,
+    pub scale_y: f32,
+    pub flip_x: bool,
+    pub flip_y: bool,
+    pub origin_x: f32,
+    pub origin_y: f32,
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Transform {
+            rotation: 0.0,
+            scale_x: 1.0,
+            scale_y: 1.0,
+            flip_x: false,
+            flip_y: false,
+            origin_x: 0.0,
+            origin_y: 0.0,
+        }
+    }
+}
+
+impl Transform {
+    pub fn new() -> Self {
+        Transform::default()
+    }
+
+    pub fn rotate(mut self, radians: f32) -> Self {
+        self.rotation = radians;
+        self
+    }
+
+    pub fn scale(mut self, x: f32, y: f32) -> Self {
+        self.scale_x = x;
+        self.scale_y = y;
+        self
+    }
+
+    pub fn flip(mut self, horizontal: bool, vertical: bool) -> Self {
+        self.flip_x = horizontal;
+        self.flip_y = vertical;
+        self
+    }
+
+    pub fn origin(mut self, x: f32, y: f32) -> Self {
+        self.origin_x = x;
+        self.origin_y = y;
+        self
+    }
+
+    // Maps a point given relative to the Rect's size (0..1 on both axes) to an offset from the
+    // Rect's top-left corner, with the origin as pivot.
+    pub fn apply(&self, x: f32, y: f32, width: f32, height: f32) -> (f32, f32) {
+        let (px, py) = (
+            (x - self.origin_x) * width * self.scale_x,
+            (y - self.origin_y) * height * self.scale_y,
+        );
+        let (sin, cos) = self.rotation.sin_cos();
+        (
+            px * cos - py * sin + self.origin_x * width,
+            px * sin + py * cos + self.origin_y * height,
+        )
+    }
+}
+
 pub trait Entity {
     fn init(&mut self);
     fn update(&mut self, rect: &mut Rect);
+    // Runs after update, lets an entity change the world around it, e.g. steer the camera,
+    // open a door in a tile map or move the torch it carries. See FrameContext.
+    fn update_world(&mut self, _rect: &Rect, _ctx: &mut FrameContext) {}
+    // Runs every update after update_world, for drawing text and other things besides the
+    // entity's own sprite.
+    fn draw(&self, _rect: &Rect, _canvas: &mut Canvas) {}
     // fn events<F>(HashMap<Event, F>) {}
 }
 
+// The parts of the game an entity can reach from update_world. New features add a field here
+// rather than a hook of their own.
+pub struct FrameContext<'a> {
+    // The entity's own SpriteStyle, e.g. to flip the sprite when it turns around
+    pub style: &'a mut SpriteStyle,
+    pub camera: &'a mut Camera2D,
+    // The extra cameras in Game::cameras, e.g. the second player's half of a split-screen
+    pub cameras: &'a mut [Camera2D],
+    pub tilemaps: &'a mut [TileMap],
+    pub emitters: &'a mut [Emitter],
+    // Indexed by MaterialId, e.g. to change how far a dissolve has come
+    pub materials: &'a mut [Material],
+    // Lights are indexed in the order they were added
+    pub lighting: &'a mut Lighting,
+    // Indexed by RenderTextureId, e.g. to move a minimap's camera with the player
+    pub render_textures: &'a mut [RenderTexture],
+    // Screenshots and recording frames
+    pub capture: &'a mut Capture,
+    // How much GPU memory sprite textures took last frame, other textures aren't counted
+    pub sprite_memory: &'a SpriteMemory,
+    // Format the window shows frames in, None until the game runs
+    pub surface: Option<SurfaceFormat>,
+}
+
 pub struct Texture {
     pub rect: Rect,
+    pub style: SpriteStyle,
     pub entity: Box<Entity + Send + Sync>,
     pub sprite: (Vec<u8>, (u32, u32)),
+    // None uses the game's default sampling
+    pub sampling: Option<Sampling>,
+    // Shades the sprite by where light comes from when lighting is enabled
+    pub normal_map: Option<(Vec<u8>, (u32, u32))>,
+    // Frames and clips of a sprite loaded from an Aseprite file, sprite holding all frames
+    pub sheet: Option<SpriteSheet>,
+}
+
+// The frames of an Aseprite file in the sprite's atlas and the clips made of them, played by
+// setting SpriteStyle::animation.
+pub struct SpriteSheet {
+    pub frames: Vec<Frame>,
+    pub clips: Vec<AnimationClip>,
+    playing: Option<String>,
+    elapsed: Duration,
+}
+
+impl SpriteSheet {
+    pub fn new(frames: Vec<Frame>, clips: Vec<AnimationClip>) -> Self {
+        SpriteSheet {
+            frames: frames,
+            clips: clips,
+            playing: None,
+            elapsed: Duration::from_secs(0),
+        }
+    }
+
+    // Moves the clip style plays forward by dt and shows its current frame. A clip that
+    // started playing since the last update starts from its first frame.
+    pub(crate) fn advance(&mut self, style: &mut SpriteStyle, dt: Duration) {
+        if style.animation != self.playing {
+            self.playing = style.animation.clone();
+            self.elapsed = Duration::from_secs(0);
+        } else {
+            self.elapsed += dt;
+        }
+        let clip = match self.playing {
+            Some(ref name) => self.clips.iter().find(|c| c.name == *name),
+            None => None,
+        };
+        if let Some(frame) = clip.and_then(|c| self.frames.get(c.frame_at(self.elapsed))) {
+            style.region = Some(frame.region);
+        }
+    }
 }
@@ -0,0 +1,175 @@
+// Rendering options exposed to games.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Filter {
+    Nearest,
+    Linear,
+}
+
+// How the virtual resolution is fit into the window.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scaling {
+    // Largest whole multiple of the virtual resolution that fits, with black bars around it
+    Integer,
+    // Largest size that keeps the aspect ratio, with black bars on two sides
+    Fit,
+    // Fills the window, ignoring the aspect ratio
+    Stretch,
+    // Grows the virtual resolution along one axis to match the window's aspect ratio
+    Expand,
+}
+
+// A fixed resolution the scene is rendered at before being scaled to the window.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VirtualResolution {
+    pub width: u32,
+    pub height: u32,
+    pub scaling: Scaling,
+}
+
+// What happens when a texture is sampled outside of 0..1.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Wrap {
+    Clamp,
+    Repeat,
+    Mirror,
+}
+
+// How a texture is sampled. Textures with equal settings share one sampler.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Sampling {
+    pub filter: Filter,
+    pub wrap: Wrap,
+    // Generates mipmaps when the texture is uploaded, for textures drawn scaled down
+    pub mipmaps: bool,
+    // 1 disables anisotropic filtering, clamped to what the device supports
+    pub anisotropy: u8,
+}
+
+impl Default for Sampling {
+    fn default() -> Self {
+        Sampling {
+            filter: Filter::Linear,
+            wrap: Wrap::Repeat,
+            mipmaps: false,
+            anisotropy: 1,
+        }
+    }
+}
+
+impl Sampling {
+    pub fn pixel_art() -> Self {
+        Sampling {
+            filter: Filter::Nearest,
+            wrap: Wrap::Clamp,
+            ..Sampling::default()
+        }
+    }
+}
+
+// Straight (not premultiplied) RGBA color, each channel from 0.0 to 1.0.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Color {
+    pub r: f32,
+    pub g: f32,
+    pub b: f32,
+    pub a: f32,
+}
+
+impl Color {
+    pub const WHITE: Color = Color::rgba(1.0, 1.0, 1.0, 1.0);
+    pub const BLACK: Color = Color::rgba(0.0, 0.0, 0.0, 1.0);
+    pub const TRANSPARENT: Color = Color::rgba(0.0, 0.0, 0.0, 0.0);
+
+    pub const fn rgba(r: f32, g: f32, b: f32, a: f32) -> Self {
+        Color {
+            r: r,
+            g: g,
+            b: b,
+            a: a,
+        }
+    }
+
+    pub const fn rgb(r: f32, g: f32, b: f32) -> Self {
+        Color::rgba(r, g, b, 1.0)
+    }
+
+    pub fn with_alpha(self, a: f32) -> Self {
+        Color { a: a, ..self }
+    }
+
+    pub fn to_array(self) -> [f32; 4] {
+        [self.r, self.g, self.b, self.a]
+    }
+}
+
+// How a sprite is combined with what's already been drawn.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum BlendMode {
+    // Regular transparency for textures with straight alpha
+    Alpha,
+    // Transparency for textures whose color is already multiplied by their alpha
+    Premultiplied,
+    // Adds to the colors behind, for glows and fire
+    Additive,
+    // Darkens the colors behind
+    Multiply,
+    // Lightens the colors behind
+    Screen,
+}
+
+impl Default for BlendMode {
+    fn default() -> Self {
+        BlendMode::Alpha
+    }
+}
+
+// GPU memory taken by the textures of connected sprites, updated every frame. Only sprites are
+// counted and evicted: the glyph atlas, tile sets, parallax layers, particles, materials, color
+// grading LUTs and render textures stay on the GPU outside of it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpriteMemory {
+    // Bytes of sprite textures allowed on the GPU at once, see Game::sprite_budget
+    pub budget: Option<u64>,
+    // Bytes of sprite textures on the GPU right now
+    pub used: u64,
+    // By the texture's index in Game::textures
+    pub textures: Vec<TextureUsage>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TextureUsage {
+    pub id: usize,
+    // Last known size on the GPU, a preview's while a large texture is still streaming in
+    pub bytes: u64,
+    // Evicted textures are uploaded again the next time they're in view
+    pub resident: bool,
+    // Frames since the texture was last drawn, None if it never was
+    pub frames_unseen: Option<u64>,
+}
+
+impl SpriteMemory {
+    pub fn new() -> Self {
+        SpriteMemory {
+            budget: None,
+            used: 0,
+            textures: Vec::new(),
+        }
+    }
+}
+
+// How the window's frames are shown, known once the game runs. See FrameContext::surface.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SurfaceFormat {
+    // Whether colors are encoded to sRGB as they're written. Without it colors come out darker
+    // than they were authored, unless post-processing encodes them.
+    pub srgb: bool,
+    pub color_space: ColorSpace,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ColorSpace {
+    Srgb,
+    // Any color space besides sRGB, frames may not look as authored
+    Other,
+}
@@ -1,21 +1,34 @@
+pub mod asset;
+pub mod camera;
+pub mod canvas;
+pub mod capture;
 pub mod entity;
 mod framecounter;
+pub mod graphics;
+pub mod lighting;
+pub mod material;
+pub mod parallax;
+pub mod particles;
+pub mod postprocess;
 mod render;
+pub mod rendertexture;
+pub mod text;
+pub mod tilemap;
 extern crate image;
 extern crate winit;
 
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::{mpsc, Arc, Mutex};
 use std::thread;
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use image::*;
 
-use render::vk;
-
 use std::collections::HashMap;
 
 use vulkano::format::Format;
 use vulkano::image::{Dimensions, ImmutableImage};
+use vulkano::swapchain::SwapchainCreationError;
 use vulkano::sync::GpuFuture;
 use vulkano_win::VkSurfaceBuild;
 
@@ -24,20 +37,70 @@ use winit::WindowEvent;
 
 struct Settings {
     framelimit: u16,
+    y_sorted_layers: Vec<i32>,
+    virtual_resolution: Option<graphics::VirtualResolution>,
+    filter: graphics::Filter,
+    fps_font: Option<text::FontId>,
+    sprite_budget: Option<u64>,
+    resizable: bool,
 }
 
 pub struct Game {
     settings: Settings,
     pub active_textures: HashMap<String, bool>,
     pub textures: Vec<entity::Texture>,
+    pub camera: camera::Camera2D,
+    // Drawn after camera in order, e.g. for split-screen or picture-in-picture. Parts of the
+    // window no camera covers show camera's clear color.
+    pub cameras: Vec<camera::Camera2D>,
+    pub tilemaps: Vec<tilemap::TileMap>,
+    pub parallax: Vec<parallax::ParallaxLayer>,
+    pub emitters: Vec<particles::Emitter>,
+    pub materials: Vec<material::Material>,
+    // Run over the finished frame in order
+    pub post_effects: Vec<postprocess::Effect>,
+    pub lighting: lighting::Lighting,
+    pub render_textures: Vec<rendertexture::RenderTexture>,
+    pub capture: capture::Capture,
+    // How much GPU memory sprite textures took last frame
+    pub sprite_memory: graphics::SpriteMemory,
+    // Format of the window's frames, None until the game runs
+    pub surface_format: Option<graphics::SurfaceFormat>,
+    fonts: Vec<Arc<text::Font>>,
+    // What the entities drew on their canvas during the last update
+    canvas: Vec<canvas::Command>,
+    factories: HashMap<String, asset::tiled::Factory>,
 }
 
 impl Game {
     pub fn init() -> Self {
         Game {
-            settings: Settings { framelimit: 144 },
+            settings: Settings {
+                framelimit: 144,
+                y_sorted_layers: Vec::new(),
+                virtual_resolution: None,
+                filter: graphics::Filter::Linear,
+                fps_font: None,
+                sprite_budget: None,
+                resizable: true,
+            },
             active_textures: HashMap::new(),
             textures: Vec::new(),
+            camera: camera::Camera2D::new(),
+            cameras: Vec::new(),
+            tilemaps: Vec::new(),
+            parallax: Vec::new(),
+            emitters: Vec::new(),
+            materials: Vec::new(),
+            post_effects: Vec::new(),
+            lighting: lighting::Lighting::new(),
+            render_textures: Vec::new(),
+            capture: capture::Capture::new(),
+            sprite_memory: graphics::SpriteMemory::new(),
+            surface_format: None,
+            fonts: Vec::new(),
+            canvas: Vec::new(),
+            factories: HashMap::new(),
         }
     }
 
@@ -47,21 +110,285 @@ impl Game {
         rect: entity::Rect,
         img_path: &str,
     ) -> Result<(), String> {
-        let img = match image::open(img_path) {
-            Err(e) => return Err(format!("Could not open {}: {}", img_path, e)),
-            Ok(i) => i,
+        self.load_texture(entity, rect, entity::SpriteStyle::default(), img_path, None)
+    }
+
+    // Like connect, with the sprite drawn on another layer, transformed, tinted, blended or
+    // with a material, see entity::SpriteStyle.
+    pub fn connect_with_style(
+        &mut self,
+        entity: Box<entity::Entity + Send + Sync>,
+        rect: entity::Rect,
+        style: entity::SpriteStyle,
+        img_path: &str,
+    ) -> Result<(), String> {
+        self.load_texture(entity, rect, style, img_path, None)
+    }
+
+    // Like connect, but samples the image with its own filter, wrap mode and mipmaps instead of
+    // the game's defaults.
+    pub fn connect_with_sampling(
+        &mut self,
+        entity: Box<entity::Entity + Send + Sync>,
+        rect: entity::Rect,
+        img_path: &str,
+        sampling: graphics::Sampling,
+    ) -> Result<(), String> {
+        let style = entity::SpriteStyle::default();
+        self.load_texture(entity, rect, style, img_path, Some(sampling))
+    }
+
+    // Like connect, with a normal map the sprite is shaded with when lighting is enabled. The
+    // normal map is sampled like the sprite so it should line up with it pixel for pixel.
+    pub fn connect_with_normal_map(
+        &mut self,
+        entity: Box<entity::Entity + Send + Sync>,
+        rect: entity::Rect,
+        img_path: &str,
+        normal_path: &str,
+    ) -> Result<(), String> {
+        let img = image::open(normal_path)
+            .map_err(|e| format!("Could not open {}: {}", normal_path, e))?;
+        let img = img.to_rgba();
+        let dimensions = img.dimensions();
+        self.load_texture(entity, rect, entity::SpriteStyle::default(), img_path, None)?;
+        self.textures.last_mut().unwrap().normal_map = Some((img.into_raw(), dimensions));
+        Ok(())
+    }
+
+    // Like connect, with the sprite showing a render texture instead of an image.
+    pub fn connect_render_texture(
+        &mut self,
+        entity: Box<entity::Entity + Send + Sync>,
+        rect: entity::Rect,
+        texture: rendertexture::RenderTextureId,
+    ) {
+        self.textures.push(entity::Texture {
+            rect: rect,
+            style: entity::SpriteStyle::default().with_render_texture(texture),
+            entity: entity,
+            // Stands in until the render texture has been drawn
+            sprite: (vec![255; 4], (1, 1)),
+            sampling: None,
+            normal_map: None,
+            sheet: None,
+        });
+    }
+
+    fn load_texture(
+        &mut self,
+        entity: Box<entity::Entity + Send + Sync>,
+        rect: entity::Rect,
+        mut style: entity::SpriteStyle,
+        img_path: &str,
+        sampling: Option<graphics::Sampling>,
+    ) -> Result<(), String> {
+        let extension = std::path::Path::new(img_path)
+            .extension()
+            .and_then(|e| e.to_str())
+            .map(|e| e.to_lowercase());
+        let mut sheet = None;
+        let (raw, (w, h)) = match extension.as_ref().map(|e| e.as_str()) {
+            // Aseprite files are uploaded as one atlas, the sprite shows the first frame until
+            // a clip is played
+            Some("aseprite") | Some("ase") => {
+                let sprite = asset::aseprite::load(img_path)?;
+                if style.region.is_none() {
+                    style.region = Some(sprite.frames[0].region);
+                }
+                let atlas = sprite.atlas;
+                sheet = Some(entity::SpriteSheet::new(sprite.frames, sprite.clips));
+                (atlas.pixels, (atlas.width, atlas.height))
+            }
+            _ => {
+                let img = match image::open(img_path) {
+                    Err(e) => return Err(format!("Could not open {}: {}", img_path, e)),
+                    Ok(i) => i,
+                };
+                let (w, h) = (img.width(), img.height());
+                (img.to_rgba().into_raw(), (w, h))
+            }
         };
-        let (w, h) = (img.width(), img.height());
 
-        let raw = img.to_rgba().into_raw();
         self.textures.push(entity::Texture {
             rect: rect,
+            style: style,
             entity: entity,
             sprite: (raw, (w, h)),
+            sampling: sampling,
+            normal_map: None,
+            sheet: sheet,
         });
         Ok(())
     }
 
+    // Adds a camera drawn after Game::camera and returns its index in Game::cameras. Give it a
+    // viewport so it doesn't cover the cameras before it.
+    pub fn add_camera(&mut self, camera: camera::Camera2D) -> usize {
+        self.cameras.push(camera);
+        self.cameras.len() - 1
+    }
+
+    // Limits the GPU memory sprite textures take. Over the budget the textures that haven't
+    // been in view the longest are evicted, and uploaded again when they come back into view.
+    // Text, tile sets, parallax layers, materials and render textures don't count towards it.
+    pub fn sprite_budget(&mut self, bytes: u64) {
+        self.settings.sprite_budget = Some(bytes);
+    }
+
+    // Saves the first frame as a PNG file, see capture::Capture for saving frames later on.
+    pub fn screenshot(&mut self, path: &str) {
+        self.capture.screenshot(path);
+    }
+
+    // Saves every frame to directory as a numbered PNG sequence, see capture::Capture.
+    pub fn record_frames(&mut self, directory: &str) {
+        self.capture.start_recording(directory);
+    }
+
+    // Sets whether the player can resize the window, it can by default.
+    pub fn resizable(&mut self, resizable: bool) {
+        self.settings.resizable = resizable;
+    }
+
+    // Draws the textures on layer from top to bottom of the screen instead of in the order
+    // they were connected, so lower sprites overlap higher ones as in top-down games.
+    pub fn y_sort_layer(&mut self, layer: i32) {
+        if !self.settings.y_sorted_layers.contains(&layer) {
+            self.settings.y_sorted_layers.push(layer);
+        }
+    }
+
+    // Renders at a fixed resolution, e.g. 320x180 for pixel art, and scales the result to
+    // the window instead of rendering at the window's resolution.
+    pub fn virtual_resolution(&mut self, width: u32, height: u32, scaling: graphics::Scaling) {
+        self.settings.virtual_resolution = Some(graphics::VirtualResolution {
+            width: width,
+            height: height,
+            scaling: scaling,
+        });
+    }
+
+    // Sets how textures without their own Sampling, and the virtual resolution when upscaled,
+    // are filtered. Nearest keeps pixel art crisp.
+    pub fn filter(&mut self, filter: graphics::Filter) {
+        self.settings.filter = filter;
+    }
+
+    // Adds a tile map to the world and returns its index in Game::tilemaps.
+    pub fn add_tilemap(&mut self, map: tilemap::TileMap) -> usize {
+        self.tilemaps.push(map);
+        self.tilemaps.len() - 1
+    }
+
+    // Adds a scrolling background and returns its index in Game::parallax.
+    pub fn add_parallax(&mut self, layer: parallax::ParallaxLayer) -> usize {
+        self.parallax.push(layer);
+        self.parallax.len() - 1
+    }
+
+    // Adds a particle emitter at a fixed position and returns its index in Game::emitters.
+    pub fn add_emitter(&mut self, emitter: particles::Emitter) -> usize {
+        self.emitters.push(emitter);
+        self.emitters.len() - 1
+    }
+
+    // Adds a particle emitter that follows the center of an entity, plus the emitter's offset.
+    // entity is the entity's index in Game::textures, i.e. the order it was connected in.
+    pub fn attach_emitter(&mut self, entity: usize, mut emitter: particles::Emitter) -> usize {
+        emitter.attached_to = Some(entity);
+        self.add_emitter(emitter)
+    }
+
+    // Compiles a material's shader so sprites can be drawn with it, see SpriteStyle::with_material.
+    pub fn add_material(
+        &mut self,
+        mut material: material::Material,
+    ) -> Result<material::MaterialId, String> {
+        material.compile()?;
+        self.materials.push(material);
+        Ok(material::MaterialId(self.materials.len() - 1))
+    }
+
+    // Adds a post-processing effect after the ones already added and returns its index in
+    // Game::post_effects.
+    pub fn add_effect(&mut self, effect: postprocess::Effect) -> usize {
+        self.post_effects.push(effect);
+        self.post_effects.len() - 1
+    }
+
+    // Turns on lighting, the world is only as bright as ambient where no light reaches.
+    pub fn enable_lighting(&mut self, ambient: graphics::Color) {
+        self.lighting.enabled = true;
+        self.lighting.ambient = ambient;
+    }
+
+    // Adds a light and returns its index in Game::lighting.lights.
+    pub fn add_light(&mut self, light: lighting::Light) -> usize {
+        self.lighting.lights.push(light);
+        self.lighting.lights.len() - 1
+    }
+
+    // Adds a shape that blocks light and returns its index in Game::lighting.occluders. See
+    // lighting::Occluder::from_tiles and asset::tiled::TiledMap::occluders for occluders
+    // from a level's walls.
+    pub fn add_occluder(&mut self, occluder: lighting::Occluder) -> usize {
+        self.lighting.occluders.push(occluder);
+        self.lighting.occluders.len() - 1
+    }
+
+    // Adds a render texture and returns its id, see SpriteStyle::with_render_texture and
+    // Game::connect_render_texture for drawing it.
+    pub fn add_render_texture(
+        &mut self,
+        texture: rendertexture::RenderTexture,
+    ) -> rendertexture::RenderTextureId {
+        self.render_textures.push(texture);
+        rendertexture::RenderTextureId(self.render_textures.len() - 1)
+    }
+
+    // Makes spawn_objects turn objects of the given type (class in newer versions of Tiled)
+    // into entities.
+    pub fn register_factory<F>(&mut self, kind: &str, factory: F)
+    where
+        F: Fn(&asset::tiled::Object) -> Result<asset::tiled::Spawn, String> + Send + Sync + 'static,
+    {
+        self.factories.insert(kind.to_string(), Box::new(factory));
+    }
+
+    // Connects an entity for every object with a registered factory. Objects of other types
+    // are skipped, e.g. to be read as spawn points or trigger areas by the game itself.
+    pub fn spawn_objects(&mut self, objects: &[asset::tiled::Object]) -> Result<(), String> {
+        for object in objects {
+            let spawn = match self.factories.get(&object.kind) {
+                Some(factory) => factory(object)
+                    .map_err(|e| format!("Could not spawn object {}: {}", object.id, e))?,
+                None => continue,
+            };
+            self.load_texture(
+                spawn.entity,
+                spawn.rect,
+                spawn.style,
+                &spawn.image,
+                spawn.sampling,
+            )?;
+        }
+        Ok(())
+    }
+
+    // Loads a TrueType/OpenType font, or a BMFont when the path ends in .fnt, for drawing
+    // text through Entity::draw.
+    pub fn load_font(&mut self, path: &str) -> Result<text::FontId, String> {
+        let id = text::FontId(self.fonts.len());
+        self.fonts.push(Arc::new(text::Font::load(id, path)?));
+        Ok(id)
+    }
+
+    // Draws the frame rate in the top-left corner of the screen with font.
+    pub fn show_fps(&mut self, font: text::FontId) {
+        self.settings.fps_font = Some(font);
+    }
+
     // pub fn deactivate(&mut self, e: &entity::Entity) {}
 
     pub fn run(mut self) {
@@ -71,16 +398,25 @@ impl Game {
         let mut events_loop = winit::EventsLoop::new();
         let monitor = events_loop.get_primary_monitor();
         let surface = winit::WindowBuilder::new()
-            .with_resizable(false)
+            .with_resizable(self.settings.resizable)
             //.with_fullscreen(Some(monitor))
             .build_vk_surface(&events_loop, vk_instance.clone())
             .unwrap();
 
         // Vulkan
-        let mut vk = render::vkinit::init(vk_instance, &surface);;
+        let mut vk = render::vkinit::init(vk_instance, &surface, self.settings.filter);;
+        vk.set_virtual_resolution(self.settings.virtual_resolution);
+        vk.set_sprite_budget(self.settings.sprite_budget);
+        self.surface_format = Some(vk.surface_format);
 
         // Prepare threadding
         // Game session
+        let default_sampling = graphics::Sampling {
+            filter: self.settings.filter,
+            ..graphics::Sampling::default()
+        };
+        let fonts = Arc::new(self.fonts.clone());
+        let fps_font = self.settings.fps_font.map(|id| self.fonts[id.0].clone());
         let data_event = Arc::new(Mutex::new(self));
         let data_user = data_event.clone();
         let data_backend = data_event.clone();
@@ -92,13 +428,12 @@ impl Game {
         let img_recv = Arc::new(Mutex::new(img_recv_raw));
 
         for _ in 0..4 {
-            vk::spawn_render_thread(
+            render::transfer::spawn_upload_thread(
                 img_recv.clone(),
-                vk.queue.clone(),
-                vk.device.clone(),
+                vk.transfer_queue.clone(),
                 vk.pipeline.clone(),
                 draw_buffer.clone(),
-                wait_buffer.clone(),
+                vk.samplers.clone(),
             );
         }
 
@@ -106,16 +441,59 @@ impl Game {
         let mut vk_previous_frame_end =
             Box::new(vulkano::sync::now(vk.device.clone())) as Box<GpuFuture + Send + Sync>;
         let vk = Arc::new(Mutex::new(vk));
-        let vk_event = vk.clone();
+        // Set by the event loop when the window's size or HiDPI factor changes
+        let resized = Arc::new(AtomicBool::new(false));
+        let resized_event = resized.clone();
+        // Set by the backend when rendering can't go on, the event loop then ends the game
+        let stopped = Arc::new(AtomicBool::new(false));
+        let stopped_event = stopped.clone();
+        let proxy = events_loop.create_proxy();
 
         // User loop
+        let mut last_update = Instant::now();
         thread::spawn(move || loop {
             // User defined code-per-entity gets run
             thread::sleep(Duration::from_millis((1000 / 60) as u64));
             let mut data = data_user.lock().unwrap();
+            let now = Instant::now();
+            let elapsed = now - last_update;
+            let dt = elapsed.as_secs() as f32 + elapsed.subsec_micros() as f32 / 1_000_000.0;
+            last_update = now;
+            let data = &mut *data;
+            let mut canvas = canvas::Canvas::new(fonts.clone());
             for texture in &mut data.textures {
                 texture.entity.update(&mut texture.rect);
+                let mut ctx = entity::FrameContext {
+                    style: &mut texture.style,
+                    camera: &mut data.camera,
+                    cameras: &mut data.cameras,
+                    tilemaps: &mut data.tilemaps,
+                    emitters: &mut data.emitters,
+                    materials: &mut data.materials,
+                    lighting: &mut data.lighting,
+                    render_textures: &mut data.render_textures,
+                    capture: &mut data.capture,
+                    sprite_memory: &data.sprite_memory,
+                    surface: data.surface_format,
+                };
+                texture.entity.update_world(&texture.rect, &mut ctx);
+                if let Some(ref mut sheet) = texture.sheet {
+                    sheet.advance(&mut texture.style, elapsed);
+                }
+                texture.entity.draw(&texture.rect, &mut canvas);
             }
+            let textures = &data.textures;
+            for emitter in &mut data.emitters {
+                if let Some(texture) = emitter.attached_to.and_then(|i| textures.get(i)) {
+                    let rect = &texture.rect;
+                    let transform = &texture.style.transform;
+                    let (dx, dy) = transform.apply(0.5, 0.5, rect.width, rect.height);
+                    emitter.position_x = rect.position_x + dx + emitter.offset_x;
+                    emitter.position_y = rect.position_y + dy + emitter.offset_y;
+                }
+                emitter.update(dt);
+            }
+            data.canvas = canvas.into_commands();
         });
 
         let mut fps = framecounter::FPSCounter::new();
@@ -124,50 +502,124 @@ impl Game {
         thread::spawn(move || {
             let data = data_backend.lock().unwrap();
 
-            for texture in &data.textures {
-                img_send
-                    .send((
-                        texture.sprite.0.clone(),
-                        ((texture.sprite.1).0, (texture.sprite.1).1),
-                        texture.rect.clone(),
-                    ))
-                    .unwrap();
+            let upload = |id: usize, texture: &entity::Texture| render::transfer::Upload {
+                id: id,
+                pixels: texture.sprite.0.clone(),
+                dimensions: texture.sprite.1,
+                rect: texture.rect.clone(),
+                style: texture.style.clone(),
+                sampling: texture.sampling.unwrap_or(default_sampling),
+            };
+            for (id, texture) in data.textures.iter().enumerate() {
+                img_send.send(upload(id, texture)).unwrap();
             }
             drop(data);
             let window = surface.window();
+            let mut frames = 0;
+            let mut recreate = false;
+            let stop = |error: String| {
+                eprintln!("{}", error);
+                stopped.store(true, Ordering::SeqCst);
+                let _ = proxy.wakeup();
+            };
 
             loop {
-                let data = data_backend.lock().unwrap();
+                // A minimized window has nothing to draw to, rendering waits until it's restored
+                let dims: (u32, u32) = match window.get_inner_size() {
+                    Some(size) => size.to_physical(window.get_hidpi_factor()).into(),
+                    None => (0, 0),
+                };
+                if dims.0 == 0 || dims.1 == 0 {
+                    thread::sleep(Duration::from_millis(50));
+                    continue;
+                }
+                if resized.swap(false, Ordering::SeqCst) {
+                    recreate = true;
+                }
+                if recreate {
+                    match vk.lock().unwrap().update_swapchain([dims.0, dims.1]) {
+                        Ok(()) => recreate = false,
+                        // The window changed size again while recreating
+                        Err(SwapchainCreationError::UnsupportedDimensions) => {
+                            thread::sleep(Duration::from_millis(10));
+                            continue;
+                        }
+                        Err(e) => return stop(format!("Could not recreate the swapchain: {}", e)),
+                    }
+                }
+
+                let mut data = data_backend.lock().unwrap();
                 let mut db = draw_buffer.lock().unwrap();
                 if data.textures.len() != db.len() {
                     // Oh no, all textures haven't been loaded yet
                     thread::sleep(Duration::from_millis(10));
                     continue;
                 }
-                for i in 0..data.textures.len() {
-                    if data.textures[i].rect != *db[i].1 {
-                        db[i].1 = Arc::new(data.textures[i].rect.clone());
+                for draw in db.iter_mut() {
+                    let texture = &data.textures[draw.id];
+                    if texture.rect != *draw.rect {
+                        draw.rect = Arc::new(texture.rect.clone());
+                    }
+                    if texture.style != *draw.style {
+                        draw.style = Arc::new(texture.style.clone());
                     }
                 }
+                render::vk::sort_draws(&mut db, &data.settings.y_sorted_layers);
+                let mut vk = vk.lock().unwrap();
+                let (reupload, memory) = vk.update_residency(&mut db);
+                for id in reupload {
+                    img_send.send(upload(id, &data.textures[id])).unwrap();
+                }
+                data.sprite_memory = memory;
+                vk.update_tilemaps(&data.tilemaps, default_sampling, &wait_buffer);
+                vk.update_parallax(&data.parallax, default_sampling, &wait_buffer);
+                vk.update_particles(&data.emitters, default_sampling, &wait_buffer);
+                vk.update_materials(&data.materials, default_sampling, &wait_buffer);
+                vk.update_post_effects(&data.post_effects, &wait_buffer);
+                vk.update_lighting(
+                    &data.lighting,
+                    &data.textures,
+                    default_sampling,
+                    &wait_buffer,
+                );
+                vk.update_render_textures(&data.render_textures, default_sampling);
+                let mut cameras = vec![data.camera.clone()];
+                cameras.extend(data.cameras.iter().cloned());
+                let mut commands = data.canvas.clone();
+                let captures = data.capture.paths();
                 drop(data);
+                if let Some(ref font) = fps_font {
+                    commands.push(canvas::Command::Text {
+                        font: font.clone(),
+                        text: format!("fps: {}", frames),
+                        x: 4.0,
+                        y: 4.0,
+                        style: text::TextStyle::new(16.0),
+                        layer: i32::max_value(),
+                        space: canvas::Space::Screen,
+                    });
+                }
                 drop(db);
 
-                let mut vk = vk.lock().unwrap();
-                let res = vk.present(
+                let res = match vk.present(
                     vk_previous_frame_end,
                     draw_buffer.clone(),
                     wait_buffer.clone(),
-                );
-                fps.tick_and_display();
+                    &cameras,
+                    &commands,
+                    captures.clone(),
+                ) {
+                    Ok(res) => res,
+                    Err(e) => return stop(e),
+                };
+                match fps_font {
+                    Some(_) => frames = fps.tick(),
+                    None => fps.tick_and_display(),
+                }
                 vk_previous_frame_end = res.0;
-                if res.1 {
-                    let dims: (u32, u32) = window
-                        .get_inner_size()
-                        .unwrap()
-                        .to_physical(window.get_hidpi_factor())
-                        .into();
-                    dbg!(dims);
-                    vk.update_swapchain([dims.0, dims.1]);
+                recreate = res.1 == render::vk::Frame::OutOfDate;
+                if res.1 == render::vk::Frame::Presented && !captures.is_empty() {
+                    data_backend.lock().unwrap().capture.frame_saved(&captures);
                 }
             }
         });
@@ -178,6 +630,18 @@ impl Game {
                 event: WindowEvent::CloseRequested,
                 ..
             } => winit::ControlFlow::Break,
+            Event::Awakened if stopped_event.load(Ordering::SeqCst) => winit::ControlFlow::Break,
+            Event::WindowEvent {
+                event: WindowEvent::Resized(_),
+                ..
+            }
+            | Event::WindowEvent {
+                event: WindowEvent::HiDpiFactorChanged(_),
+                ..
+            } => {
+                resized_event.store(true, Ordering::SeqCst);
+                winit::ControlFlow::Continue
+            }
             _ => winit::ControlFlow::Continue,
         });
     }
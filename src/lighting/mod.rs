@@ -0,0 +1,240 @@
+// Dynamic 2D lights. With lighting enabled the world is only as bright as the ambient color
+// and every light adds its color around it. Sprites connected with a normal map are shaded by
+// the direction light comes from, and occluders cast shadows. Canvas drawing in screen space
+// isn't lit.
+
+use crate::graphics::Color;
+use crate::tilemap::TileMap;
+
+use std::collections::HashMap;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum LightKind {
+    // Shines in every direction
+    Point,
+    // Shines in a cone angle radians wide around direction, in radians clockwise from the
+    // positive x axis. The edges of the cone fade out over softness radians.
+    Spot {
+        direction: f32,
+        angle: f32,
+        softness: f32,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Shadows {
+    None,
+    Hard,
+    // Penumbras as cast by a round light source size world units wide
+    Soft(f32),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Light {
+    pub position_x: f32,
+    pub position_y: f32,
+    // Nothing further away than radius is lit
+    pub radius: f32,
+    pub color: Color,
+    pub intensity: f32,
+    // How the light fades towards radius, 1.0 is linear and higher values fade faster
+    pub falloff: f32,
+    // How far above the world the light hangs, in world units. Lower lights bring out more
+    // relief in normal maps but also light flat sprites less far away from them.
+    pub height: f32,
+    pub kind: LightKind,
+    // How the light is blocked by the occluders in Lighting::occluders
+    pub shadows: Shadows,
+    pub enabled: bool,
+}
+
+impl Light {
+    pub fn point(x: f32, y: f32, radius: f32) -> Self {
+        Light {
+            position_x: x,
+            position_y: y,
+            radius: radius,
+            color: Color::WHITE,
+            intensity: 1.0,
+            falloff: 2.0,
+            height: radius / 2.0,
+            kind: LightKind::Point,
+            shadows: Shadows::Hard,
+            enabled: true,
+        }
+    }
+
+    pub fn spot(x: f32, y: f32, radius: f32, direction: f32, angle: f32) -> Self {
+        Light {
+            kind: LightKind::Spot {
+                direction: direction,
+                angle: angle,
+                softness: angle / 4.0,
+            },
+            ..Light::point(x, y, radius)
+        }
+    }
+
+    pub fn with_color(mut self, color: Color) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn with_intensity(mut self, intensity: f32) -> Self {
+        self.intensity = intensity;
+        self
+    }
+
+    pub fn with_falloff(mut self, falloff: f32) -> Self {
+        self.falloff = falloff;
+        self
+    }
+
+    pub fn with_height(mut self, height: f32) -> Self {
+        self.height = height;
+        self
+    }
+
+    pub fn with_shadows(mut self, shadows: Shadows) -> Self {
+        self.shadows = shadows;
+        self
+    }
+
+    pub fn at(mut self, x: f32, y: f32) -> Self {
+        self.position_x = x;
+        self.position_y = y;
+        self
+    }
+
+    // World space bounds [x, y, width, height] of what the light can reach.
+    pub(crate) fn bounds(&self) -> [f32; 4] {
+        [
+            self.position_x - self.radius,
+            self.position_y - self.radius,
+            self.radius * 2.0,
+            self.radius * 2.0,
+        ]
+    }
+}
+
+// A polygon that blocks light, with its points in world units in either winding order. The
+// occluder itself stays lit, its shadow starts at the edges facing away from the light.
+// Concave polygons work but their soft shadows come out darker where edges overlap.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Occluder {
+    pub points: Vec<(f32, f32)>,
+    pub enabled: bool,
+}
+
+impl Occluder {
+    pub fn polygon(points: Vec<(f32, f32)>) -> Self {
+        Occluder {
+            points: points,
+            enabled: true,
+        }
+    }
+
+    pub fn rect(x: f32, y: f32, width: f32, height: f32) -> Self {
+        Occluder::polygon(vec![
+            (x, y),
+            (x + width, y),
+            (x + width, y + height),
+            (x, y + height),
+        ])
+    }
+
+    // Occluders covering every cell of a tile layer that holds a tile, e.g. a layer of walls.
+    // Neighbouring cells are merged into as few rectangles as possible.
+    pub fn from_tiles(map: &TileMap, layer: usize) -> Vec<Occluder> {
+        let (tw, th) = (map.tile_width, map.tile_height);
+        let mut occluders = Vec::new();
+        // Rectangles still growing downwards, by their first and last column: (first row, rows)
+        let mut open: HashMap<(u32, u32), (u32, u32)> = HashMap::new();
+
+        let mut close = |(x0, x1): (u32, u32), (y, rows): (u32, u32)| {
+            occluders.push(Occluder::rect(
+                map.position_x + x0 as f32 * tw,
+                map.position_y + y as f32 * th,
+                (x1 - x0 + 1) as f32 * tw,
+                rows as f32 * th,
+            ))
+        };
+        for y in 0..map.height {
+            let mut runs = Vec::new();
+            let mut x = 0;
+            while x < map.width {
+                if map.tile(layer, x, y).is_none() {
+                    x += 1;
+                    continue;
+                }
+                let start = x;
+                while x + 1 < map.width && map.tile(layer, x + 1, y).is_some() {
+                    x += 1;
+                }
+                runs.push((start, x));
+                x += 1;
+            }
+
+            let mut next = HashMap::new();
+            for run in runs {
+                match open.remove(&run) {
+                    Some((first, rows)) => next.insert(run, (first, rows + 1)),
+                    None => next.insert(run, (y, 1)),
+                };
+            }
+            for (run, rect) in open.drain() {
+                close(run, rect);
+            }
+            open = next;
+        }
+        for (run, rect) in open.drain() {
+            close(run, rect);
+        }
+        occluders
+    }
+
+    // World space bounds [x, y, width, height] of the polygon.
+    pub(crate) fn bounds(&self) -> [f32; 4] {
+        let min_x = self
+            .points
+            .iter()
+            .map(|p| p.0)
+            .fold(std::f32::MAX, f32::min);
+        let min_y = self
+            .points
+            .iter()
+            .map(|p| p.1)
+            .fold(std::f32::MAX, f32::min);
+        let max_x = self
+            .points
+            .iter()
+            .map(|p| p.0)
+            .fold(std::f32::MIN, f32::max);
+        let max_y = self
+            .points
+            .iter()
+            .map(|p| p.1)
+            .fold(std::f32::MIN, f32::max);
+        [min_x, min_y, max_x - min_x, max_y - min_y]
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Lighting {
+    pub enabled: bool,
+    // How bright the world is where no light reaches, black for complete darkness
+    pub ambient: Color,
+    pub lights: Vec<Light>,
+    pub occluders: Vec<Occluder>,
+}
+
+impl Lighting {
+    pub fn new() -> Self {
+        Lighting {
+            enabled: false,
+            ambient: Color::WHITE,
+            lights: Vec::new(),
+            occluders: Vec::new(),
+        }
+    }
+}
@@ -0,0 +1,234 @@
+// Sprites drawn with a fragment shader written by the game, e.g. for water, dissolve or outline
+// effects. The GLSL is compiled with shaderc when the material is added to the game.
+
+use crate::graphics::Sampling;
+
+use std::sync::Arc;
+
+// Extra textures a material can sample besides the sprite's own.
+pub const MAX_TEXTURES: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct MaterialId(pub(crate) usize);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Param {
+    Float(f32),
+    Vec2([f32; 2]),
+    Vec4([f32; 4]),
+}
+
+impl Param {
+    fn glsl_type(&self) -> &'static str {
+        match *self {
+            Param::Float(_) => "float",
+            Param::Vec2(_) => "vec2",
+            Param::Vec4(_) => "vec4",
+        }
+    }
+
+    // Every parameter takes up a vec4 in the uniform block.
+    pub(crate) fn to_array(&self) -> [f32; 4] {
+        match *self {
+            Param::Float(x) => [x, 0.0, 0.0, 0.0],
+            Param::Vec2([x, y]) => [x, y, 0.0, 0.0],
+            Param::Vec4(v) => v,
+        }
+    }
+}
+
+pub(crate) struct MaterialTexture {
+    pub name: String,
+    pub pixels: Arc<Vec<u8>>,
+    pub dimensions: (u32, u32),
+    pub sampling: Option<Sampling>,
+}
+
+// A fragment shader and its parameters. The source is the body of a GLSL 450 shader, the
+// engine declares everything the shader can use before it:
+//
+//     in vec2 tex_coords;       texture coordinates of the sprite
+//     in vec4 color;            the sprite's tint
+//     in float premultiplied;   1.0 when the sprite's texture has premultiplied alpha
+//     out vec4 f_color;         has to be premultiplied, see premultiply
+//     uniform sampler2D tex;    the sprite's texture
+//     float time;               seconds since the game started
+//     vec4 premultiply(vec4 c); turns a texture color times tint into the output color
+//
+// as well as every parameter and texture by its name. The plain sprite shader is
+//
+//     void main() {
+//         f_color = premultiply(texture(tex, tex_coords) * color);
+//     }
+pub struct Material {
+    source: String,
+    params: Vec<(String, Param)>,
+    pub(crate) textures: Vec<MaterialTexture>,
+    pub(crate) spirv: Option<Arc<Vec<u32>>>,
+}
+
+impl Material {
+    pub fn new(source: &str) -> Self {
+        Material {
+            source: source.to_string(),
+            params: Vec::new(),
+            textures: Vec::new(),
+            spirv: None,
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, String> {
+        let source =
+            std::fs::read_to_string(path).map_err(|e| format!("Could not open {}: {}", path, e))?;
+        Ok(Material::new(&source))
+    }
+
+    pub fn with_float(self, name: &str, value: f32) -> Self {
+        self.with_param(name, Param::Float(value))
+    }
+
+    pub fn with_vec2(self, name: &str, value: [f32; 2]) -> Self {
+        self.with_param(name, Param::Vec2(value))
+    }
+
+    pub fn with_vec4(self, name: &str, value: [f32; 4]) -> Self {
+        self.with_param(name, Param::Vec4(value))
+    }
+
+    fn with_param(mut self, name: &str, value: Param) -> Self {
+        match self.params.iter_mut().find(|p| p.0 == name) {
+            Some(param) => param.1 = value,
+            None => self.params.push((name.to_string(), value)),
+        }
+        self
+    }
+
+    // Adds a texture the shader samples as `uniform sampler2D name`.
+    pub fn with_texture(self, name: &str, path: &str) -> Result<Self, String> {
+        let img = image::open(path).map_err(|e| format!("Could not open {}: {}", path, e))?;
+        let img = img.to_rgba();
+        self.with_pixels(name, img.dimensions(), img.into_raw())
+    }
+
+    // Like with_texture, from RGBA8 pixels.
+    pub fn with_pixels(
+        mut self,
+        name: &str,
+        dimensions: (u32, u32),
+        pixels: Vec<u8>,
+    ) -> Result<Self, String> {
+        if self.textures.len() == MAX_TEXTURES {
+            return Err(format!(
+                "A material can't have more than {} textures",
+                MAX_TEXTURES
+            ));
+        }
+        self.textures.push(MaterialTexture {
+            name: name.to_string(),
+            pixels: Arc::new(pixels),
+            dimensions: dimensions,
+            sampling: None,
+        });
+        Ok(self)
+    }
+
+    // Samples the texture added last with its own filter and wrap mode instead of the game's
+    // defaults.
+    pub fn with_sampling(mut self, sampling: Sampling) -> Self {
+        if let Some(texture) = self.textures.last_mut() {
+            texture.sampling = Some(sampling);
+        }
+        self
+    }
+
+    // Changes a parameter of a material that's running. Names the material wasn't created with
+    // and values of a different type are ignored, the shader can't see them.
+    pub fn set(&mut self, name: &str, value: Param) {
+        if let Some(param) = self.params.iter_mut().find(|p| p.0 == name) {
+            if param.1.glsl_type() == value.glsl_type() {
+                param.1 = value;
+            }
+        }
+    }
+
+    pub fn set_float(&mut self, name: &str, value: f32) {
+        self.set(name, Param::Float(value));
+    }
+
+    pub fn set_vec2(&mut self, name: &str, value: [f32; 2]) {
+        self.set(name, Param::Vec2(value));
+    }
+
+    pub fn set_vec4(&mut self, name: &str, value: [f32; 4]) {
+        self.set(name, Param::Vec4(value));
+    }
+
+    pub fn get(&self, name: &str) -> Option<Param> {
+        self.params.iter().find(|p| p.0 == name).map(|p| p.1)
+    }
+
+    pub(crate) fn params(&self) -> impl Iterator<Item = &Param> {
+        self.params.iter().map(|p| &p.1)
+    }
+
+    pub(crate) fn compile(&mut self) -> Result<(), String> {
+        let mut compiler = shaderc::Compiler::new().ok_or("Could not start the shader compiler")?;
+        let source = format!("{}\n#line 1\n{}", self.header(), self.source);
+        let spirv = compiler
+            .compile_into_spirv(
+                &source,
+                shaderc::ShaderKind::Fragment,
+                "material",
+                "main",
+                None,
+            )
+            .map_err(|e| format!("Could not compile material: {}", e))?;
+        self.spirv = Some(Arc::new(spirv.as_binary().to_vec()));
+        Ok(())
+    }
+
+    // Declarations in front of the game's source. They have to match the sprite vertex shader's
+    // outputs and render::material::MaterialLayout.
+    fn header(&self) -> String {
+        let mut header = String::from(
+            "#version 450
+layout(location = 0) in vec2 tex_coords;
+layout(location = 1) in vec4 color;
+layout(location = 2) in float premultiplied;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D tex;
+layout(set = 2, binding = 0) uniform Material {
+    layout(offset = 0) float time;
+",
+        );
+        for (i, (name, param)) in self.params.iter().enumerate() {
+            header.push_str(&format!(
+                "    layout(offset = {}) {} {};\n",
+                (i + 1) * 16,
+                param.glsl_type(),
+                name
+            ));
+        }
+        header.push_str("};\n");
+        for i in 0..MAX_TEXTURES {
+            let name = match self.textures.get(i) {
+                Some(texture) => texture.name.clone(),
+                None => format!("unused_texture_{}", i),
+            };
+            header.push_str(&format!(
+                "layout(set = 2, binding = {}) uniform sampler2D {};\n",
+                i + 1,
+                name
+            ));
+        }
+        header.push_str(
+            "
+vec4 premultiply(vec4 c) {
+    return vec4(c.rgb * mix(c.a, 1.0, premultiplied), c.a);
+}
+",
+        );
+        header
+    }
+}
@@ -0,0 +1,151 @@
+// Backgrounds that scroll slower (or faster) than the world as the camera moves, drawn with a
+// single quad covering the view and a repeating texture.
+
+use crate::graphics::{Color, Sampling};
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// Tells layers apart in the renderer's texture cache.
+static NEXT_LAYER_ID: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Clone)]
+pub struct ParallaxLayer {
+    id: usize,
+    pub(crate) pixels: Arc<Vec<u8>>,
+    pub(crate) dimensions: (u32, u32),
+    // Draw layer, see SpriteStyle::layer. Backgrounds usually go below everything else
+    pub layer: i32,
+    // How much the layer moves with the camera: 0.0 stays fixed on screen, 1.0 moves with the
+    // world, values in between look further away
+    pub factor_x: f32,
+    pub factor_y: f32,
+    // Automatic scrolling in world units per second, e.g. for clouds
+    pub speed_x: f32,
+    pub speed_y: f32,
+    // Whether the texture repeats along each axis. A layer that doesn't is drawn once
+    pub repeat_x: bool,
+    pub repeat_y: bool,
+    // World position of the texture's top-left corner when the camera is at the origin
+    pub offset_x: f32,
+    pub offset_y: f32,
+    // Size of a texture pixel in world units
+    pub scale: f32,
+    pub tint: Color,
+    // None uses the game's default sampling. Clamp is always replaced by Repeat
+    pub sampling: Option<Sampling>,
+}
+
+impl ParallaxLayer {
+    pub fn load(path: &str) -> Result<ParallaxLayer, String> {
+        let img = image::open(path).map_err(|e| format!("Could not open {}: {}", path, e))?;
+        let img = img.to_rgba();
+        Ok(ParallaxLayer::from_pixels(img.dimensions(), img.into_raw()))
+    }
+
+    // Layer from RGBA8 pixels.
+    pub fn from_pixels(dimensions: (u32, u32), pixels: Vec<u8>) -> ParallaxLayer {
+        ParallaxLayer {
+            id: NEXT_LAYER_ID.fetch_add(1, Ordering::Relaxed),
+            pixels: Arc::new(pixels),
+            dimensions: dimensions,
+            layer: 0,
+            factor_x: 0.5,
+            factor_y: 0.5,
+            speed_x: 0.0,
+            speed_y: 0.0,
+            repeat_x: true,
+            repeat_y: false,
+            offset_x: 0.0,
+            offset_y: 0.0,
+            scale: 1.0,
+            tint: Color::WHITE,
+            sampling: None,
+        }
+    }
+
+    pub(crate) fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn on_layer(mut self, layer: i32) -> Self {
+        self.layer = layer;
+        self
+    }
+
+    pub fn with_factor(mut self, x: f32, y: f32) -> Self {
+        self.factor_x = x;
+        self.factor_y = y;
+        self
+    }
+
+    pub fn scrolling(mut self, x: f32, y: f32) -> Self {
+        self.speed_x = x;
+        self.speed_y = y;
+        self
+    }
+
+    pub fn repeat(mut self, horizontal: bool, vertical: bool) -> Self {
+        self.repeat_x = horizontal;
+        self.repeat_y = vertical;
+        self
+    }
+
+    pub fn at(mut self, x: f32, y: f32) -> Self {
+        self.offset_x = x;
+        self.offset_y = y;
+        self
+    }
+
+    pub fn with_scale(mut self, scale: f32) -> Self {
+        self.scale = scale;
+        self
+    }
+
+    pub fn with_tint(mut self, tint: Color) -> Self {
+        self.tint = tint;
+        self
+    }
+
+    pub fn with_sampling(mut self, sampling: Sampling) -> Self {
+        self.sampling = Some(sampling);
+        self
+    }
+
+    // World space [x, y, width, height] of the layer's quad and the texture coordinates
+    // [u0, v0, u1, v1] of its corners, for a camera at camera_x, camera_y seeing view.
+    pub(crate) fn quad(
+        &self,
+        camera_x: f32,
+        camera_y: f32,
+        view: [f32; 4],
+        seconds: f32,
+    ) -> ([f32; 4], [f32; 4]) {
+        let origin_x = self.offset_x + camera_x * (1.0 - self.factor_x) + self.speed_x * seconds;
+        let origin_y = self.offset_y + camera_y * (1.0 - self.factor_y) + self.speed_y * seconds;
+        let width = self.dimensions.0 as f32 * self.scale;
+        let height = self.dimensions.1 as f32 * self.scale;
+
+        let (x, w, u0, u1) = span(origin_x, width, self.repeat_x, view[0], view[2]);
+        let (y, h, v0, v1) = span(origin_y, height, self.repeat_y, view[1], view[3]);
+        ([x, y, w, h], [u0, v0, u1, v1])
+    }
+}
+
+// Start and length of the quad along one axis and the texture coordinates at its ends.
+// A repeating texture covers the whole view, otherwise it's drawn once at origin.
+fn span(
+    origin: f32,
+    size: f32,
+    repeat: bool,
+    view_start: f32,
+    view_size: f32,
+) -> (f32, f32, f32, f32) {
+    if repeat && size > 0.0 {
+        // Only the fraction matters to a repeating texture, and keeps the coordinates small
+        let start = ((view_start - origin) / size).rem_euclid(1.0);
+        (view_start, view_size, start, start + view_size / size)
+    } else {
+        (origin, size, 0.0, 1.0)
+    }
+}
@@ -0,0 +1,351 @@
+// Particle effects simulated on the CPU every update. All particles of an emitter share one
+// texture and are drawn with a single draw call.
+
+use crate::graphics::{BlendMode, Color, Sampling};
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// Tells emitters apart in the renderer's texture cache.
+static NEXT_EMITTER_ID: AtomicUsize = AtomicUsize::new(0);
+
+// Size of the soft round texture used by emitters without their own.
+const DOT_SIZE: u32 = 32;
+
+// A value changing over a particle's lifetime, with keys at times from 0.0 (born) to 1.0
+// (dead) and linear interpolation between them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Curve<T> {
+    keys: Vec<(f32, T)>,
+}
+
+pub trait Lerp: Copy {
+    fn lerp(self, other: Self, t: f32) -> Self;
+}
+
+impl Lerp for f32 {
+    fn lerp(self, other: f32, t: f32) -> f32 {
+        self + (other - self) * t
+    }
+}
+
+impl Lerp for Color {
+    fn lerp(self, other: Color, t: f32) -> Color {
+        Color::rgba(
+            self.r.lerp(other.r, t),
+            self.g.lerp(other.g, t),
+            self.b.lerp(other.b, t),
+            self.a.lerp(other.a, t),
+        )
+    }
+}
+
+impl<T: Lerp> Curve<T> {
+    pub fn constant(value: T) -> Self {
+        Curve {
+            keys: vec![(0.0, value)],
+        }
+    }
+
+    pub fn linear(from: T, to: T) -> Self {
+        Curve {
+            keys: vec![(0.0, from), (1.0, to)],
+        }
+    }
+
+    // Adds a key, keys may be added in any order.
+    pub fn key(mut self, time: f32, value: T) -> Self {
+        let index = self
+            .keys
+            .iter()
+            .position(|k| k.0 > time)
+            .unwrap_or(self.keys.len());
+        self.keys.insert(index, (time, value));
+        self
+    }
+
+    pub fn sample(&self, time: f32) -> T {
+        let next = self.keys.iter().position(|k| k.0 > time);
+        match next {
+            Some(0) => self.keys[0].1,
+            Some(i) => {
+                let (a, b) = (self.keys[i - 1], self.keys[i]);
+                a.1.lerp(b.1, (time - a.0) / (b.0 - a.0))
+            }
+            None => self.keys[self.keys.len() - 1].1,
+        }
+    }
+}
+
+// Particles emitted all at once, time seconds after the emitter started.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Burst {
+    pub time: f32,
+    pub count: u32,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EmitterConfig {
+    // Particles per second while the emitter runs
+    pub rate: f32,
+    pub bursts: Vec<Burst>,
+    // Seconds the emitter runs for after it starts, None runs until stopped
+    pub duration: Option<f32>,
+    // Seconds, picked at random between min and max for every particle
+    pub lifetime: (f32, f32),
+    // World units per second, picked at random between min and max
+    pub speed: (f32, f32),
+    // Radians, 0.0 points right and positive angles turn clockwise as y points down
+    pub direction: f32,
+    // Width of the cone particles are emitted in around direction, in radians
+    pub spread: f32,
+    // Acceleration in world units per second squared
+    pub gravity: (f32, f32),
+    // Fraction of the velocity lost per second
+    pub drag: f32,
+    pub color: Curve<Color>,
+    // Width and height of particles in world units
+    pub size: Curve<f32>,
+    pub blend: BlendMode,
+    // Draw layer, see SpriteStyle::layer
+    pub layer: i32,
+    // Particles beyond this aren't emitted until others die
+    pub max_particles: usize,
+}
+
+impl Default for EmitterConfig {
+    fn default() -> Self {
+        EmitterConfig {
+            rate: 10.0,
+            bursts: Vec::new(),
+            duration: None,
+            lifetime: (1.0, 1.0),
+            speed: (50.0, 50.0),
+            direction: -std::f32::consts::FRAC_PI_2,
+            spread: std::f32::consts::FRAC_PI_4,
+            gravity: (0.0, 0.0),
+            drag: 0.0,
+            color: Curve::linear(Color::WHITE, Color::WHITE.with_alpha(0.0)),
+            size: Curve::constant(8.0),
+            blend: BlendMode::Alpha,
+            layer: 0,
+            max_particles: 1000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Particle {
+    pub x: f32,
+    pub y: f32,
+    pub velocity_x: f32,
+    pub velocity_y: f32,
+    pub age: f32,
+    pub lifetime: f32,
+}
+
+#[derive(Clone)]
+pub struct Emitter {
+    id: usize,
+    pub config: EmitterConfig,
+    pub(crate) pixels: Arc<Vec<u8>>,
+    pub(crate) dimensions: (u32, u32),
+    // Where new particles appear
+    pub position_x: f32,
+    pub position_y: f32,
+    // Offset from the center of the entity the emitter is attached to, see Game::attach_emitter
+    pub offset_x: f32,
+    pub offset_y: f32,
+    pub(crate) attached_to: Option<usize>,
+    // None uses the game's default sampling
+    pub sampling: Option<Sampling>,
+    particles: Vec<Particle>,
+    running: bool,
+    time: f32,
+    // Fraction of a particle left over from the previous update
+    pending: f32,
+    bursts_done: usize,
+    queued: u32,
+    seed: u32,
+}
+
+impl Emitter {
+    // Emitter drawing its particles as soft round dots, it starts running right away.
+    pub fn new(config: EmitterConfig) -> Self {
+        let id = NEXT_EMITTER_ID.fetch_add(1, Ordering::Relaxed);
+        Emitter {
+            id: id,
+            config: config,
+            pixels: Arc::new(dot(DOT_SIZE)),
+            dimensions: (DOT_SIZE, DOT_SIZE),
+            position_x: 0.0,
+            position_y: 0.0,
+            offset_x: 0.0,
+            offset_y: 0.0,
+            attached_to: None,
+            sampling: None,
+            particles: Vec::new(),
+            running: true,
+            time: 0.0,
+            pending: 0.0,
+            bursts_done: 0,
+            queued: 0,
+            seed: (id as u32).wrapping_mul(0x9E37_79B9) | 1,
+        }
+    }
+
+    pub fn with_texture(mut self, path: &str) -> Result<Self, String> {
+        let img = image::open(path).map_err(|e| format!("Could not open {}: {}", path, e))?;
+        let img = img.to_rgba();
+        self.dimensions = img.dimensions();
+        self.pixels = Arc::new(img.into_raw());
+        Ok(self)
+    }
+
+    pub fn at(mut self, x: f32, y: f32) -> Self {
+        self.position_x = x;
+        self.position_y = y;
+        self
+    }
+
+    pub fn with_offset(mut self, x: f32, y: f32) -> Self {
+        self.offset_x = x;
+        self.offset_y = y;
+        self
+    }
+
+    pub fn with_sampling(mut self, sampling: Sampling) -> Self {
+        self.sampling = Some(sampling);
+        self
+    }
+
+    pub(crate) fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn particles(&self) -> &[Particle] {
+        &self.particles
+    }
+
+    // World space [x, y, width, height] and color of every living particle.
+    pub(crate) fn quads(&self) -> impl Iterator<Item = ([f32; 4], Color)> + '_ {
+        self.particles.iter().map(move |p| {
+            let t = p.age / p.lifetime;
+            let size = self.config.size.sample(t);
+            (
+                [p.x - size / 2.0, p.y - size / 2.0, size, size],
+                self.config.color.sample(t),
+            )
+        })
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.running
+    }
+
+    // Restarts the emitter, including its timed bursts.
+    pub fn start(&mut self) {
+        self.running = true;
+        self.time = 0.0;
+        self.bursts_done = 0;
+    }
+
+    // Stops emitting, particles that are alive keep going until they die.
+    pub fn stop(&mut self) {
+        self.running = false;
+    }
+
+    // Emits count particles on the next update, whether the emitter is running or not.
+    pub fn burst(&mut self, count: u32) {
+        self.queued += count;
+    }
+
+    // Moves the simulation forward by dt seconds.
+    pub fn update(&mut self, dt: f32) {
+        let config = &self.config;
+        let damping = (1.0 - config.drag * dt).max(0.0);
+        for p in self.particles.iter_mut() {
+            p.age += dt;
+            p.velocity_x = (p.velocity_x + config.gravity.0 * dt) * damping;
+            p.velocity_y = (p.velocity_y + config.gravity.1 * dt) * damping;
+            p.x += p.velocity_x * dt;
+            p.y += p.velocity_y * dt;
+        }
+        self.particles.retain(|p| p.age < p.lifetime);
+
+        let mut count = self.queued;
+        self.queued = 0;
+        if self.running {
+            self.time += dt;
+            self.pending += self.config.rate * dt;
+            count += self.pending as u32;
+            self.pending = self.pending.fract();
+
+            while let Some(burst) = self.config.bursts.get(self.bursts_done) {
+                if burst.time > self.time {
+                    break;
+                }
+                count += burst.count;
+                self.bursts_done += 1;
+            }
+            if let Some(duration) = self.config.duration {
+                if self.time >= duration {
+                    self.running = false;
+                }
+            }
+        }
+
+        let room = self
+            .config
+            .max_particles
+            .saturating_sub(self.particles.len());
+        for _ in 0..(count as usize).min(room) {
+            self.spawn();
+        }
+    }
+
+    fn spawn(&mut self) {
+        let lifetime = self.random_between(self.config.lifetime);
+        // Would be gone before it's drawn, and has no point in its lifetime to sample
+        if lifetime <= 0.0 {
+            return;
+        }
+        let speed = self.random_between(self.config.speed);
+        let angle = self.config.direction + (self.random() - 0.5) * self.config.spread;
+        self.particles.push(Particle {
+            x: self.position_x,
+            y: self.position_y,
+            velocity_x: angle.cos() * speed,
+            velocity_y: angle.sin() * speed,
+            age: 0.0,
+            lifetime: lifetime,
+        });
+    }
+
+    // Xorshift, good enough to scatter particles without pulling in a dependency
+    fn random(&mut self) -> f32 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        (self.seed >> 8) as f32 / (1 << 24) as f32
+    }
+
+    fn random_between(&mut self, range: (f32, f32)) -> f32 {
+        range.0 + (range.1 - range.0) * self.random()
+    }
+}
+
+// White RGBA8 dot fading out towards its edge.
+fn dot(size: u32) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity((size * size * 4) as usize);
+    let center = size as f32 / 2.0;
+    for y in 0..size {
+        for x in 0..size {
+            let (dx, dy) = (x as f32 + 0.5 - center, y as f32 + 0.5 - center);
+            let d = (dx * dx + dy * dy).sqrt() / center;
+            let alpha = (1.0 - d).max(0.0);
+            pixels.extend_from_slice(&[255, 255, 255, (alpha * alpha * 255.0) as u8]);
+        }
+    }
+    pixels
+}
@@ -0,0 +1,139 @@
+// Full-screen passes run over the finished frame, in order, before it's presented.
+
+use crate::material::MaterialId;
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// Tells lookup tables apart in the renderer's texture cache.
+static NEXT_LUT_ID: AtomicUsize = AtomicUsize::new(0);
+
+#[derive(Debug, Clone)]
+pub enum Effect {
+    // Makes bright parts of the frame glow. Colors brighter than threshold (0..1) are blurred
+    // by radius pixels and added back with intensity
+    Bloom {
+        threshold: f32,
+        intensity: f32,
+        radius: f32,
+    },
+    // Darkens the edges. radius is where darkening starts, as a fraction of the distance from
+    // the center to a corner, and softness how far it takes to reach intensity
+    Vignette {
+        intensity: f32,
+        radius: f32,
+        softness: f32,
+    },
+    // Maps every color through a lookup table, intensity mixes between the original and
+    // graded colors
+    ColorGrading {
+        lut: Lut,
+        intensity: f32,
+    },
+    // Scanlines over every other pixel row and a screen bulging out by curvature
+    Crt {
+        scanlines: f32,
+        curvature: f32,
+    },
+    // Gaussian blur over about radius pixels
+    Blur {
+        radius: f32,
+    },
+    // A material drawn over the whole frame, with the frame as its tex
+    Custom(MaterialId),
+}
+
+impl Effect {
+    pub fn bloom() -> Self {
+        Effect::Bloom {
+            threshold: 0.7,
+            intensity: 1.0,
+            radius: 8.0,
+        }
+    }
+
+    pub fn vignette() -> Self {
+        Effect::Vignette {
+            intensity: 0.5,
+            radius: 0.75,
+            softness: 0.45,
+        }
+    }
+
+    pub fn color_grading(lut: Lut) -> Self {
+        Effect::ColorGrading {
+            lut: lut,
+            intensity: 1.0,
+        }
+    }
+
+    pub fn crt() -> Self {
+        Effect::Crt {
+            scanlines: 0.3,
+            curvature: 0.05,
+        }
+    }
+
+    pub fn blur(radius: f32) -> Self {
+        Effect::Blur { radius: radius }
+    }
+}
+
+// A color lookup table laid out as size slices of size x size pixels side by side, red
+// growing to the right and green downwards within a slice and blue from slice to slice. A
+// 256x16 strip is the common layout.
+#[derive(Debug, Clone)]
+pub struct Lut {
+    id: usize,
+    pub(crate) pixels: Arc<Vec<u8>>,
+    pub(crate) size: u32,
+}
+
+impl Lut {
+    pub fn load(path: &str) -> Result<Lut, String> {
+        let img = image::open(path).map_err(|e| format!("Could not open {}: {}", path, e))?;
+        let img = img.to_rgba();
+        Lut::from_pixels(img.dimensions(), img.into_raw())
+    }
+
+    // Lookup table from RGBA8 pixels.
+    pub fn from_pixels(dimensions: (u32, u32), pixels: Vec<u8>) -> Result<Lut, String> {
+        let size = dimensions.1;
+        if size < 2 || dimensions.0 != size * size {
+            return Err(format!(
+                "A lookup table has to be size * size pixels wide and size pixels high, not {}x{}",
+                dimensions.0, dimensions.1
+            ));
+        }
+        Ok(Lut {
+            id: NEXT_LUT_ID.fetch_add(1, Ordering::Relaxed),
+            pixels: Arc::new(pixels),
+            size: size,
+        })
+    }
+
+    // Table that leaves colors as they are, to start grading from in an image editor. Tables
+    // need at least 2 entries per channel, smaller sizes are raised to 2.
+    pub fn identity(size: u32) -> Lut {
+        let size = size.max(2);
+        let max = (size - 1) as f32;
+        let mut pixels = Vec::with_capacity((size * size * size * 4) as usize);
+        for g in 0..size {
+            for b in 0..size {
+                for r in 0..size {
+                    let channel = |c: u32| (c as f32 / max * 255.0).round() as u8;
+                    pixels.extend_from_slice(&[channel(r), channel(g), channel(b), 255]);
+                }
+            }
+        }
+        Lut::from_pixels((size * size, size), pixels).unwrap()
+    }
+
+    pub(crate) fn id(&self) -> usize {
+        self.id
+    }
+
+    pub(crate) fn dimensions(&self) -> (u32, u32) {
+        (self.size * self.size, self.size)
+    }
+}
@@ -0,0 +1,94 @@
+use std::sync::Arc;
+use std::thread;
+
+use vulkano::{
+    buffer::{BufferUsage, CpuAccessibleBuffer},
+    device::Device,
+    format::Format,
+    sync::{FenceSignalFuture, GpuFuture},
+};
+
+// Whether frames of a swapchain with this format can be saved, only 8 bit RGBA and BGRA are.
+pub fn can_save(format: Format) -> bool {
+    match format {
+        Format::R8G8B8A8Unorm
+        | Format::R8G8B8A8Srgb
+        | Format::B8G8R8A8Unorm
+        | Format::B8G8R8A8Srgb => true,
+        _ => false,
+    }
+}
+
+// A presented frame being copied out of the swapchain, see VkSession::present.
+pub struct FrameCopy {
+    pub buffer: Arc<CpuAccessibleBuffer<[u8]>>,
+    pub dimensions: [u32; 2],
+    pub format: Format,
+    pub paths: Vec<String>,
+}
+
+impl FrameCopy {
+    pub fn new(
+        device: Arc<Device>,
+        dimensions: [u32; 2],
+        format: Format,
+        paths: Vec<String>,
+    ) -> Self {
+        let size = dimensions[0] as usize * dimensions[1] as usize * 4;
+        let buffer = unsafe {
+            CpuAccessibleBuffer::uninitialized_array(
+                device,
+                size,
+                BufferUsage::transfer_destination(),
+            )
+            .unwrap()
+        };
+        FrameCopy {
+            buffer: buffer,
+            dimensions: dimensions,
+            format: format,
+            paths: paths,
+        }
+    }
+
+    // Waits for the frame's fence, then reads, encodes and writes the frame on a thread of its
+    // own so rendering goes on in the meantime.
+    pub fn save<F>(self, fence: Arc<FenceSignalFuture<F>>)
+    where
+        F: GpuFuture + Send + Sync + 'static,
+    {
+        let bgra = match self.format {
+            Format::B8G8R8A8Unorm | Format::B8G8R8A8Srgb => true,
+            _ => false,
+        };
+        thread::spawn(move || {
+            let read = fence
+                .wait(None)
+                .map_err(|e| e.to_string())
+                .and_then(|_| self.buffer.read().map_err(|e| e.to_string()));
+            let mut pixels = match read {
+                Ok(pixels) => pixels.to_vec(),
+                Err(e) => {
+                    eprintln!("Could not save {}: {}", self.paths.join(", "), e);
+                    return;
+                }
+            };
+            for pixel in pixels.chunks_mut(4) {
+                if bgra {
+                    pixel.swap(0, 2);
+                }
+                // The window isn't see-through, whatever alpha ended up in the swapchain
+                pixel[3] = 255;
+            }
+            let [w, h] = self.dimensions;
+            for path in &self.paths {
+                if let Some(parent) = std::path::Path::new(path).parent() {
+                    let _ = std::fs::create_dir_all(parent);
+                }
+                if let Err(e) = image::save_buffer(path, &pixels, w, h, image::ColorType::RGBA(8)) {
+                    eprintln!("Could not save {}: {}", path, e);
+                }
+            }
+        });
+    }
+}
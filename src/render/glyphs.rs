@@ -0,0 +1,242 @@
+use crate::render::vk::{SpritePipeline, Vertex};
+use crate::text::{Font, GlyphKey, TextStyle};
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use vulkano::{
+    buffer::CpuBufferPool,
+    command_buffer::AutoCommandBufferBuilder,
+    descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet},
+    device::Queue,
+    format::Format,
+    image::{Dimensions, ImageUsage, StorageImage},
+    sampler::Sampler,
+};
+
+const ATLAS_SIZE: u32 = 1024;
+const PADDING: u32 = 1;
+
+// Glyphs of every font, rasterized the first time they're drawn and packed into one texture
+// on shelves. When a frame's text doesn't fit the atlas is cleared and the frame is laid out
+// again, which packs only the glyphs that frame uses. The texture stays on the GPU, only the
+// part of it glyphs were added to is copied again.
+pub struct GlyphCache {
+    pixels: Vec<u8>,
+    regions: HashMap<GlyphKey, Option<[f32; 4]>>,
+    // Glyphs the frame being laid out uses
+    used: HashSet<GlyphKey>,
+    // Whether a glyph of the frame being laid out didn't fit
+    full: bool,
+    cursor: (u32, u32),
+    shelf_height: u32,
+    // Part of the atlas changed since the last upload, as [x0, y0, x1, y1]
+    dirty: Option<[u32; 4]>,
+    image: Option<Arc<StorageImage<Format>>>,
+    set: Option<Arc<DescriptorSet + Send + Sync>>,
+}
+
+impl GlyphCache {
+    pub fn new() -> Self {
+        GlyphCache {
+            pixels: vec![0; (ATLAS_SIZE * ATLAS_SIZE * 4) as usize],
+            regions: HashMap::new(),
+            used: HashSet::new(),
+            full: false,
+            cursor: (PADDING, PADDING),
+            shelf_height: 0,
+            dirty: None,
+            image: None,
+            set: None,
+        }
+    }
+
+    // Vertices of the glyph quads of every text drawn this frame, with the top-left corner of
+    // each text at its x, y. Glyphs that don't fit even in an atlas holding only this frame's
+    // glyphs are left out.
+    pub fn layout_frame(
+        &mut self,
+        texts: &[(&Font, &str, f32, f32, &TextStyle)],
+    ) -> Vec<Vec<Vertex>> {
+        self.used.clear();
+        self.full = false;
+        let vertices = self.layout_all(texts);
+        // Clearing only helps when the atlas holds glyphs this frame doesn't use
+        if !self.full || self.used.len() == self.regions.len() {
+            return vertices;
+        }
+        self.clear();
+        self.layout_all(texts)
+    }
+
+    fn layout_all(&mut self, texts: &[(&Font, &str, f32, f32, &TextStyle)]) -> Vec<Vec<Vertex>> {
+        texts
+            .iter()
+            .map(|&(font, text, x, y, style)| self.text_vertices(font, text, x, y, style))
+            .collect()
+    }
+
+    // Vertices of the text's glyph quads with the top-left corner of the text at x, y.
+    fn text_vertices(
+        &mut self,
+        font: &Font,
+        text: &str,
+        x: f32,
+        y: f32,
+        style: &TextStyle,
+    ) -> Vec<Vertex> {
+        let layout = font.layout(text, style);
+        let mut vertices = Vec::with_capacity(layout.glyphs.len() * 6);
+        for glyph in layout.glyphs {
+            if let Some(uv) = self.region(font, glyph.key) {
+                vertices.extend_from_slice(&Vertex::quad(
+                    x + glyph.x,
+                    y + glyph.y,
+                    glyph.width,
+                    glyph.height,
+                    uv,
+                    style.color,
+                ));
+            }
+        }
+        vertices
+    }
+
+    // Texture coordinates of a glyph, None for glyphs without pixels such as spaces and for
+    // glyphs that don't fit in the atlas.
+    fn region(&mut self, font: &Font, key: GlyphKey) -> Option<[f32; 4]> {
+        if let Some(region) = self.regions.get(&key) {
+            self.used.insert(key);
+            return *region;
+        }
+
+        let region = match font.rasterize(key) {
+            None => None,
+            Some((pixels, w, h)) => match self.insert(&pixels, w, h) {
+                Some(region) => Some(region),
+                None => {
+                    // Not cached, the glyph may fit once the atlas is cleared
+                    self.full = true;
+                    return None;
+                }
+            },
+        };
+        self.used.insert(key);
+        self.regions.insert(key, region);
+        region
+    }
+
+    fn insert(&mut self, pixels: &[u8], w: u32, h: u32) -> Option<[f32; 4]> {
+        if w + PADDING * 2 > ATLAS_SIZE || h + PADDING * 2 > ATLAS_SIZE {
+            return None;
+        }
+        if self.cursor.0 + w + PADDING > ATLAS_SIZE {
+            self.cursor = (PADDING, self.cursor.1 + self.shelf_height + PADDING);
+            self.shelf_height = 0;
+        }
+        if self.cursor.1 + h + PADDING > ATLAS_SIZE {
+            return None;
+        }
+
+        let (x, y) = self.cursor;
+        for row in 0..h {
+            let src = (row * w * 4) as usize;
+            let dst = (((y + row) * ATLAS_SIZE + x) * 4) as usize;
+            self.pixels[dst..dst + (w * 4) as usize]
+                .copy_from_slice(&pixels[src..src + (w * 4) as usize]);
+        }
+        self.cursor.0 += w + PADDING;
+        self.shelf_height = self.shelf_height.max(h);
+        self.dirty = Some(match self.dirty {
+            Some([x0, y0, x1, y1]) => [x0.min(x), y0.min(y), x1.max(x + w), y1.max(y + h)],
+            None => [x, y, x + w, y + h],
+        });
+
+        let size = ATLAS_SIZE as f32;
+        Some([
+            x as f32 / size,
+            y as f32 / size,
+            (x + w) as f32 / size,
+            (y + h) as f32 / size,
+        ])
+    }
+
+    fn clear(&mut self) {
+        for p in self.pixels.iter_mut() {
+            *p = 0;
+        }
+        self.regions.clear();
+        self.cursor = (PADDING, PADDING);
+        self.shelf_height = 0;
+        self.dirty = Some([0, 0, ATLAS_SIZE, ATLAS_SIZE]);
+    }
+
+    // The atlas texture, created the first time text is drawn. Its pixels are copied to it by
+    // record_upload.
+    pub fn descriptor_set(
+        &mut self,
+        pipeline: &SpritePipeline,
+        sampler: Arc<Sampler>,
+        queue: Arc<Queue>,
+    ) -> Arc<DescriptorSet + Send + Sync> {
+        if self.set.is_none() {
+            let image = StorageImage::with_usage(
+                queue.device().clone(),
+                Dimensions::Dim2d {
+                    width: ATLAS_SIZE,
+                    height: ATLAS_SIZE,
+                },
+                Format::R8G8B8A8Srgb,
+                ImageUsage {
+                    transfer_destination: true,
+                    sampled: true,
+                    ..ImageUsage::none()
+                },
+                Some(queue.family()),
+            )
+            .unwrap();
+            self.set = Some(Arc::new(
+                PersistentDescriptorSet::start(pipeline.clone(), 0)
+                    .add_sampled_image(image.clone(), sampler)
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+            ));
+            self.image = Some(image);
+            self.dirty = Some([0, 0, ATLAS_SIZE, ATLAS_SIZE]);
+        }
+        self.set.clone().unwrap()
+    }
+
+    // Records copying the part of the atlas glyphs were added to since the last call into cb,
+    // which has to be outside a render pass and before the frame's text is drawn. Once the atlas
+    // exists every frame writes to it, at least its always empty top-left pixel, so vulkano sees
+    // each frame using it exclusively and allows the next write while frames are in flight.
+    pub fn record_upload(
+        &mut self,
+        cb: AutoCommandBufferBuilder,
+        staging: &CpuBufferPool<u8>,
+    ) -> AutoCommandBufferBuilder {
+        let image = match self.image {
+            Some(ref image) => image.clone(),
+            None => return cb,
+        };
+        let [x0, y0, x1, y1] = self.dirty.take().unwrap_or([0, 0, 1, 1]);
+        let mut pixels = Vec::with_capacity(((x1 - x0) * (y1 - y0) * 4) as usize);
+        for y in y0..y1 {
+            let start = ((y * ATLAS_SIZE + x0) * 4) as usize;
+            pixels.extend_from_slice(&self.pixels[start..start + ((x1 - x0) * 4) as usize]);
+        }
+        let chunk = staging.chunk(pixels.into_iter()).unwrap();
+        cb.copy_buffer_to_image_dimensions(
+            chunk,
+            image,
+            [x0, y0, 0],
+            [x1 - x0, y1 - y0, 1],
+            0,
+            1,
+            0,
+        )
+        .unwrap()
+    }
+}
@@ -0,0 +1,528 @@
+use crate::entity::{Texture, Transform};
+use crate::graphics::{self, BlendMode, Color, Sampling};
+use crate::lighting::{Light, LightKind, Lighting, Occluder, Shadows};
+use crate::render::pipeline;
+use crate::render::sampler::SamplerCache;
+use crate::render::shader;
+use crate::render::target::RenderTarget;
+use crate::render::texture;
+use crate::render::vk::{
+    overlaps, viewport_state, Draw, ShapePipeline, ShapeVertex, SpritePipeline, Vertex, View,
+    WaitBuffer,
+};
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use vulkano::{
+    buffer::{BufferUsage, CpuAccessibleBuffer, CpuBufferPool},
+    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet},
+    device::{Device, Queue},
+    format::Format,
+    framebuffer::RenderPassAbstract,
+    image::{ImageViewAccess, ImmutableImage},
+    sampler::Sampler,
+};
+
+// Shadows of soft lights are cast from this many points around the light.
+const SOFT_SAMPLES: usize = 8;
+
+// Push constants of a light, the Params block of render::shader::light_fs.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct LightParams {
+    center: [f32; 4],
+    spot: [f32; 4],
+    target: [f32; 4],
+}
+
+// Push constants of the normal pass, the Orientation block of render::shader::normal_fs.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct Orientation {
+    axes: [f32; 4],
+}
+
+impl Orientation {
+    // Normal maps point y up, so the sprite's clockwise rotation turns them the other way
+    fn of(t: &Transform) -> Self {
+        let fx = if t.flip_x { -1.0 } else { 1.0 } * t.scale_x.signum();
+        let fy = if t.flip_y { -1.0 } else { 1.0 } * t.scale_y.signum();
+        let (sin, cos) = t.rotation.sin_cos();
+        Orientation {
+            axes: [fx * cos, -fx * sin, fy * sin, fy * cos],
+        }
+    }
+}
+
+// Normals of the world, the light falling on it and the shadows of one light, all the size of
+// the scene.
+struct Targets {
+    dimensions: [u32; 2],
+    normals: RenderTarget,
+    // Lights with shadows are added by copying the lights so far to the other buffer
+    light: [RenderTarget; 2],
+    shadows: RenderTarget,
+}
+
+// Draws the game's lights into a light buffer before the scene, which is then multiplied over
+// the world once it's drawn, see lighting::Lighting.
+pub struct LightCache {
+    lighting: Lighting,
+    // Descriptor sets of the uploaded normal maps by texture id, sprites without one face the
+    // camera
+    normal_maps: HashMap<usize, Arc<DescriptorSet + Send + Sync>>,
+    flat: Option<Arc<DescriptorSet + Send + Sync>>,
+    // Shadows of lights that don't have any
+    unshadowed: Option<Arc<ImmutableImage<Format>>>,
+    targets: Option<Targets>,
+    // Which light buffer holds the finished lights
+    current: usize,
+    normal_pipeline: SpritePipeline,
+    light_pipeline: ShapePipeline,
+    shadow_pipeline: ShapePipeline,
+    copy: SpritePipeline,
+    composite: SpritePipeline,
+    sampler: Arc<Sampler>,
+    strip: Arc<CpuAccessibleBuffer<[Vertex]>>,
+}
+
+impl LightCache {
+    pub fn new(
+        device: Arc<Device>,
+        render_pass: &Arc<RenderPassAbstract + Send + Sync>,
+        samplers: &SamplerCache,
+    ) -> Self {
+        let copy = shader::blit_fs::Shader::load(device.clone()).unwrap();
+        LightCache {
+            lighting: Lighting::new(),
+            normal_maps: HashMap::new(),
+            flat: None,
+            unshadowed: None,
+            targets: None,
+            current: 0,
+            normal_pipeline: pipeline::normal_pipeline(device.clone(), render_pass),
+            light_pipeline: pipeline::light_pipeline(device.clone(), render_pass),
+            shadow_pipeline: pipeline::shape_pipeline(
+                device.clone(),
+                render_pass,
+                Some(BlendMode::Additive),
+            ),
+            copy: pipeline::post_pipeline(
+                device.clone(),
+                render_pass,
+                copy.main_entry_point(),
+                None,
+            ),
+            composite: pipeline::post_pipeline(
+                device.clone(),
+                render_pass,
+                copy.main_entry_point(),
+                Some(BlendMode::Multiply),
+            ),
+            sampler: samplers.get(Sampling {
+                filter: graphics::Filter::Nearest,
+                wrap: graphics::Wrap::Clamp,
+                ..Sampling::default()
+            }),
+            strip: CpuAccessibleBuffer::from_iter(
+                device.clone(),
+                BufferUsage::vertex_buffer(),
+                Vertex::fullscreen().iter().cloned(),
+            )
+            .unwrap(),
+        }
+    }
+
+    // Takes in the game's lights and uploads the normal maps of textures that have one.
+    pub fn update(
+        &mut self,
+        lighting: &Lighting,
+        textures: &[Texture],
+        samplers: &SamplerCache,
+        default_sampling: Sampling,
+        queue: Arc<Queue>,
+        staging: &CpuBufferPool<u8>,
+        wait_buffer: &WaitBuffer,
+    ) {
+        self.lighting = lighting.clone();
+        if !lighting.enabled {
+            return;
+        }
+
+        let pipeline = &self.normal_pipeline;
+        let normal_set = |pixels: &[u8], dimensions, sampling| {
+            let (image, future) =
+                texture::upload_linear(pixels, dimensions, queue.clone(), staging);
+            wait_buffer.lock().unwrap().push(future);
+            Arc::new(
+                PersistentDescriptorSet::start(pipeline.clone(), 2)
+                    .add_sampled_image(image, samplers.get(sampling))
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+            ) as Arc<DescriptorSet + Send + Sync>
+        };
+        if self.flat.is_none() {
+            self.flat = Some(normal_set(&[128, 128, 255, 255], (1, 1), default_sampling));
+        }
+        if self.unshadowed.is_none() {
+            let (image, future) =
+                texture::upload_linear(&[0, 0, 0, 255], (1, 1), queue.clone(), staging);
+            wait_buffer.lock().unwrap().push(future);
+            self.unshadowed = Some(image);
+        }
+        for (id, texture) in textures.iter().enumerate() {
+            if let Some((ref pixels, dimensions)) = texture.normal_map {
+                if !self.normal_maps.contains_key(&id) {
+                    let sampling = Sampling {
+                        mipmaps: false,
+                        ..texture.sampling.unwrap_or(default_sampling)
+                    };
+                    let set = normal_set(pixels, dimensions, sampling);
+                    self.normal_maps.insert(id, set);
+                }
+            }
+        }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.lighting.enabled
+    }
+
+    // (Re)creates the normal, light and shadow buffers for a scene of the given size.
+    pub fn prepare(
+        &mut self,
+        device: Arc<Device>,
+        render_pass: &Arc<RenderPassAbstract + Send + Sync>,
+        dimensions: [u32; 2],
+        format: Format,
+    ) {
+        let outdated = match self.targets {
+            Some(ref targets) => targets.dimensions != dimensions,
+            None => true,
+        };
+        if outdated {
+            let target = || RenderTarget::new(device.clone(), render_pass, dimensions, format);
+            self.targets = Some(Targets {
+                dimensions: dimensions,
+                normals: target(),
+                light: [target(), target()],
+                shadows: target(),
+            });
+        }
+    }
+
+    // Records the normal pass over every sprite and the light passes over every light in view.
+    // Lights without occluders in reach share one pass, every light with shadows takes a pass
+    // drawing its shadows and one adding it to the lights so far. Every pass draws once per
+    // camera, into that camera's viewport.
+    pub fn draw_lights(
+        &mut self,
+        mut cb: AutoCommandBufferBuilder,
+        draws: &[Draw],
+        views: &[View],
+        device: Arc<Device>,
+    ) -> AutoCommandBufferBuilder {
+        let targets = self.targets.as_ref().unwrap();
+        let flat = self.flat.as_ref().unwrap();
+
+        // Where nothing is drawn the world faces the camera
+        cb = cb
+            .begin_render_pass(
+                targets.normals.framebuffer.clone(),
+                false,
+                vec![[0.5, 0.5, 1.0, 1.0].into()],
+            )
+            .unwrap();
+        for draw in draws {
+            // Evicted sprites aren't drawn, see Residency
+            let set = match draw.set {
+                Some(ref set) => set,
+                None => continue,
+            };
+            let vertices = CpuAccessibleBuffer::from_iter(
+                device.clone(),
+                BufferUsage::vertex_buffer(),
+                Vertex::from(&draw.rect, &draw.style, draw.dimensions)
+                    .iter()
+                    .cloned(),
+            )
+            .unwrap();
+            let normal_map = self.normal_maps.get(&draw.id).unwrap_or(flat);
+            let orientation = Orientation::of(&draw.style.transform);
+            for view in views {
+                cb = cb
+                    .draw(
+                        self.normal_pipeline.clone(),
+                        &view.state,
+                        vertices.clone(),
+                        (set.clone(), view.sprite_set.clone(), normal_map.clone()),
+                        orientation,
+                    )
+                    .unwrap();
+            }
+        }
+        cb = cb.end_render_pass().unwrap();
+
+        let mut unshadowed = Vec::new();
+        let mut shadowed = Vec::new();
+        for light in &self.lighting.lights {
+            let bounds = light.bounds();
+            let visible = views.iter().any(|view| overlaps(bounds, view.bounds));
+            if !light.enabled || light.radius <= 0.0 || !visible {
+                continue;
+            }
+            let occluders: Vec<&Occluder> = match light.shadows {
+                Shadows::None => Vec::new(),
+                _ => self
+                    .lighting
+                    .occluders
+                    .iter()
+                    .filter(|o| o.enabled && o.points.len() >= 3 && overlaps(o.bounds(), bounds))
+                    .collect(),
+            };
+            if occluders.is_empty() {
+                unshadowed.push(light);
+            } else {
+                shadowed.push((light, occluders));
+            }
+        }
+
+        let input_set = |shadows: Arc<ImageViewAccess + Send + Sync>| {
+            Arc::new(
+                PersistentDescriptorSet::start(self.light_pipeline.clone(), 1)
+                    .add_sampled_image(targets.normals.image.clone(), self.sampler.clone())
+                    .unwrap()
+                    .add_sampled_image(shadows, self.sampler.clone())
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+            ) as Arc<DescriptorSet + Send + Sync>
+        };
+        let unshadowed_set = input_set(self.unshadowed.clone().unwrap());
+
+        let ambient = self.lighting.ambient;
+        cb = cb
+            .begin_render_pass(
+                targets.light[0].framebuffer.clone(),
+                false,
+                vec![[ambient.r, ambient.g, ambient.b, 1.0].into()],
+            )
+            .unwrap();
+        for light in unshadowed {
+            cb = self.draw_light(cb, light, &unshadowed_set, views, &device);
+        }
+        cb = cb.end_render_pass().unwrap();
+
+        let mut current = 0;
+        if !shadowed.is_empty() {
+            let shadowed_set = input_set(targets.shadows.image.clone());
+            let [w, h] = targets.dimensions;
+            let full = viewport_state([0.0, 0.0], [w as f32, h as f32]);
+            for (light, occluders) in shadowed {
+                cb = cb
+                    .begin_render_pass(
+                        targets.shadows.framebuffer.clone(),
+                        false,
+                        vec![[0.0; 4].into()],
+                    )
+                    .unwrap();
+                let vertices = shadow_vertices(light, &occluders);
+                if !vertices.is_empty() {
+                    let vertices = CpuAccessibleBuffer::from_iter(
+                        device.clone(),
+                        BufferUsage::vertex_buffer(),
+                        vertices.into_iter(),
+                    )
+                    .unwrap();
+                    for view in views {
+                        cb = cb
+                            .draw(
+                                self.shadow_pipeline.clone(),
+                                &view.state,
+                                vertices.clone(),
+                                view.shape_set.clone(),
+                                (),
+                            )
+                            .unwrap();
+                    }
+                }
+                cb = cb.end_render_pass().unwrap();
+
+                let (input, output) = (&targets.light[current], &targets.light[1 - current]);
+                let set = Arc::new(
+                    PersistentDescriptorSet::start(self.copy.clone(), 0)
+                        .add_sampled_image(input.image.clone(), self.sampler.clone())
+                        .unwrap()
+                        .build()
+                        .unwrap(),
+                );
+                cb = cb
+                    .begin_render_pass(output.framebuffer.clone(), false, vec![[0.0; 4].into()])
+                    .unwrap()
+                    .draw(self.copy.clone(), &full, self.strip.clone(), set, ())
+                    .unwrap();
+                cb = self.draw_light(cb, light, &shadowed_set, views, &device);
+                cb = cb.end_render_pass().unwrap();
+                current = 1 - current;
+            }
+        }
+        self.current = current;
+        cb
+    }
+
+    // Adds a light to the light buffer whose render pass has begun, in every view that shows
+    // it. set holds the normals and the light's shadows.
+    fn draw_light(
+        &self,
+        mut cb: AutoCommandBufferBuilder,
+        light: &Light,
+        set: &Arc<DescriptorSet + Send + Sync>,
+        views: &[View],
+        device: &Arc<Device>,
+    ) -> AutoCommandBufferBuilder {
+        let c = light.color;
+        let i = light.intensity * c.a;
+        let color = Color::rgb(c.r * i, c.g * i, c.b * i);
+        let [x, y, bw, bh] = light.bounds();
+        let corner = |cx: f32, cy: f32| ShapeVertex::new([x + cx * bw, y + cy * bh], color);
+        let (tl, bl, tr, br) = (
+            corner(0.0, 0.0),
+            corner(0.0, 1.0),
+            corner(1.0, 0.0),
+            corner(1.0, 1.0),
+        );
+        let vertices = CpuAccessibleBuffer::from_iter(
+            device.clone(),
+            BufferUsage::vertex_buffer(),
+            vec![tl, bl.clone(), tr.clone(), tr, bl, br].into_iter(),
+        )
+        .unwrap();
+
+        let (spot, is_spot) = match light.kind {
+            LightKind::Point => ([0.0; 4], 0.0),
+            LightKind::Spot {
+                direction,
+                angle,
+                softness,
+            } => {
+                let half = angle / 2.0;
+                let (outer, inner) = (half, (half - softness).max(0.0));
+                let (sin, cos) = direction.sin_cos();
+                ([cos, sin, outer.cos(), inner.cos()], 1.0)
+            }
+        };
+        let [w, h] = self.targets.as_ref().unwrap().dimensions;
+        let params = LightParams {
+            center: [
+                light.position_x,
+                light.position_y,
+                light.radius,
+                light.falloff,
+            ],
+            spot: spot,
+            target: [1.0 / w as f32, 1.0 / h as f32, light.height, is_spot],
+        };
+        for view in views.iter().filter(|v| overlaps(light.bounds(), v.bounds)) {
+            cb = cb
+                .draw(
+                    self.light_pipeline.clone(),
+                    &view.state,
+                    vertices.clone(),
+                    (view.shape_set.clone(), set.clone()),
+                    params,
+                )
+                .unwrap();
+        }
+        cb
+    }
+
+    // Multiplies the light buffer over what's been drawn so far, inside the scene's render
+    // pass. state has to cover the whole scene.
+    pub fn composite(
+        &self,
+        cb: AutoCommandBufferBuilder,
+        state: &DynamicState,
+    ) -> AutoCommandBufferBuilder {
+        let targets = self.targets.as_ref().unwrap();
+        let set = Arc::new(
+            PersistentDescriptorSet::start(self.composite.clone(), 0)
+                .add_sampled_image(
+                    targets.light[self.current].image.clone(),
+                    self.sampler.clone(),
+                )
+                .unwrap()
+                .build()
+                .unwrap(),
+        );
+        cb.draw(self.composite.clone(), state, self.strip.clone(), set, ())
+            .unwrap()
+    }
+}
+
+// Triangles covering the shadows occluders cast from a light. Every edge facing away from the
+// light is stretched away from it past the light's radius, through a third point in between
+// so wide edges are still covered. A soft light casts from points around it, each adding its
+// share to the shadow buffer.
+fn shadow_vertices(light: &Light, occluders: &[&Occluder]) -> Vec<ShapeVertex> {
+    let (x, y) = (light.position_x, light.position_y);
+    let sources: Vec<(f32, f32)> = match light.shadows {
+        Shadows::Soft(size) if size > 0.0 => (0..SOFT_SAMPLES)
+            .map(|i| {
+                let angle = i as f32 / SOFT_SAMPLES as f32 * std::f32::consts::PI * 2.0;
+                let (sin, cos) = angle.sin_cos();
+                (x + cos * size / 2.0, y + sin * size / 2.0)
+            })
+            .collect(),
+        _ => vec![(x, y)],
+    };
+    let color = Color::rgba(1.0, 1.0, 1.0, 1.0 / sources.len() as f32);
+    let reach = light.radius * 2.0;
+
+    let mut vertices = Vec::new();
+    for &(lx, ly) in &sources {
+        let away = |p: (f32, f32)| {
+            let (dx, dy) = (p.0 - lx, p.1 - ly);
+            let length = (dx * dx + dy * dy).sqrt();
+            (dx / length.max(1e-4), dy / length.max(1e-4), length)
+        };
+        for occluder in occluders {
+            let points = &occluder.points;
+            // The winding decides which side of an edge is outside
+            let area: f32 = (0..points.len())
+                .map(|i| {
+                    let (a, b) = (points[i], points[(i + 1) % points.len()]);
+                    a.0 * b.1 - b.0 * a.1
+                })
+                .sum();
+            let winding = area.signum();
+
+            for i in 0..points.len() {
+                let (a, b) = (points[i], points[(i + 1) % points.len()]);
+                let normal = (winding * (b.1 - a.1), -winding * (b.0 - a.0));
+                if normal.0 * (lx - a.0) + normal.1 * (ly - a.1) >= 0.0 {
+                    continue;
+                }
+                let (ax, ay, da) = away(a);
+                let (bx, by, db) = away(b);
+                let (mx, my, dm) = away((lx + ax + bx, ly + ay + by));
+                if dm < 1e-4 {
+                    continue;
+                }
+                let far_a = [a.0 + ax * reach, a.1 + ay * reach];
+                let far_b = [b.0 + bx * reach, b.1 + by * reach];
+                let far_m = [
+                    lx + mx * (reach + da.max(db)),
+                    ly + my * (reach + da.max(db)),
+                ];
+                let (a, b) = ([a.0, a.1], [b.0, b.1]);
+                for &position in &[a, b, far_b, a, far_b, far_m, a, far_m, far_a] {
+                    vertices.push(ShapeVertex::new(position, color));
+                }
+            }
+        }
+    }
+    vertices
+}
@@ -0,0 +1,271 @@
+use crate::graphics::{BlendMode, Sampling};
+use crate::material::{Material, MAX_TEXTURES};
+use crate::render::pipeline;
+use crate::render::sampler::SamplerCache;
+use crate::render::texture;
+use crate::render::vk::{SpritePipeline, WaitBuffer};
+
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Instant;
+use std::vec::IntoIter;
+
+use vulkano::{
+    buffer::CpuBufferPool,
+    descriptor::{
+        descriptor::{
+            DescriptorBufferDesc, DescriptorDesc, DescriptorDescTy, DescriptorImageDesc,
+            DescriptorImageDescArray, DescriptorImageDescDimensions, ShaderStages,
+        },
+        descriptor_set::{DescriptorSet, PersistentDescriptorSet},
+        pipeline_layout::{PipelineLayoutDesc, PipelineLayoutDescPcRange},
+    },
+    device::{Device, Queue},
+    format::Format,
+    framebuffer::RenderPassAbstract,
+    image::ImmutableImage,
+    pipeline::shader::{ShaderInterfaceDef, ShaderInterfaceDefEntry, ShaderModule},
+    sampler::Sampler,
+};
+
+// Inputs of a material's fragment shader, the outputs of the sprite vertex shader.
+#[derive(Debug, Clone, Copy)]
+pub struct MaterialInput;
+
+unsafe impl ShaderInterfaceDef for MaterialInput {
+    type Iter = IntoIter<ShaderInterfaceDefEntry>;
+
+    fn elements(&self) -> Self::Iter {
+        vec![
+            interface_entry(0, Format::R32G32Sfloat, "tex_coords"),
+            interface_entry(1, Format::R32G32B32A32Sfloat, "color"),
+            interface_entry(2, Format::R32Sfloat, "premultiplied"),
+        ]
+        .into_iter()
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct MaterialOutput;
+
+unsafe impl ShaderInterfaceDef for MaterialOutput {
+    type Iter = IntoIter<ShaderInterfaceDefEntry>;
+
+    fn elements(&self) -> Self::Iter {
+        vec![interface_entry(0, Format::R32G32B32A32Sfloat, "f_color")].into_iter()
+    }
+}
+
+fn interface_entry(location: u32, format: Format, name: &'static str) -> ShaderInterfaceDefEntry {
+    ShaderInterfaceDefEntry {
+        location: location..location + 1,
+        format: format,
+        name: Some(name.into()),
+    }
+}
+
+// Descriptors of a material's fragment shader: the sprite's texture at set 0 like the sprite
+// shader, then the parameters and the extra textures at set 2. The camera at set 1 comes from
+// the vertex shader. Must match the header in Material::header.
+#[derive(Debug, Clone, Copy)]
+pub struct MaterialLayout;
+
+unsafe impl PipelineLayoutDesc for MaterialLayout {
+    fn num_sets(&self) -> usize {
+        3
+    }
+
+    fn num_bindings_in_set(&self, set: usize) -> Option<usize> {
+        match set {
+            0 => Some(1),
+            1 => Some(0),
+            2 => Some(1 + MAX_TEXTURES),
+            _ => None,
+        }
+    }
+
+    fn descriptor(&self, set: usize, binding: usize) -> Option<DescriptorDesc> {
+        let ty = match (set, binding) {
+            (0, 0) => sampled_image(),
+            (2, 0) => DescriptorDescTy::Buffer(DescriptorBufferDesc {
+                dynamic: Some(false),
+                storage: false,
+            }),
+            (2, b) if b <= MAX_TEXTURES => sampled_image(),
+            _ => return None,
+        };
+        Some(DescriptorDesc {
+            ty: ty,
+            array_count: 1,
+            stages: ShaderStages {
+                fragment: true,
+                ..ShaderStages::none()
+            },
+            readonly: true,
+        })
+    }
+
+    fn num_push_constants_ranges(&self) -> usize {
+        0
+    }
+
+    fn push_constants_range(&self, _num: usize) -> Option<PipelineLayoutDescPcRange> {
+        None
+    }
+}
+
+fn sampled_image() -> DescriptorDescTy {
+    DescriptorDescTy::CombinedImageSampler(DescriptorImageDesc {
+        sampled: true,
+        dimensions: DescriptorImageDescDimensions::TwoDimensional,
+        format: None,
+        multisampled: false,
+        array_layers: DescriptorImageDescArray::NonArrayed,
+    })
+}
+
+type Texture = (Arc<ImmutableImage<Format>>, Arc<Sampler>);
+
+struct MaterialEntry {
+    module: Arc<ShaderModule>,
+    pipelines: HashMap<BlendMode, SpritePipeline>,
+    // Always MAX_TEXTURES long, slots the material doesn't use hold a white pixel
+    textures: Vec<Texture>,
+    // time followed by the parameters, as of the last update
+    uniforms: Vec<[f32; 4]>,
+}
+
+// Shader modules, pipelines and textures of the game's materials, indexed by MaterialId.
+// Pipelines are created the first time a material is drawn with a blend mode.
+pub struct MaterialCache {
+    entries: Vec<MaterialEntry>,
+    pool: CpuBufferPool<[f32; 4]>,
+    white: Option<Arc<ImmutableImage<Format>>>,
+    // time is measured from here
+    started: Instant,
+}
+
+impl MaterialCache {
+    pub fn new(device: Arc<Device>) -> Self {
+        MaterialCache {
+            entries: Vec::new(),
+            pool: CpuBufferPool::uniform_buffer(device),
+            white: None,
+            started: Instant::now(),
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        materials: &[Material],
+        samplers: &SamplerCache,
+        default_sampling: Sampling,
+        queue: Arc<Queue>,
+        staging: &CpuBufferPool<u8>,
+        wait_buffer: &WaitBuffer,
+    ) {
+        for material in &materials[self.entries.len().min(materials.len())..] {
+            let spirv = material
+                .spirv
+                .as_ref()
+                .expect("Materials are compiled when they're added to the game");
+            // The SPIR-V comes straight from shaderc
+            let module =
+                unsafe { ShaderModule::from_words(queue.device().clone(), spirv) }.unwrap();
+
+            let mut textures = Vec::with_capacity(MAX_TEXTURES);
+            for extra in &material.textures {
+                let sampling = extra.sampling.unwrap_or(default_sampling);
+                let (image, future) = texture::upload(
+                    &extra.pixels,
+                    extra.dimensions,
+                    sampling.mipmaps,
+                    queue.clone(),
+                    staging,
+                );
+                wait_buffer.lock().unwrap().push(future);
+                textures.push((image, samplers.get(sampling)));
+            }
+            while textures.len() < MAX_TEXTURES {
+                let white = self.white.get_or_insert_with(|| {
+                    let (image, future) = texture::upload(
+                        &[255, 255, 255, 255],
+                        (1, 1),
+                        false,
+                        queue.clone(),
+                        staging,
+                    );
+                    wait_buffer.lock().unwrap().push(future);
+                    image
+                });
+                textures.push((white.clone(), samplers.get(default_sampling)));
+            }
+
+            self.entries.push(MaterialEntry {
+                module: module,
+                pipelines: HashMap::new(),
+                textures: textures,
+                uniforms: Vec::new(),
+            });
+        }
+
+        for (entry, material) in self.entries.iter_mut().zip(materials) {
+            entry.uniforms.clear();
+            entry.uniforms.push([0.0; 4]);
+            entry
+                .uniforms
+                .extend(material.params().map(|p| p.to_array()));
+        }
+    }
+
+    pub fn contains(&self, index: usize) -> bool {
+        index < self.entries.len()
+    }
+
+    pub fn pipeline(
+        &mut self,
+        index: usize,
+        blend: BlendMode,
+        device: Arc<Device>,
+        render_pass: &Arc<RenderPassAbstract + Send + Sync>,
+    ) -> SpritePipeline {
+        let entry = &mut self.entries[index];
+        let module = &entry.module;
+        entry
+            .pipelines
+            .entry(blend)
+            .or_insert_with(|| pipeline::material_pipeline(device, render_pass, module, blend))
+            .clone()
+    }
+
+    // Parameters and extra textures of a material at set 2 of pipeline, with the current time.
+    pub fn descriptor_set(
+        &self,
+        index: usize,
+        pipeline: SpritePipeline,
+    ) -> Arc<DescriptorSet + Send + Sync> {
+        let entry = &self.entries[index];
+        let elapsed = self.started.elapsed();
+        let seconds = elapsed.as_secs() as f32 + elapsed.subsec_micros() as f32 / 1_000_000.0;
+        let mut uniforms = entry.uniforms.clone();
+        uniforms[0][0] = seconds;
+
+        // One image per slot, the builder's type changes with every descriptor so it can't loop
+        let t = &entry.textures;
+        Arc::new(
+            PersistentDescriptorSet::start(pipeline, 2)
+                .add_buffer(self.pool.chunk(uniforms).unwrap())
+                .unwrap()
+                .add_sampled_image(t[0].0.clone(), t[0].1.clone())
+                .unwrap()
+                .add_sampled_image(t[1].0.clone(), t[1].1.clone())
+                .unwrap()
+                .add_sampled_image(t[2].0.clone(), t[2].1.clone())
+                .unwrap()
+                .add_sampled_image(t[3].0.clone(), t[3].1.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        )
+    }
+}
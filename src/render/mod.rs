@@ -1,3 +1,18 @@
+pub mod capture;
+pub mod glyphs;
+pub mod lighting;
+pub mod material;
+pub mod parallax;
+pub mod particles;
+pub mod pipeline;
+pub mod post;
+pub mod rendertexture;
+pub mod residency;
+pub mod sampler;
 mod shader;
+pub mod target;
+pub mod texture;
+pub mod tilemap;
+pub mod transfer;
 pub mod vk;
 pub mod vkinit;
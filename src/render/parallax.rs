@@ -0,0 +1,97 @@
+use crate::graphics::{Sampling, Wrap};
+use crate::parallax::ParallaxLayer;
+use crate::render::sampler::SamplerCache;
+use crate::render::texture;
+use crate::render::vk::{SpritePipeline, Vertex, WaitBuffer};
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+use std::time::Instant;
+
+use vulkano::{
+    buffer::CpuBufferPool,
+    descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet},
+    device::Queue,
+};
+
+// Textures of the parallax layers, uploaded the first time a layer is seen.
+pub struct ParallaxCache {
+    textures: HashMap<usize, Arc<DescriptorSet + Send + Sync>>,
+    layers: Vec<(ParallaxLayer, Arc<DescriptorSet + Send + Sync>)>,
+    // Auto-scrolling is measured from here
+    started: Instant,
+}
+
+impl ParallaxCache {
+    pub fn new() -> Self {
+        ParallaxCache {
+            textures: HashMap::new(),
+            layers: Vec::new(),
+            started: Instant::now(),
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        layers: &[ParallaxLayer],
+        pipeline: &SpritePipeline,
+        samplers: &SamplerCache,
+        default_sampling: Sampling,
+        queue: Arc<Queue>,
+        staging: &CpuBufferPool<u8>,
+        wait_buffer: &WaitBuffer,
+    ) {
+        self.layers.clear();
+        let mut seen = HashSet::new();
+
+        for layer in layers {
+            seen.insert(layer.id());
+            let textures = &mut self.textures;
+            let set = textures.entry(layer.id()).or_insert_with(|| {
+                let mut sampling = layer.sampling.unwrap_or(default_sampling);
+                if sampling.wrap == Wrap::Clamp {
+                    sampling.wrap = Wrap::Repeat;
+                }
+                let (image, future) = texture::upload(
+                    &layer.pixels,
+                    layer.dimensions,
+                    sampling.mipmaps,
+                    queue.clone(),
+                    staging,
+                );
+                wait_buffer.lock().unwrap().push(future);
+                Arc::new(
+                    PersistentDescriptorSet::start(pipeline.clone(), 0)
+                        .add_sampled_image(image, samplers.get(sampling))
+                        .unwrap()
+                        .build()
+                        .unwrap(),
+                )
+            });
+            self.layers.push((layer.clone(), set.clone()));
+        }
+
+        self.textures.retain(|id, _| seen.contains(id));
+    }
+
+    // Draw layer, texture and vertices of every parallax layer for a camera at camera_x,
+    // camera_y seeing view.
+    pub fn vertices(
+        &self,
+        camera_x: f32,
+        camera_y: f32,
+        view: [f32; 4],
+    ) -> Vec<(i32, Arc<DescriptorSet + Send + Sync>, Vec<Vertex>)> {
+        let elapsed = self.started.elapsed();
+        let seconds = elapsed.as_secs() as f32 + elapsed.subsec_micros() as f32 / 1_000_000.0;
+
+        self.layers
+            .iter()
+            .map(|(layer, set)| {
+                let ([x, y, w, h], uv) = layer.quad(camera_x, camera_y, view, seconds);
+                let vertices = Vertex::quad(x, y, w, h, uv, layer.tint).to_vec();
+                (layer.layer, set.clone(), vertices)
+            })
+            .collect()
+    }
+}
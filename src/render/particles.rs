@@ -0,0 +1,97 @@
+use crate::graphics::{BlendMode, Sampling};
+use crate::particles::Emitter;
+use crate::render::sampler::SamplerCache;
+use crate::render::texture;
+use crate::render::vk::{SpritePipeline, Vertex, WaitBuffer};
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use vulkano::{
+    buffer::CpuBufferPool,
+    descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet},
+    device::Queue,
+};
+
+pub struct ParticleDraw {
+    pub layer: i32,
+    pub blend: BlendMode,
+    pub set: Arc<DescriptorSet + Send + Sync>,
+    // Every particle of the emitter, drawn with a single call
+    pub vertices: Vec<Vertex>,
+}
+
+// Textures of the emitters, uploaded the first time an emitter is seen, and the vertices of
+// their particles as of the last update.
+pub struct ParticleCache {
+    textures: HashMap<usize, Arc<DescriptorSet + Send + Sync>>,
+    draws: Vec<ParticleDraw>,
+}
+
+impl ParticleCache {
+    pub fn new() -> Self {
+        ParticleCache {
+            textures: HashMap::new(),
+            draws: Vec::new(),
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        emitters: &[Emitter],
+        pipeline: &SpritePipeline,
+        samplers: &SamplerCache,
+        default_sampling: Sampling,
+        queue: Arc<Queue>,
+        staging: &CpuBufferPool<u8>,
+        wait_buffer: &WaitBuffer,
+    ) {
+        self.draws.clear();
+        let mut seen = HashSet::new();
+
+        for emitter in emitters {
+            seen.insert(emitter.id());
+            if emitter.particles().is_empty() {
+                continue;
+            }
+            let set = self.textures.entry(emitter.id()).or_insert_with(|| {
+                let sampling = emitter.sampling.unwrap_or(default_sampling);
+                let (image, future) = texture::upload(
+                    &emitter.pixels,
+                    emitter.dimensions,
+                    sampling.mipmaps,
+                    queue.clone(),
+                    staging,
+                );
+                wait_buffer.lock().unwrap().push(future);
+                Arc::new(
+                    PersistentDescriptorSet::start(pipeline.clone(), 0)
+                        .add_sampled_image(image, samplers.get(sampling))
+                        .unwrap()
+                        .build()
+                        .unwrap(),
+                )
+            });
+
+            let mut vertices = Vec::with_capacity(emitter.particles().len() * 6);
+            for ([x, y, w, h], color) in emitter.quads() {
+                vertices.extend_from_slice(&Vertex::quad(x, y, w, h, [0.0, 0.0, 1.0, 1.0], color));
+            }
+            if emitter.config.blend == BlendMode::Premultiplied {
+                Vertex::premultiply(&mut vertices);
+            }
+            self.draws.push(ParticleDraw {
+                layer: emitter.config.layer,
+                blend: emitter.config.blend,
+                set: set.clone(),
+                vertices: vertices,
+            });
+        }
+
+        self.textures.retain(|id, _| seen.contains(id));
+    }
+
+    pub fn draws(&self) -> &[ParticleDraw] {
+        &self.draws
+    }
+}
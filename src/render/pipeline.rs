@@ -0,0 +1,202 @@
+// Every pipeline depends only on the render pass and the swapchain format it was made for,
+// viewports are dynamic. Pipelines are built once and kept when the swapchain is recreated.
+
+use crate::graphics::BlendMode;
+use crate::render::material::{MaterialInput, MaterialLayout, MaterialOutput};
+use crate::render::shader;
+use crate::render::vk::{ShapePipeline, ShapeVertex, SpritePipeline, Vertex};
+
+use std::ffi::CStr;
+use std::sync::Arc;
+
+use vulkano::{
+    descriptor::pipeline_layout::PipelineLayoutDesc,
+    device::Device,
+    framebuffer::{RenderPassAbstract, Subpass},
+    pipeline::{
+        blend::{AttachmentBlend, BlendFactor, BlendOp},
+        shader::{GraphicsEntryPoint, GraphicsShaderType, ShaderInterfaceDef, ShaderModule},
+        GraphicsPipeline,
+    },
+};
+
+// Pipeline drawing sprites with the given blend mode.
+pub fn sprite_pipeline(
+    device: Arc<Device>,
+    render_pass: &Arc<RenderPassAbstract + Send + Sync>,
+    blend: BlendMode,
+) -> SpritePipeline {
+    let vs = shader::vs::Shader::load(device.clone()).unwrap();
+    let fs = shader::fs::Shader::load(device.clone()).unwrap();
+
+    Arc::new(
+        GraphicsPipeline::start()
+            .vertex_input_single_buffer::<Vertex>()
+            .vertex_shader(vs.main_entry_point(), ())
+            .triangle_list()
+            .viewports_dynamic_scissors_irrelevant(1)
+            .fragment_shader(fs.main_entry_point(), ())
+            .blend_collective(attachment_blend(blend))
+            .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+            .build(device.clone())
+            .unwrap(),
+    )
+}
+
+// Sprite pipeline whose fragment shader is a material compiled at runtime.
+pub fn material_pipeline(
+    device: Arc<Device>,
+    render_pass: &Arc<RenderPassAbstract + Send + Sync>,
+    module: &ShaderModule,
+    blend: BlendMode,
+) -> SpritePipeline {
+    let vs = shader::vs::Shader::load(device.clone()).unwrap();
+    // Material::header declares exactly these inputs, outputs and descriptors
+    let fs = unsafe {
+        module.graphics_entry_point(
+            CStr::from_bytes_with_nul_unchecked(b"main\0"),
+            MaterialInput,
+            MaterialOutput,
+            MaterialLayout,
+            GraphicsShaderType::Fragment,
+        )
+    };
+
+    Arc::new(
+        GraphicsPipeline::start()
+            .vertex_input_single_buffer::<Vertex>()
+            .vertex_shader(vs.main_entry_point(), ())
+            .triangle_list()
+            .viewports_dynamic_scissors_irrelevant(1)
+            .fragment_shader(fs, ())
+            .blend_collective(attachment_blend(blend))
+            .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+            .build(device.clone())
+            .unwrap(),
+    )
+}
+
+// Pipeline for a full-screen post-processing pass drawn with the blit vertex shader. Without a
+// blend mode the pass replaces what's in the target.
+pub fn post_pipeline<I, O, L>(
+    device: Arc<Device>,
+    render_pass: &Arc<RenderPassAbstract + Send + Sync>,
+    fs: GraphicsEntryPoint<(), I, O, L>,
+    blend: Option<BlendMode>,
+) -> SpritePipeline
+where
+    I: ShaderInterfaceDef,
+    O: ShaderInterfaceDef,
+    L: PipelineLayoutDesc + Clone + Send + Sync + 'static,
+{
+    let vs = shader::blit_vs::Shader::load(device.clone()).unwrap();
+
+    let builder = GraphicsPipeline::start()
+        .vertex_input_single_buffer::<Vertex>()
+        .vertex_shader(vs.main_entry_point(), ())
+        .triangle_strip()
+        .viewports_dynamic_scissors_irrelevant(1)
+        .fragment_shader(fs, ())
+        .render_pass(Subpass::from(render_pass.clone(), 0).unwrap());
+    let builder = match blend {
+        Some(blend) => builder.blend_collective(attachment_blend(blend)),
+        None => builder,
+    };
+    Arc::new(builder.build(device.clone()).unwrap())
+}
+
+// Untextured pipeline for canvas shapes and shadows, the camera is at set 0. Without a blend
+// mode shapes replace what's in the target, which clears a camera's viewport.
+pub fn shape_pipeline(
+    device: Arc<Device>,
+    render_pass: &Arc<RenderPassAbstract + Send + Sync>,
+    blend: Option<BlendMode>,
+) -> ShapePipeline {
+    let vs = shader::shape_vs::Shader::load(device.clone()).unwrap();
+    let fs = shader::shape_fs::Shader::load(device.clone()).unwrap();
+
+    let builder = GraphicsPipeline::start()
+        .vertex_input_single_buffer::<ShapeVertex>()
+        .vertex_shader(vs.main_entry_point(), ())
+        .triangle_list()
+        .viewports_dynamic_scissors_irrelevant(1)
+        .fragment_shader(fs.main_entry_point(), ())
+        .render_pass(Subpass::from(render_pass.clone(), 0).unwrap());
+    let builder = match blend {
+        Some(blend) => builder.blend_collective(attachment_blend(blend)),
+        None => builder,
+    };
+    Arc::new(builder.build(device.clone()).unwrap())
+}
+
+// Sprite pipeline drawing normal maps instead of colors, the normal map is at set 2.
+pub fn normal_pipeline(
+    device: Arc<Device>,
+    render_pass: &Arc<RenderPassAbstract + Send + Sync>,
+) -> SpritePipeline {
+    let vs = shader::vs::Shader::load(device.clone()).unwrap();
+    let fs = shader::normal_fs::Shader::load(device.clone()).unwrap();
+
+    Arc::new(
+        GraphicsPipeline::start()
+            .vertex_input_single_buffer::<Vertex>()
+            .vertex_shader(vs.main_entry_point(), ())
+            .triangle_list()
+            .viewports_dynamic_scissors_irrelevant(1)
+            .fragment_shader(fs.main_entry_point(), ())
+            .blend_collective(attachment_blend(BlendMode::Alpha))
+            .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+            .build(device.clone())
+            .unwrap(),
+    )
+}
+
+// Pipeline adding a light to the light buffer. Like shapes the camera is at set 0, the
+// normals and shadows the light reads are at set 1.
+pub fn light_pipeline(
+    device: Arc<Device>,
+    render_pass: &Arc<RenderPassAbstract + Send + Sync>,
+) -> ShapePipeline {
+    let vs = shader::light_vs::Shader::load(device.clone()).unwrap();
+    let fs = shader::light_fs::Shader::load(device.clone()).unwrap();
+
+    Arc::new(
+        GraphicsPipeline::start()
+            .vertex_input_single_buffer::<ShapeVertex>()
+            .vertex_shader(vs.main_entry_point(), ())
+            .triangle_list()
+            .viewports_dynamic_scissors_irrelevant(1)
+            .fragment_shader(fs.main_entry_point(), ())
+            .blend_collective(attachment_blend(BlendMode::Additive))
+            .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
+            .build(device.clone())
+            .unwrap(),
+    )
+}
+
+// The sprite fragment shader always outputs premultiplied alpha, so every mode is expressed
+// in terms of a premultiplied source.
+pub fn attachment_blend(blend: BlendMode) -> AttachmentBlend {
+    let (color_source, color_destination) = match blend {
+        BlendMode::Alpha | BlendMode::Premultiplied => {
+            (BlendFactor::One, BlendFactor::OneMinusSrcAlpha)
+        }
+        BlendMode::Additive => (BlendFactor::One, BlendFactor::One),
+        BlendMode::Multiply => (BlendFactor::DstColor, BlendFactor::OneMinusSrcAlpha),
+        BlendMode::Screen => (BlendFactor::One, BlendFactor::OneMinusSrcColor),
+    };
+
+    AttachmentBlend {
+        enabled: true,
+        color_op: BlendOp::Add,
+        color_source: color_source,
+        color_destination: color_destination,
+        alpha_op: BlendOp::Add,
+        alpha_source: BlendFactor::One,
+        alpha_destination: BlendFactor::OneMinusSrcAlpha,
+        mask_red: true,
+        mask_green: true,
+        mask_blue: true,
+        mask_alpha: true,
+    }
+}
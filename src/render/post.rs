@@ -0,0 +1,455 @@
+use crate::graphics::{self, BlendMode, Sampling};
+use crate::postprocess::Effect;
+use crate::render::material::MaterialCache;
+use crate::render::pipeline;
+use crate::render::sampler::SamplerCache;
+use crate::render::shader;
+use crate::render::target::RenderTarget;
+use crate::render::texture;
+use crate::render::vk::{viewport_state, SpritePipeline, Vertex, WaitBuffer};
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use vulkano::{
+    buffer::{BufferUsage, CpuAccessibleBuffer, CpuBufferPool},
+    command_buffer::AutoCommandBufferBuilder,
+    descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet},
+    device::{Device, Queue},
+    format::Format,
+    framebuffer::RenderPassAbstract,
+    image::ImmutableImage,
+    sampler::Sampler,
+};
+
+// Push constants of the built-in passes, the Params block in render::shader.
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+struct Params {
+    values: [f32; 4],
+    // 1 / width and 1 / height of the pass's input
+    texel: [f32; 4],
+}
+
+// The targets passes read from and draw to, all the size of the scene except for the half
+// size ones bloom blurs in.
+struct Targets {
+    dimensions: [u32; 2],
+    scene: RenderTarget,
+    ping: RenderTarget,
+    pong: RenderTarget,
+    scratch: RenderTarget,
+    half: [RenderTarget; 2],
+}
+
+// Runs the game's post-processing effects over the finished scene, see postprocess::Effect.
+pub struct PostChain {
+    effects: Vec<Effect>,
+    luts: HashMap<usize, (Arc<ImmutableImage<Format>>, u32)>,
+    targets: Option<Targets>,
+    copy: SpritePipeline,
+    bright: SpritePipeline,
+    blur: SpritePipeline,
+    add: SpritePipeline,
+    vignette: SpritePipeline,
+    grade: SpritePipeline,
+    crt: SpritePipeline,
+    sampler: Arc<Sampler>,
+    strip: Arc<CpuAccessibleBuffer<[Vertex]>>,
+    // Custom passes are materials, drawn with the sprite shader through an identity camera
+    quad: Arc<CpuAccessibleBuffer<[Vertex]>>,
+    identity_camera: Arc<DescriptorSet + Send + Sync>,
+}
+
+impl PostChain {
+    pub fn new(
+        device: Arc<Device>,
+        render_pass: &Arc<RenderPassAbstract + Send + Sync>,
+        sprite_pipeline: &SpritePipeline,
+        samplers: &SamplerCache,
+    ) -> Self {
+        let copy = shader::blit_fs::Shader::load(device.clone()).unwrap();
+        let bright = shader::post_bright::Shader::load(device.clone()).unwrap();
+        let blur = shader::post_blur::Shader::load(device.clone()).unwrap();
+        let add = shader::post_add::Shader::load(device.clone()).unwrap();
+        let vignette = shader::post_vignette::Shader::load(device.clone()).unwrap();
+        let grade = shader::post_grade::Shader::load(device.clone()).unwrap();
+        let crt = shader::post_crt::Shader::load(device.clone()).unwrap();
+
+        let camera = CpuAccessibleBuffer::from_data(
+            device.clone(),
+            BufferUsage::uniform_buffer(),
+            shader::vs::ty::Camera {
+                view_projection: [
+                    [1.0, 0.0, 0.0, 0.0],
+                    [0.0, 1.0, 0.0, 0.0],
+                    [0.0, 0.0, 1.0, 0.0],
+                    [0.0, 0.0, 0.0, 1.0],
+                ],
+            },
+        )
+        .unwrap();
+
+        PostChain {
+            effects: Vec::new(),
+            luts: HashMap::new(),
+            targets: None,
+            copy: pipeline::post_pipeline(
+                device.clone(),
+                render_pass,
+                copy.main_entry_point(),
+                None,
+            ),
+            bright: pipeline::post_pipeline(
+                device.clone(),
+                render_pass,
+                bright.main_entry_point(),
+                None,
+            ),
+            blur: pipeline::post_pipeline(
+                device.clone(),
+                render_pass,
+                blur.main_entry_point(),
+                None,
+            ),
+            add: pipeline::post_pipeline(
+                device.clone(),
+                render_pass,
+                add.main_entry_point(),
+                Some(BlendMode::Additive),
+            ),
+            vignette: pipeline::post_pipeline(
+                device.clone(),
+                render_pass,
+                vignette.main_entry_point(),
+                None,
+            ),
+            grade: pipeline::post_pipeline(
+                device.clone(),
+                render_pass,
+                grade.main_entry_point(),
+                None,
+            ),
+            crt: pipeline::post_pipeline(device.clone(), render_pass, crt.main_entry_point(), None),
+            sampler: samplers.get(Sampling {
+                filter: graphics::Filter::Linear,
+                wrap: graphics::Wrap::Clamp,
+                ..Sampling::default()
+            }),
+            strip: CpuAccessibleBuffer::from_iter(
+                device.clone(),
+                BufferUsage::vertex_buffer(),
+                Vertex::fullscreen().iter().cloned(),
+            )
+            .unwrap(),
+            quad: CpuAccessibleBuffer::from_iter(
+                device.clone(),
+                BufferUsage::vertex_buffer(),
+                Vertex::quad(
+                    -1.0,
+                    -1.0,
+                    2.0,
+                    2.0,
+                    [0.0, 0.0, 1.0, 1.0],
+                    graphics::Color::WHITE,
+                )
+                .iter()
+                .cloned(),
+            )
+            .unwrap(),
+            identity_camera: Arc::new(
+                PersistentDescriptorSet::start(sprite_pipeline.clone(), 1)
+                    .add_buffer(camera)
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+            ),
+        }
+    }
+
+    // Takes in the game's effects and uploads the lookup tables of new ones.
+    pub fn update(
+        &mut self,
+        effects: &[Effect],
+        queue: Arc<Queue>,
+        staging: &CpuBufferPool<u8>,
+        wait_buffer: &WaitBuffer,
+    ) {
+        let mut seen = HashSet::new();
+        for effect in effects {
+            if let Effect::ColorGrading { ref lut, .. } = *effect {
+                seen.insert(lut.id());
+                self.luts.entry(lut.id()).or_insert_with(|| {
+                    let (image, future) = texture::upload(
+                        &lut.pixels,
+                        lut.dimensions(),
+                        false,
+                        queue.clone(),
+                        staging,
+                    );
+                    wait_buffer.lock().unwrap().push(future);
+                    (image, lut.size)
+                });
+            }
+        }
+        self.luts.retain(|id, _| seen.contains(id));
+        self.effects = effects.to_vec();
+    }
+
+    pub fn is_active(&self) -> bool {
+        !self.effects.is_empty()
+    }
+
+    // (Re)creates the targets for a scene of the given size.
+    pub fn prepare(
+        &mut self,
+        device: Arc<Device>,
+        render_pass: &Arc<RenderPassAbstract + Send + Sync>,
+        dimensions: [u32; 2],
+        format: Format,
+    ) {
+        let outdated = match self.targets {
+            Some(ref targets) => targets.dimensions != dimensions,
+            None => true,
+        };
+        if outdated {
+            let target =
+                |dimensions| RenderTarget::new(device.clone(), render_pass, dimensions, format);
+            let half = [(dimensions[0] / 2).max(1), (dimensions[1] / 2).max(1)];
+            self.targets = Some(Targets {
+                dimensions: dimensions,
+                scene: target(dimensions),
+                ping: target(dimensions),
+                pong: target(dimensions),
+                scratch: target(dimensions),
+                half: [target(half), target(half)],
+            });
+        }
+    }
+
+    // Where the scene is drawn when there's no virtual resolution target to draw it to.
+    pub fn scene(&self) -> &RenderTarget {
+        &self.targets.as_ref().unwrap().scene
+    }
+
+    // Records every effect's passes, starting from input, which has to be the size given to
+    // prepare. Returns the target holding the result.
+    pub fn run<'a>(
+        &'a self,
+        mut cb: AutoCommandBufferBuilder,
+        input: &'a RenderTarget,
+        materials: &mut MaterialCache,
+        device: Arc<Device>,
+        render_pass: &Arc<RenderPassAbstract + Send + Sync>,
+        linear: bool,
+    ) -> (AutoCommandBufferBuilder, &'a RenderTarget) {
+        let targets = self.targets.as_ref().unwrap();
+        let mut current = input;
+
+        for effect in &self.effects {
+            let output = if std::ptr::eq(current, &targets.ping) {
+                &targets.pong
+            } else {
+                &targets.ping
+            };
+
+            cb = match *effect {
+                Effect::Bloom {
+                    threshold,
+                    intensity,
+                    radius,
+                } => {
+                    // Bright parts are blurred at half size, which also doubles the radius
+                    let glow = &targets.half[0];
+                    cb = self.pass(cb, &self.bright, current, glow, [threshold, 0.0, 0.0, 0.0]);
+                    cb = self.blur(cb, glow, glow, radius / 2.0);
+
+                    // The frame is copied and the glow added on top of it
+                    let frame_set = self.input_set(&self.copy, current);
+                    let glow_set = self.input_set(&self.add, glow);
+                    let state = viewport_state([0.0, 0.0], size(output));
+                    cb.begin_render_pass(output.framebuffer.clone(), false, vec![[0.0; 4].into()])
+                        .unwrap()
+                        .draw(
+                            self.copy.clone(),
+                            &state,
+                            self.strip.clone(),
+                            frame_set,
+                            params(current, [0.0; 4]),
+                        )
+                        .unwrap()
+                        .draw(
+                            self.add.clone(),
+                            &state,
+                            self.strip.clone(),
+                            glow_set,
+                            params(glow, [intensity, 0.0, 0.0, 0.0]),
+                        )
+                        .unwrap()
+                        .end_render_pass()
+                        .unwrap()
+                }
+                Effect::Vignette {
+                    intensity,
+                    radius,
+                    softness,
+                } => self.pass(
+                    cb,
+                    &self.vignette,
+                    current,
+                    output,
+                    [intensity, radius, softness, 0.0],
+                ),
+                Effect::ColorGrading { ref lut, intensity } => {
+                    let (ref image, size) = self.luts[&lut.id()];
+                    let set = Arc::new(
+                        PersistentDescriptorSet::start(self.grade.clone(), 0)
+                            .add_sampled_image(current.image.clone(), self.sampler.clone())
+                            .unwrap()
+                            .add_sampled_image(image.clone(), self.sampler.clone())
+                            .unwrap()
+                            .build()
+                            .unwrap(),
+                    );
+                    let values = [size as f32, intensity, if linear { 1.0 } else { 0.0 }, 0.0];
+                    self.draw(cb, &self.grade, set, current, output, values)
+                }
+                Effect::Crt {
+                    scanlines,
+                    curvature,
+                } => self.pass(
+                    cb,
+                    &self.crt,
+                    current,
+                    output,
+                    [scanlines, curvature, 0.0, 0.0],
+                ),
+                Effect::Blur { radius } => self.blur(cb, current, output, radius),
+                Effect::Custom(id) => {
+                    if !materials.contains(id.0) {
+                        continue;
+                    }
+                    let pipeline =
+                        materials.pipeline(id.0, BlendMode::Alpha, device.clone(), render_pass);
+                    let material_set = materials.descriptor_set(id.0, pipeline.clone());
+                    let set = self.input_set(&pipeline, current);
+                    cb.begin_render_pass(output.framebuffer.clone(), false, vec![[0.0; 4].into()])
+                        .unwrap()
+                        .draw(
+                            pipeline,
+                            &viewport_state([0.0, 0.0], size(output)),
+                            self.quad.clone(),
+                            (set, self.identity_camera.clone(), material_set),
+                            (),
+                        )
+                        .unwrap()
+                        .end_render_pass()
+                        .unwrap()
+                }
+            };
+            current = output;
+        }
+        (cb, current)
+    }
+
+    // Two blur passes, horizontal from input to scratch, or the other half size target for
+    // half size input, and vertical from there to output. input and output may be the same.
+    fn blur(
+        &self,
+        cb: AutoCommandBufferBuilder,
+        input: &RenderTarget,
+        output: &RenderTarget,
+        radius: f32,
+    ) -> AutoCommandBufferBuilder {
+        let targets = self.targets.as_ref().unwrap();
+        let scratch = if input.dimensions == targets.dimensions {
+            &targets.scratch
+        } else if std::ptr::eq(input, &targets.half[0]) {
+            &targets.half[1]
+        } else {
+            &targets.half[0]
+        };
+        // The nine taps cover four steps on either side
+        let step = radius / 4.0;
+        let cb = self.pass(cb, &self.blur, input, scratch, [1.0, 0.0, step, 0.0]);
+        self.pass(cb, &self.blur, scratch, output, [0.0, 1.0, step, 0.0])
+    }
+
+    // A built-in pass reading from input and replacing what's in output.
+    fn pass(
+        &self,
+        cb: AutoCommandBufferBuilder,
+        pipeline: &SpritePipeline,
+        input: &RenderTarget,
+        output: &RenderTarget,
+        values: [f32; 4],
+    ) -> AutoCommandBufferBuilder {
+        let set = self.input_set(pipeline, input);
+        self.draw(cb, pipeline, set, input, output, values)
+    }
+
+    fn draw(
+        &self,
+        cb: AutoCommandBufferBuilder,
+        pipeline: &SpritePipeline,
+        set: Arc<DescriptorSet + Send + Sync>,
+        input: &RenderTarget,
+        output: &RenderTarget,
+        values: [f32; 4],
+    ) -> AutoCommandBufferBuilder {
+        cb.begin_render_pass(output.framebuffer.clone(), false, vec![[0.0; 4].into()])
+            .unwrap()
+            .draw(
+                pipeline.clone(),
+                &viewport_state([0.0, 0.0], size(output)),
+                self.strip.clone(),
+                set,
+                params(input, values),
+            )
+            .unwrap()
+            .end_render_pass()
+            .unwrap()
+    }
+
+    fn input_set(
+        &self,
+        pipeline: &SpritePipeline,
+        input: &RenderTarget,
+    ) -> Arc<DescriptorSet + Send + Sync> {
+        Arc::new(
+            PersistentDescriptorSet::start(pipeline.clone(), 0)
+                .add_sampled_image(input.image.clone(), self.sampler.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        )
+    }
+}
+
+fn params(input: &RenderTarget, values: [f32; 4]) -> Params {
+    Params {
+        values: values,
+        texel: [
+            1.0 / input.dimensions[0] as f32,
+            1.0 / input.dimensions[1] as f32,
+            0.0,
+            0.0,
+        ],
+    }
+}
+
+fn size(target: &RenderTarget) -> [f32; 2] {
+    [target.dimensions[0] as f32, target.dimensions[1] as f32]
+}
+
+// Whether the format stores linear colors which are encoded to sRGB on write.
+pub fn is_srgb(format: Format) -> bool {
+    match format {
+        Format::R8G8B8A8Srgb
+        | Format::B8G8R8A8Srgb
+        | Format::A8B8G8R8SrgbPack32
+        | Format::R8G8B8Srgb
+        | Format::B8G8R8Srgb => true,
+        _ => false,
+    }
+}
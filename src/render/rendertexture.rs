@@ -0,0 +1,134 @@
+use crate::camera::{Camera2D, Layers};
+use crate::graphics::{Color, Sampling, Wrap};
+use crate::render::sampler::SamplerCache;
+use crate::render::target::RenderTarget;
+use crate::render::vk::SpritePipeline;
+use crate::rendertexture::RenderTexture;
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use vulkano::{
+    descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet},
+    device::Device,
+    format::Format,
+    framebuffer::{FramebufferAbstract, RenderPassAbstract},
+};
+
+struct Entry {
+    target: RenderTarget,
+    sampling: Sampling,
+    set: Arc<DescriptorSet + Send + Sync>,
+    // The image can't be sampled before it's been drawn to once
+    drawn: bool,
+}
+
+// What present needs to draw one render texture.
+pub struct TexturePass {
+    pub framebuffer: Arc<FramebufferAbstract + Send + Sync>,
+    pub dimensions: [u32; 2],
+    pub camera: Camera2D,
+    pub layers: Layers,
+    pub clear_color: Color,
+}
+
+// The images of the game's render textures, recreated when a texture changes size.
+pub struct RenderTextureCache {
+    textures: Vec<RenderTexture>,
+    entries: HashMap<usize, Entry>,
+}
+
+impl RenderTextureCache {
+    pub fn new() -> Self {
+        RenderTextureCache {
+            textures: Vec::new(),
+            entries: HashMap::new(),
+        }
+    }
+
+    pub fn update(
+        &mut self,
+        textures: &[RenderTexture],
+        device: Arc<Device>,
+        render_pass: &Arc<RenderPassAbstract + Send + Sync>,
+        format: Format,
+        pipeline: &SpritePipeline,
+        samplers: &SamplerCache,
+        default_sampling: Sampling,
+    ) {
+        self.textures = textures.to_vec();
+        self.entries.retain(|&id, _| id < textures.len());
+
+        for (id, texture) in textures.iter().enumerate() {
+            let dimensions = [texture.width.max(1), texture.height.max(1)];
+            let sampling = Sampling {
+                mipmaps: false,
+                ..texture.sampling.unwrap_or(Sampling {
+                    wrap: Wrap::Clamp,
+                    ..default_sampling
+                })
+            };
+            let stale = match self.entries.get(&id) {
+                Some(entry) => entry.target.dimensions != dimensions || entry.sampling != sampling,
+                None => true,
+            };
+            if !stale {
+                continue;
+            }
+
+            // Only changing the sampling keeps the image and what's been drawn to it
+            let (target, drawn) = match self.entries.remove(&id) {
+                Some(entry) if entry.target.dimensions == dimensions => (entry.target, entry.drawn),
+                _ => (
+                    RenderTarget::new(device.clone(), render_pass, dimensions, format),
+                    false,
+                ),
+            };
+            let set = Arc::new(
+                PersistentDescriptorSet::start(pipeline.clone(), 0)
+                    .add_sampled_image(target.image.clone(), samplers.get(sampling))
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+            );
+            self.entries.insert(
+                id,
+                Entry {
+                    target: target,
+                    sampling: sampling,
+                    set: set,
+                    drawn: drawn,
+                },
+            );
+        }
+    }
+
+    // The render textures to draw this frame. They count as drawn from here on, so they have to
+    // be drawn before anything samples them.
+    pub fn passes(&mut self) -> Vec<TexturePass> {
+        let mut passes = Vec::new();
+        for (id, texture) in self.textures.iter().enumerate() {
+            let entry = match self.entries.get_mut(&id) {
+                Some(entry) if texture.enabled || !entry.drawn => entry,
+                _ => continue,
+            };
+            entry.drawn = true;
+            passes.push(TexturePass {
+                framebuffer: entry.target.framebuffer.clone(),
+                dimensions: entry.target.dimensions,
+                camera: texture.camera.clone(),
+                layers: texture.layers.clone(),
+                clear_color: texture.clear_color,
+            });
+        }
+        passes
+    }
+
+    // Descriptor set sprites showing the render texture are drawn with, once it's been drawn.
+    pub fn descriptor_set(&self, id: usize) -> Option<Arc<DescriptorSet + Send + Sync>> {
+        self.entries
+            .get(&id)
+            .filter(|entry| entry.drawn)
+            .map(|entry| entry.set.clone())
+    }
+}
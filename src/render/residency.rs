@@ -0,0 +1,204 @@
+use crate::graphics::{SpriteMemory, TextureUsage};
+use crate::render::vk::Draw;
+
+use std::collections::{HashMap, HashSet};
+
+// Keeps the textures of sprites within the game's budget. Sprites in view count as drawn,
+// once the budget is exceeded the textures drawn least recently lose their descriptor set,
+// which frees the image. Evicted sprites that come into view again are asked to be uploaded
+// again by the game, and are left out until they are.
+pub struct Residency {
+    budget: Option<u64>,
+    frame: u64,
+    last_drawn: HashMap<usize, u64>,
+    // Evicted textures seen this frame
+    wanted: HashSet<usize>,
+    // Evicted textures on their way back to the GPU
+    requested: HashSet<usize>,
+}
+
+impl Residency {
+    pub fn new() -> Self {
+        Residency {
+            budget: None,
+            frame: 0,
+            last_drawn: HashMap::new(),
+            wanted: HashSet::new(),
+            requested: HashSet::new(),
+        }
+    }
+
+    pub fn set_budget(&mut self, budget: Option<u64>) {
+        self.budget = budget;
+    }
+
+    pub fn drawn(&mut self, id: usize) {
+        self.last_drawn.insert(id, self.frame);
+    }
+
+    pub fn want(&mut self, id: usize) {
+        if !self.requested.contains(&id) {
+            self.wanted.insert(id);
+        }
+    }
+
+    // Evicts textures until the sprites fit in the budget, never ones drawn this frame, and
+    // starts the next frame. Returns the textures to upload again and what's on the GPU now.
+    pub fn end_frame(&mut self, draws: &mut [Draw]) -> (Vec<usize>, SpriteMemory) {
+        for draw in draws.iter().filter(|d| d.set.is_some()) {
+            self.requested.remove(&draw.id);
+        }
+        let mut used: u64 = draws
+            .iter()
+            .filter(|d| d.set.is_some())
+            .map(|d| d.bytes)
+            .sum();
+
+        let resident: Vec<(usize, usize, u64)> = draws
+            .iter()
+            .enumerate()
+            .filter(|(_, d)| d.set.is_some())
+            .map(|(i, d)| (i, d.id, d.bytes))
+            .collect();
+        for i in self.evictions(&resident, used) {
+            draws[i].set = None;
+            used -= draws[i].bytes;
+        }
+
+        let reupload: Vec<usize> = self.wanted.drain().collect();
+        self.requested.extend(reupload.iter().cloned());
+        let memory = SpriteMemory {
+            budget: self.budget,
+            used: used,
+            textures: draws
+                .iter()
+                .map(|d| TextureUsage {
+                    id: d.id,
+                    bytes: d.bytes,
+                    resident: d.set.is_some(),
+                    frames_unseen: self.last_drawn.get(&d.id).map(|&f| self.frame - f),
+                })
+                .collect(),
+        };
+        self.frame += 1;
+        (reupload, memory)
+    }
+
+    // Indices of the resident (index, id, bytes) draws to evict for the rest to fit in the
+    // budget, those drawn least recently first.
+    fn evictions(&self, resident: &[(usize, usize, u64)], mut used: u64) -> Vec<usize> {
+        let budget = match self.budget {
+            Some(budget) => budget,
+            None => return Vec::new(),
+        };
+        let mut candidates: Vec<(Option<u64>, usize, u64)> = resident
+            .iter()
+            .map(|&(i, id, bytes)| (self.last_drawn.get(&id).cloned(), i, bytes))
+            .filter(|&(last, _, _)| last != Some(self.frame))
+            .collect();
+        // Never drawn sorts first
+        candidates.sort();
+        let mut evicted = Vec::new();
+        for (_, i, bytes) in candidates {
+            if used <= budget {
+                break;
+            }
+            evicted.push(i);
+            used -= bytes;
+        }
+        evicted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::entity::{Rect, SpriteStyle};
+
+    use std::sync::Arc;
+
+    // Evicted draws, the only kind that can be made without a device
+    fn draw(id: usize) -> Draw {
+        Draw {
+            id: id,
+            set: None,
+            rect: Arc::new(Rect::new(0.0, 0.0, 1.0, 1.0)),
+            style: Arc::new(SpriteStyle::default()),
+            dimensions: (1, 1),
+            bytes: 100,
+        }
+    }
+
+    fn residency(budget: Option<u64>) -> Residency {
+        let mut residency = Residency::new();
+        residency.set_budget(budget);
+        residency
+    }
+
+    #[test]
+    fn no_budget() {
+        let residency = residency(None);
+        assert!(residency
+            .evictions(&[(0, 0, 100), (1, 1, 100)], 200)
+            .is_empty());
+    }
+
+    #[test]
+    fn within_budget() {
+        let residency = residency(Some(200));
+        assert!(residency
+            .evictions(&[(0, 0, 100), (1, 1, 100)], 200)
+            .is_empty());
+    }
+
+    #[test]
+    fn least_recently_drawn_first() {
+        let mut residency = residency(Some(100));
+        residency.drawn(2);
+        residency.end_frame(&mut []);
+        residency.drawn(0);
+        residency.end_frame(&mut []);
+        // 1 was never drawn, 2 longer ago than 0
+        let resident = [(0, 0, 100), (1, 1, 100), (2, 2, 100), (3, 3, 100)];
+        residency.drawn(3);
+        assert_eq!(residency.evictions(&resident, 400), vec![1, 2, 0]);
+        assert_eq!(residency.evictions(&resident, 300), vec![1, 2]);
+    }
+
+    #[test]
+    fn drawn_this_frame_stays() {
+        let mut residency = residency(Some(0));
+        residency.drawn(0);
+        residency.drawn(1);
+        assert!(residency
+            .evictions(&[(0, 0, 100), (1, 1, 100)], 200)
+            .is_empty());
+    }
+
+    #[test]
+    fn reupload_once() {
+        let mut residency = residency(Some(0));
+        let mut draws = vec![draw(0), draw(1)];
+        residency.want(1);
+        let (reupload, memory) = residency.end_frame(&mut draws);
+        assert_eq!(reupload, vec![1]);
+        assert_eq!(memory.used, 0);
+        assert!(memory.textures.iter().all(|t| !t.resident));
+
+        // Still on its way back
+        residency.want(1);
+        assert!(residency.end_frame(&mut draws).0.is_empty());
+    }
+
+    #[test]
+    fn frames_unseen() {
+        let mut residency = residency(None);
+        let mut draws = vec![draw(0), draw(1)];
+        residency.drawn(0);
+        residency.end_frame(&mut draws);
+        residency.end_frame(&mut draws);
+        let (_, memory) = residency.end_frame(&mut draws);
+        assert_eq!(memory.textures[0].frames_unseen, Some(2));
+        assert_eq!(memory.textures[1].frames_unseen, None);
+    }
+}
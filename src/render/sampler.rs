@@ -0,0 +1,73 @@
+use crate::graphics::{Filter, Sampling, Wrap};
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use vulkano::{
+    device::Device,
+    sampler::{self, MipmapMode, Sampler, SamplerAddressMode},
+};
+
+// Samplers are created once per distinct Sampling and shared between every texture using it.
+pub struct SamplerCache {
+    device: Arc<Device>,
+    samplers: Mutex<HashMap<Sampling, Arc<Sampler>>>,
+}
+
+impl SamplerCache {
+    pub fn new(device: Arc<Device>) -> Self {
+        SamplerCache {
+            device: device,
+            samplers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, sampling: Sampling) -> Arc<Sampler> {
+        self.samplers
+            .lock()
+            .unwrap()
+            .entry(sampling)
+            .or_insert_with(|| create(self.device.clone(), sampling))
+            .clone()
+    }
+}
+
+fn create(device: Arc<Device>, sampling: Sampling) -> Arc<Sampler> {
+    let filter = match sampling.filter {
+        Filter::Nearest => sampler::Filter::Nearest,
+        Filter::Linear => sampler::Filter::Linear,
+    };
+    let mipmap_mode = match sampling.filter {
+        Filter::Linear if sampling.mipmaps => MipmapMode::Linear,
+        _ => MipmapMode::Nearest,
+    };
+    let address_mode = match sampling.wrap {
+        Wrap::Clamp => SamplerAddressMode::ClampToEdge,
+        Wrap::Repeat => SamplerAddressMode::Repeat,
+        Wrap::Mirror => SamplerAddressMode::MirroredRepeat,
+    };
+
+    // Anisotropy is an optional device feature
+    let anisotropy = if device.enabled_features().sampler_anisotropy {
+        let limit = device.physical_device().limits().max_sampler_anisotropy();
+        (sampling.anisotropy.max(1) as f32).min(limit)
+    } else {
+        1.0
+    };
+    let max_lod = if sampling.mipmaps { 1000.0 } else { 0.0 };
+
+    Sampler::new(
+        device,
+        filter,
+        filter,
+        mipmap_mode,
+        address_mode,
+        address_mode,
+        address_mode,
+        0.0,
+        anisotropy,
+        0.0,
+        max_lod,
+    )
+    .unwrap()
+}
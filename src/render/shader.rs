@@ -6,24 +6,64 @@ pub mod vs {
         src: "
 #version 450
 layout(location = 0) in vec2 position;
-layout(location = 1) in vec2 window_dimensions;
-layout(location = 2) in vec2 image_dimensions;
+layout(location = 1) in vec2 tex_coords;
+layout(location = 2) in vec4 color;
+layout(location = 3) in float premultiplied;
 
-layout(location = 0) out vec2 tex_coords;
+layout(location = 0) out vec2 v_tex_coords;
+layout(location = 1) out vec4 v_color;
+layout(location = 2) out float v_premultiplied;
 
+layout(set = 1, binding = 0) uniform Camera {
+    mat4 view_projection;
+} camera;
+
+void main() {
+    gl_Position = camera.view_projection * vec4(position, 0.0, 1.0);
+    v_tex_coords = tex_coords;
+    v_color = color;
+    v_premultiplied = premultiplied;
+}"
+    }
+}
+
+pub mod fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+#version 450
+layout(location = 0) in vec2 tex_coords;
+layout(location = 1) in vec4 color;
+layout(location = 2) in float premultiplied;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D tex;
 void main() {
+    // Output is always premultiplied, see render::pipeline::attachment_blend
+    vec4 c = texture(tex, tex_coords) * color;
+    f_color = vec4(c.rgb * mix(c.a, 1.0, premultiplied), c.a);
+}"
+    }
+}
+
+pub mod blit_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+#version 450
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec2 tex_coords;
 
-    vec2 scale;
-    scale.x = image_dimensions.x / window_dimensions.x;
-    scale.y = image_dimensions.y / window_dimensions.y;
+layout(location = 0) out vec2 v_tex_coords;
 
+void main() {
     gl_Position = vec4(position, 0.0, 1.0);
-    tex_coords = (position) + vec2(scale.x, scale.y);
+    v_tex_coords = tex_coords;
 }"
     }
 }
 
-pub mod fs {
+pub mod blit_fs {
     vulkano_shaders::shader! {
         ty: "fragment",
         src: "
@@ -37,3 +77,308 @@ void main() {
 }"
     }
 }
+
+pub mod shape_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+#version 450
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec4 color;
+
+layout(location = 0) out vec4 v_color;
+
+layout(set = 0, binding = 0) uniform Camera {
+    mat4 view_projection;
+} camera;
+
+void main() {
+    gl_Position = camera.view_projection * vec4(position, 0.0, 1.0);
+    v_color = color;
+}"
+    }
+}
+
+pub mod shape_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+#version 450
+layout(location = 0) in vec4 color;
+layout(location = 0) out vec4 f_color;
+
+void main() {
+    f_color = vec4(color.rgb * color.a, color.a);
+}"
+    }
+}
+
+// The built-in post-processing passes run blit_vs over the whole target and share the Params
+// push constants, see render::post::Params.
+
+pub mod post_bright {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+#version 450
+layout(location = 0) in vec2 tex_coords;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D tex;
+layout(push_constant) uniform Params {
+    vec4 values;
+    vec4 texel;
+} params;
+
+// values.x is the threshold
+void main() {
+    vec4 c = texture(tex, tex_coords);
+    float brightness = max(c.r, max(c.g, c.b));
+    float amount = max(brightness - params.values.x, 0.0) / max(brightness, 0.0001);
+    f_color = vec4(c.rgb * amount, 1.0);
+}"
+    }
+}
+
+pub mod post_blur {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+#version 450
+layout(location = 0) in vec2 tex_coords;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D tex;
+layout(push_constant) uniform Params {
+    vec4 values;
+    vec4 texel;
+} params;
+
+const float weights[5] = float[](0.227027, 0.1945946, 0.1216216, 0.054054, 0.016216);
+
+// values.xy is the direction, values.z the distance between taps in pixels
+void main() {
+    vec2 step = params.values.xy * params.texel.xy * params.values.z;
+    vec4 sum = texture(tex, tex_coords) * weights[0];
+    for (int i = 1; i < 5; i++) {
+        sum += texture(tex, tex_coords + step * float(i)) * weights[i];
+        sum += texture(tex, tex_coords - step * float(i)) * weights[i];
+    }
+    f_color = sum;
+}"
+    }
+}
+
+pub mod post_add {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+#version 450
+layout(location = 0) in vec2 tex_coords;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D tex;
+layout(push_constant) uniform Params {
+    vec4 values;
+    vec4 texel;
+} params;
+
+// values.x scales the color, alpha is left alone so it can be blended additively
+void main() {
+    f_color = vec4(texture(tex, tex_coords).rgb * params.values.x, 0.0);
+}"
+    }
+}
+
+pub mod post_vignette {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+#version 450
+layout(location = 0) in vec2 tex_coords;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D tex;
+layout(push_constant) uniform Params {
+    vec4 values;
+    vec4 texel;
+} params;
+
+// values.x is the intensity, values.y the radius and values.z the softness
+void main() {
+    vec4 c = texture(tex, tex_coords);
+    float d = distance(tex_coords, vec2(0.5)) * 1.4142136;
+    float shade = smoothstep(params.values.y, params.values.y + params.values.z, d);
+    f_color = vec4(c.rgb * (1.0 - shade * params.values.x), c.a);
+}"
+    }
+}
+
+pub mod post_grade {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+#version 450
+layout(location = 0) in vec2 tex_coords;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D tex;
+layout(set = 0, binding = 1) uniform sampler2D lut;
+layout(push_constant) uniform Params {
+    vec4 values;
+    vec4 texel;
+} params;
+
+// values.x is the size of the table, values.y the intensity and values.z is 1.0 when the
+// frame holds linear colors, which have to be encoded to look them up
+void main() {
+    vec4 c = texture(tex, tex_coords);
+    vec3 color = clamp(c.rgb, 0.0, 1.0);
+    if (params.values.z > 0.5) {
+        color = pow(color, vec3(1.0 / 2.2));
+    }
+
+    float n = params.values.x;
+    float blue = color.b * (n - 1.0);
+    float slice = floor(blue);
+    vec2 uv = vec2((color.r * (n - 1.0) + 0.5) / (n * n), (color.g * (n - 1.0) + 0.5) / n);
+    vec3 a = texture(lut, uv + vec2(slice / n, 0.0)).rgb;
+    vec3 b = texture(lut, uv + vec2(min(slice + 1.0, n - 1.0) / n, 0.0)).rgb;
+    vec3 graded = mix(a, b, blue - slice);
+    f_color = vec4(mix(c.rgb, graded, params.values.y), c.a);
+}"
+    }
+}
+
+pub mod post_crt {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+#version 450
+layout(location = 0) in vec2 tex_coords;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D tex;
+layout(push_constant) uniform Params {
+    vec4 values;
+    vec4 texel;
+} params;
+
+// values.x is the scanline intensity and values.y the curvature
+void main() {
+    vec2 centered = tex_coords * 2.0 - 1.0;
+    centered += centered * (centered.yx * centered.yx) * params.values.y;
+    vec2 uv = centered * 0.5 + 0.5;
+    if (uv.x < 0.0 || uv.x > 1.0 || uv.y < 0.0 || uv.y > 1.0) {
+        f_color = vec4(0.0, 0.0, 0.0, 1.0);
+        return;
+    }
+
+    vec4 c = texture(tex, uv);
+    float line = 0.5 + 0.5 * sin(uv.y / params.texel.y * 3.1415927);
+    f_color = vec4(c.rgb * mix(1.0, line, params.values.x), c.a);
+}"
+    }
+}
+
+// Lighting, see render::lighting. Normals are drawn with the sprite vertex shader into a
+// target the lights read from.
+
+pub mod normal_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+#version 450
+layout(location = 0) in vec2 tex_coords;
+layout(location = 1) in vec4 color;
+layout(location = 2) in float premultiplied;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 0, binding = 0) uniform sampler2D tex;
+layout(set = 2, binding = 0) uniform sampler2D normal_map;
+// Where the normal map's x and y axes point once the sprite is flipped and rotated
+layout(push_constant) uniform Orientation {
+    vec4 axes;
+} orientation;
+void main() {
+    vec3 n = texture(normal_map, tex_coords).rgb * 2.0 - 1.0;
+    n.xy = n.x * orientation.axes.xy + n.y * orientation.axes.zw;
+    // The sprite's alpha decides where its normals replace the ones behind it
+    float a = texture(tex, tex_coords).a * color.a;
+    f_color = vec4((n * 0.5 + 0.5) * a, a);
+}"
+    }
+}
+
+pub mod light_vs {
+    vulkano_shaders::shader! {
+        ty: "vertex",
+        src: "
+#version 450
+layout(location = 0) in vec2 position;
+layout(location = 1) in vec4 color;
+
+layout(location = 0) out vec4 v_color;
+layout(location = 1) out vec2 v_world;
+
+layout(set = 0, binding = 0) uniform Camera {
+    mat4 view_projection;
+} camera;
+
+void main() {
+    gl_Position = camera.view_projection * vec4(position, 0.0, 1.0);
+    v_color = color;
+    v_world = position;
+}"
+    }
+}
+
+pub mod light_fs {
+    vulkano_shaders::shader! {
+        ty: "fragment",
+        src: "
+#version 450
+layout(location = 0) in vec4 color;
+layout(location = 1) in vec2 world;
+layout(location = 0) out vec4 f_color;
+
+layout(set = 1, binding = 0) uniform sampler2D normals;
+// How much of the light is blocked, in the red channel
+layout(set = 1, binding = 1) uniform sampler2D shadows;
+
+// render::lighting::LightParams
+layout(push_constant) uniform Params {
+    // x, y, radius, falloff
+    vec4 center;
+    // direction x and y, cosines of the cone's outer and inner edge
+    vec4 spot;
+    // 1 / width and 1 / height of the target, height of the light, 1.0 for spot lights
+    vec4 target;
+} light;
+
+void main() {
+    vec2 to_light = light.center.xy - world;
+    float distance = length(to_light) / light.center.z;
+    if (distance >= 1.0) {
+        discard;
+    }
+    float attenuation = pow(1.0 - distance, light.center.w);
+    if (light.target.w > 0.0 && distance > 0.0) {
+        float cosine = dot(normalize(-to_light), light.spot.xy);
+        attenuation *= smoothstep(light.spot.z, light.spot.w, cosine);
+    }
+
+    vec2 uv = gl_FragCoord.xy * light.target.xy;
+    attenuation *= 1.0 - texture(shadows, uv).r;
+
+    // Normal maps point y up, the world y down
+    vec3 n = texture(normals, uv).rgb * 2.0 - 1.0;
+    n.y = -n.y;
+    vec3 l = normalize(vec3(to_light, light.target.z));
+    float diffuse = max(dot(normalize(n), l), 0.0);
+
+    // Added to the light buffer, alpha stays as the ambient cleared it
+    f_color = vec4(color.rgb * attenuation * diffuse, 0.0);
+}"
+    }
+}
@@ -0,0 +1,88 @@
+use crate::graphics::{Scaling, VirtualResolution};
+
+use std::sync::Arc;
+
+use vulkano::{
+    device::Device,
+    format::Format,
+    framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract},
+    image::AttachmentImage,
+};
+
+// An offscreen color image that can be rendered to and then sampled from.
+pub struct RenderTarget {
+    pub image: Arc<AttachmentImage<Format>>,
+    pub framebuffer: Arc<FramebufferAbstract + Send + Sync>,
+    pub dimensions: [u32; 2],
+}
+
+impl RenderTarget {
+    pub fn new(
+        device: Arc<Device>,
+        render_pass: &Arc<RenderPassAbstract + Send + Sync>,
+        dimensions: [u32; 2],
+        format: Format,
+    ) -> Self {
+        let image = AttachmentImage::sampled(device, dimensions, format).unwrap();
+        let framebuffer = Arc::new(
+            Framebuffer::start(render_pass.clone())
+                .add(image.clone())
+                .unwrap()
+                .build()
+                .unwrap(),
+        ) as Arc<FramebufferAbstract + Send + Sync>;
+
+        RenderTarget {
+            image: image,
+            framebuffer: framebuffer,
+            dimensions: dimensions,
+        }
+    }
+}
+
+// Size the offscreen target needs to be for a window of the given size.
+pub fn target_dimensions(res: &VirtualResolution, window: [u32; 2]) -> [u32; 2] {
+    if res.scaling != Scaling::Expand {
+        return [res.width, res.height];
+    }
+
+    let window_aspect = window[0] as f32 / window[1].max(1) as f32;
+    let aspect = res.width as f32 / res.height as f32;
+    if window_aspect > aspect {
+        [
+            (res.height as f32 * window_aspect).round() as u32,
+            res.height,
+        ]
+    } else {
+        [res.width, (res.width as f32 / window_aspect).round() as u32]
+    }
+}
+
+// Origin and size in pixels of the area of the window the target is drawn to.
+pub fn letterbox(
+    res: &VirtualResolution,
+    target: [u32; 2],
+    window: [u32; 2],
+) -> ([f32; 2], [f32; 2]) {
+    let (tw, th) = (target[0] as f32, target[1] as f32);
+    let (ww, wh) = (window[0] as f32, window[1] as f32);
+
+    let size = match res.scaling {
+        Scaling::Stretch => [ww, wh],
+        Scaling::Integer => {
+            let scale = (ww / tw).min(wh / th).floor().max(1.0);
+            [tw * scale, th * scale]
+        }
+        Scaling::Fit | Scaling::Expand => {
+            let scale = (ww / tw).min(wh / th);
+            [tw * scale, th * scale]
+        }
+    };
+    (
+        [
+            ((ww - size[0]) / 2.0).floor(),
+            ((wh - size[1]) / 2.0).floor(),
+        ],
+        size,
+    )
+}
@@ -0,0 +1,175 @@
+use std::sync::Arc;
+
+use vulkano::{
+    buffer::CpuBufferPool,
+    command_buffer::{AutoCommandBufferBuilder, CommandBuffer},
+    device::{DeviceOwned, Queue},
+    format::Format,
+    image::{Dimensions, ImageLayout, ImageUsage, ImmutableImage, MipmapsCount},
+    sync::GpuFuture,
+};
+
+// Uploads RGBA8 pixels to a sampled image through staging, with a full mipmap chain if mipmaps
+// is set.
+pub fn upload(
+    pixels: &[u8],
+    dimensions: (u32, u32),
+    mipmaps: bool,
+    queue: Arc<Queue>,
+    staging: &CpuBufferPool<u8>,
+) -> (Arc<ImmutableImage<Format>>, Box<GpuFuture + Send + Sync>) {
+    submit(queue, |cb| {
+        record_upload(cb, pixels, dimensions, mipmaps, staging)
+    })
+}
+
+// Builds a command buffer with record and executes it on queue.
+fn submit<F>(
+    queue: Arc<Queue>,
+    record: F,
+) -> (Arc<ImmutableImage<Format>>, Box<GpuFuture + Send + Sync>)
+where
+    F: FnOnce(AutoCommandBufferBuilder) -> (Arc<ImmutableImage<Format>>, AutoCommandBufferBuilder),
+{
+    let cb =
+        AutoCommandBufferBuilder::primary_one_time_submit(queue.device().clone(), queue.family())
+            .unwrap();
+    let (image, cb) = record(cb);
+    let future = cb.build().unwrap().execute(queue).unwrap();
+
+    (image, Box::new(future))
+}
+
+// Records copying RGBA8 pixels to a new sampled image into cb, through a chunk of staging. The
+// image can be used by every queue family of the device, so it may be uploaded on another
+// queue than the one drawing it.
+pub fn record_upload(
+    cb: AutoCommandBufferBuilder,
+    pixels: &[u8],
+    dimensions: (u32, u32),
+    mipmaps: bool,
+    staging: &CpuBufferPool<u8>,
+) -> (Arc<ImmutableImage<Format>>, AutoCommandBufferBuilder) {
+    let levels = if mipmaps {
+        mip_chain(pixels, dimensions)
+    } else {
+        vec![(pixels.to_vec(), dimensions)]
+    };
+    record_levels(cb, levels, Format::R8G8B8A8Srgb, staging)
+}
+
+// Records copying every level of levels to a new image of format into cb.
+fn record_levels(
+    mut cb: AutoCommandBufferBuilder,
+    levels: Vec<(Vec<u8>, (u32, u32))>,
+    format: Format,
+    staging: &CpuBufferPool<u8>,
+) -> (Arc<ImmutableImage<Format>>, AutoCommandBufferBuilder) {
+    let dimensions = levels[0].1;
+    let device = staging.device().clone();
+    let (image, init) = ImmutableImage::uninitialized(
+        device.clone(),
+        Dimensions::Dim2d {
+            width: dimensions.0,
+            height: dimensions.1,
+        },
+        format,
+        MipmapsCount::Specific(levels.len() as u32),
+        ImageUsage {
+            transfer_destination: true,
+            sampled: true,
+            ..ImageUsage::none()
+        },
+        ImageLayout::ShaderReadOnlyOptimal,
+        device.active_queue_families(),
+    )
+    .unwrap();
+
+    let init = Arc::new(init);
+    for (level, (data, (w, h))) in levels.into_iter().enumerate() {
+        let chunk = staging.chunk(data.into_iter()).unwrap();
+        cb = cb
+            .copy_buffer_to_image_dimensions(
+                chunk,
+                init.clone(),
+                [0, 0, 0],
+                [w, h, 1],
+                0,
+                1,
+                level as u32,
+            )
+            .unwrap();
+    }
+
+    (image, cb)
+}
+
+// Uploads RGBA8 pixels that aren't colors, like normal maps, so sampling doesn't decode them
+// from sRGB.
+pub fn upload_linear(
+    pixels: &[u8],
+    dimensions: (u32, u32),
+    queue: Arc<Queue>,
+    staging: &CpuBufferPool<u8>,
+) -> (Arc<ImmutableImage<Format>>, Box<GpuFuture + Send + Sync>) {
+    submit(queue, |cb| {
+        let levels = vec![(pixels.to_vec(), dimensions)];
+        record_levels(cb, levels, Format::R8G8B8A8Unorm, staging)
+    })
+}
+
+// Halves the image with a box filter until it's 1x1, the first level is the image itself.
+// Filtering is done on the stored sRGB values, which is slightly too dark but cheap.
+fn mip_chain(pixels: &[u8], dimensions: (u32, u32)) -> Vec<(Vec<u8>, (u32, u32))> {
+    let mut levels = vec![(pixels.to_vec(), dimensions)];
+    while levels[levels.len() - 1].1 != (1, 1) {
+        let next = {
+            let (ref pixels, dimensions) = levels[levels.len() - 1];
+            halve(pixels, dimensions)
+        };
+        levels.push(next);
+    }
+    levels
+}
+
+// Halves the image until neither side is larger than max_size, for a quick preview of a
+// texture that's still being uploaded.
+pub fn downscale(pixels: &[u8], dimensions: (u32, u32), max_size: u32) -> (Vec<u8>, (u32, u32)) {
+    let mut level = (pixels.to_vec(), dimensions);
+    while level.1 .0 > max_size || level.1 .1 > max_size {
+        level = halve(&level.0, level.1);
+    }
+    level
+}
+
+// Bytes of GPU memory an RGBA8 image takes, a mipmap chain adds about a third.
+pub fn image_bytes(dimensions: (u32, u32), mipmaps: bool) -> u64 {
+    let bytes = dimensions.0 as u64 * dimensions.1 as u64 * 4;
+    if mipmaps {
+        bytes * 4 / 3
+    } else {
+        bytes
+    }
+}
+
+fn halve(src: &[u8], (w, h): (u32, u32)) -> (Vec<u8>, (u32, u32)) {
+    let (nw, nh) = ((w / 2).max(1), (h / 2).max(1));
+    let texel = |x: u32, y: u32, c: u32| {
+        let (x, y) = (x.min(w - 1), y.min(h - 1));
+        src[((y * w + x) * 4 + c) as usize] as u32
+    };
+
+    let mut dst = Vec::with_capacity((nw * nh * 4) as usize);
+    for y in 0..nh {
+        for x in 0..nw {
+            for c in 0..4 {
+                let sum = texel(x * 2, y * 2, c)
+                    + texel(x * 2 + 1, y * 2, c)
+                    + texel(x * 2, y * 2 + 1, c)
+                    + texel(x * 2 + 1, y * 2 + 1, c);
+                dst.push((sum / 4) as u8);
+            }
+        }
+    }
+    (dst, (nw, nh))
+}
@@ -0,0 +1,214 @@
+use crate::graphics::{Color, Sampling};
+use crate::render::sampler::SamplerCache;
+use crate::render::texture;
+use crate::render::vk::{SpritePipeline, Vertex, WaitBuffer};
+use crate::tilemap::{Tile, TileMap};
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use vulkano::{
+    buffer::{BufferUsage, CpuAccessibleBuffer, CpuBufferPool},
+    descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet},
+    device::Queue,
+};
+
+// Map id, layer index and chunk index.
+type ChunkKey = (usize, usize, usize);
+
+// Vertex buffers of the tile chunks and the tilesets' descriptor sets, kept between frames.
+// Tilesets are keyed by their id, so one put in place of another in a map is uploaded anew.
+pub struct TileMapCache {
+    tilesets: HashMap<usize, Arc<DescriptorSet + Send + Sync>>,
+    chunks: HashMap<ChunkKey, Chunk>,
+    draws: Vec<ChunkDraw>,
+}
+
+struct Chunk {
+    revision: u64,
+    // Vertices are in world space, so moving or tinting the map rebuilds them
+    origin: (f32, f32),
+    tint: Color,
+    // One buffer per tileset used in the chunk
+    buffers: Vec<(usize, Arc<CpuAccessibleBuffer<[Vertex]>>)>,
+}
+
+pub struct ChunkDraw {
+    pub layer: i32,
+    pub set: Arc<DescriptorSet + Send + Sync>,
+    pub vertices: Arc<CpuAccessibleBuffer<[Vertex]>>,
+    // World space [x, y, width, height], for culling chunks outside the camera's view
+    pub bounds: [f32; 4],
+}
+
+impl TileMapCache {
+    pub fn new() -> Self {
+        TileMapCache {
+            tilesets: HashMap::new(),
+            chunks: HashMap::new(),
+            draws: Vec::new(),
+        }
+    }
+
+    pub fn draws(&self) -> &[ChunkDraw] {
+        &self.draws
+    }
+
+    // Uploads new tilesets and rebuilds the chunks that changed since the last call.
+    // Everything belonging to maps and tilesets that are gone is dropped.
+    pub fn update(
+        &mut self,
+        maps: &[TileMap],
+        pipeline: &SpritePipeline,
+        samplers: &SamplerCache,
+        default_sampling: Sampling,
+        queue: Arc<Queue>,
+        staging: &CpuBufferPool<u8>,
+        wait_buffer: &WaitBuffer,
+    ) {
+        self.draws.clear();
+        let mut seen = HashSet::new();
+        let mut seen_tilesets = HashSet::new();
+
+        for map in maps {
+            seen.insert(map.id());
+
+            for tileset in &map.tilesets {
+                seen_tilesets.insert(tileset.id());
+                if self.tilesets.contains_key(&tileset.id()) {
+                    continue;
+                }
+                let sampling = tileset.sampling.unwrap_or(default_sampling);
+                let (image, future) = texture::upload(
+                    &tileset.pixels,
+                    tileset.dimensions,
+                    sampling.mipmaps,
+                    queue.clone(),
+                    staging,
+                );
+                let set = Arc::new(
+                    PersistentDescriptorSet::start(pipeline.clone(), 0)
+                        .add_sampled_image(image, samplers.get(sampling))
+                        .unwrap()
+                        .build()
+                        .unwrap(),
+                );
+                self.tilesets.insert(tileset.id(), set);
+                wait_buffer.lock().unwrap().push(future);
+            }
+
+            for (l, layer) in map.layers().iter().enumerate() {
+                if !layer.visible {
+                    continue;
+                }
+                for c in 0..(map.chunks_x() * map.chunks_y()) as usize {
+                    let key = (map.id(), l, c);
+                    let origin = (map.position_x, map.position_y);
+                    let stale = match self.chunks.get(&key) {
+                        Some(chunk) => {
+                            chunk.revision != layer.revision(c)
+                                || chunk.origin != origin
+                                || chunk.tint != layer.tint
+                        }
+                        None => true,
+                    };
+                    if stale {
+                        let chunk = Chunk {
+                            revision: layer.revision(c),
+                            origin: origin,
+                            tint: layer.tint,
+                            buffers: build_chunk(map, l, c, queue.clone()),
+                        };
+                        self.chunks.insert(key, chunk);
+                    }
+
+                    let bounds = map.chunk_bounds(c);
+                    for (tileset, vertices) in &self.chunks[&key].buffers {
+                        let set = map
+                            .tilesets
+                            .get(*tileset)
+                            .and_then(|t| self.tilesets.get(&t.id()));
+                        if let Some(set) = set {
+                            self.draws.push(ChunkDraw {
+                                layer: layer.layer,
+                                set: set.clone(),
+                                vertices: vertices.clone(),
+                                bounds: bounds,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
+        self.tilesets.retain(|id, _| seen_tilesets.contains(id));
+        self.chunks.retain(|k, _| seen.contains(&k.0));
+    }
+}
+
+fn build_chunk(
+    map: &TileMap,
+    layer: usize,
+    chunk: usize,
+    queue: Arc<Queue>,
+) -> Vec<(usize, Arc<CpuAccessibleBuffer<[Vertex]>>)> {
+    let tint = map.layers()[layer].tint;
+    let mut by_tileset: HashMap<usize, Vec<Vertex>> = HashMap::new();
+
+    for (x, y, tile) in map.chunk_tiles(layer, chunk) {
+        let tileset = match map.tilesets.get(tile.tileset) {
+            Some(t) => t,
+            None => continue,
+        };
+        let (w, h) = (tileset.tile_width as f32, tileset.tile_height as f32);
+        // Tiles are anchored to the bottom-left corner of their cell
+        let left = map.position_x + x as f32 * map.tile_width;
+        let top = map.position_y + (y + 1) as f32 * map.tile_height - h;
+
+        by_tileset
+            .entry(tile.tileset)
+            .or_insert_with(Vec::new)
+            .extend_from_slice(&Vertex::quad_uv(
+                left,
+                top,
+                w,
+                h,
+                tile_uvs(tileset.uv(tile.index), tile),
+                tint,
+            ));
+    }
+
+    let mut buffers: Vec<_> = by_tileset
+        .into_iter()
+        .map(|(tileset, vertices)| {
+            let buffer = CpuAccessibleBuffer::from_iter(
+                queue.device().clone(),
+                BufferUsage::vertex_buffer(),
+                vertices.into_iter(),
+            )
+            .unwrap();
+            (tileset, buffer)
+        })
+        .collect();
+    buffers.sort_by_key(|b| b.0);
+    buffers
+}
+
+// Texture coordinates of the top-left, bottom-left, top-right and bottom-right corners.
+fn tile_uvs(uv: [f32; 4], tile: Tile) -> [[f32; 2]; 4] {
+    let [u0, v0, u1, v1] = uv;
+    let (mut tl, mut bl, mut tr, mut br) = ([u0, v0], [u0, v1], [u1, v0], [u1, v1]);
+    // Same order as Tiled: diagonal first, then horizontal and vertical
+    if tile.flip_diagonal {
+        std::mem::swap(&mut bl, &mut tr);
+    }
+    if tile.flip_x {
+        std::mem::swap(&mut tl, &mut tr);
+        std::mem::swap(&mut bl, &mut br);
+    }
+    if tile.flip_y {
+        std::mem::swap(&mut tl, &mut bl);
+        std::mem::swap(&mut tr, &mut br);
+    }
+    [tl, bl, tr, br]
+}
@@ -0,0 +1,150 @@
+use crate::entity::{Rect, SpriteStyle};
+use crate::graphics::Sampling;
+use crate::render::sampler::SamplerCache;
+use crate::render::texture;
+use crate::render::vk::{Draw, DrawBuffer, SpritePipeline};
+
+use std::collections::VecDeque;
+use std::sync::{mpsc::Receiver, Arc, Mutex};
+
+use vulkano::{
+    buffer::CpuBufferPool,
+    command_buffer::{AutoCommandBufferBuilder, CommandBuffer},
+    descriptor::descriptor_set::PersistentDescriptorSet,
+    device::Queue,
+    sync::GpuFuture,
+};
+
+// Most textures one command buffer uploads, the rest wait for the next one.
+const MAX_BATCH: usize = 32;
+// Textures larger than this are streamed in, a preview at most PREVIEW_SIZE pixels wide and
+// high is shown until the whole texture has been uploaded.
+const STREAM_BYTES: u64 = 4 * 1024 * 1024;
+const PREVIEW_SIZE: u32 = 128;
+
+// A texture waiting to be uploaded by an upload thread.
+pub struct Upload {
+    pub id: usize,
+    pub pixels: Vec<u8>,
+    pub dimensions: (u32, u32),
+    pub rect: Rect,
+    pub style: SpriteStyle,
+    pub sampling: Sampling,
+}
+
+// Uploads the textures of connected sprites on queue, which is the transfer queue when the
+// device has one. Every upload waiting when the thread wakes up is copied in one command buffer
+// through the thread's staging pool. A texture's Draw is only added to the draw buffer once
+// the copy has finished, so the present loop never waits on an upload. Large textures get a
+// preview first and are uploaded whole one at a time, when no other uploads are waiting.
+pub fn spawn_upload_thread(
+    img_recv: Arc<Mutex<Receiver<Upload>>>,
+    queue: Arc<Queue>,
+    pipeline: SpritePipeline,
+    draw_buffer: DrawBuffer,
+    samplers: Arc<SamplerCache>,
+) {
+    std::thread::spawn(move || {
+        let staging = CpuBufferPool::upload(queue.device().clone());
+        let mut streaming: VecDeque<Upload> = VecDeque::new();
+        loop {
+            let mut uploads = {
+                let recv = img_recv.lock().unwrap();
+                let mut uploads = Vec::new();
+                if streaming.is_empty() {
+                    uploads.push(recv.recv().unwrap());
+                }
+                while uploads.len() < MAX_BATCH {
+                    match recv.try_recv() {
+                        Ok(upload) => uploads.push(upload),
+                        Err(_) => break,
+                    }
+                }
+                uploads
+            };
+
+            // (upload, pixels and dimensions to upload now, whether it's the whole texture)
+            let mut batch = Vec::with_capacity(uploads.len());
+            if uploads.is_empty() {
+                let upload = streaming.pop_front().unwrap();
+                batch.push((upload, None));
+            }
+            for upload in uploads.drain(..) {
+                let mipmaps = upload.sampling.mipmaps;
+                if texture::image_bytes(upload.dimensions, mipmaps) > STREAM_BYTES {
+                    let preview =
+                        texture::downscale(&upload.pixels, upload.dimensions, PREVIEW_SIZE);
+                    streaming.push_back(Upload {
+                        pixels: upload.pixels.clone(),
+                        rect: upload.rect.clone(),
+                        style: upload.style.clone(),
+                        ..upload
+                    });
+                    batch.push((upload, Some(preview)));
+                } else {
+                    batch.push((upload, None));
+                }
+            }
+
+            let mut cb = AutoCommandBufferBuilder::primary_one_time_submit(
+                queue.device().clone(),
+                queue.family(),
+            )
+            .unwrap();
+            let mut images = Vec::with_capacity(batch.len());
+            for (upload, preview) in &batch {
+                let (pixels, dimensions) = match *preview {
+                    Some((ref pixels, dimensions)) => (pixels, dimensions),
+                    None => (&upload.pixels, upload.dimensions),
+                };
+                let (image, next) = texture::record_upload(
+                    cb,
+                    pixels,
+                    dimensions,
+                    upload.sampling.mipmaps,
+                    &staging,
+                );
+                cb = next;
+                images.push((
+                    image,
+                    texture::image_bytes(dimensions, upload.sampling.mipmaps),
+                ));
+            }
+            cb.build()
+                .unwrap()
+                .execute(queue.clone())
+                .unwrap()
+                .then_signal_fence_and_flush()
+                .unwrap()
+                .wait(None)
+                .unwrap();
+
+            // Textures uploaded again after being evicted, or in whole after their preview,
+            // replace the set of their existing Draw
+            let mut draws = draw_buffer.lock().unwrap();
+            for ((upload, _), (image, bytes)) in batch.into_iter().zip(images) {
+                let set = Arc::new(
+                    PersistentDescriptorSet::start(pipeline.clone(), 0)
+                        .add_sampled_image(image, samplers.get(upload.sampling))
+                        .unwrap()
+                        .build()
+                        .unwrap(),
+                );
+                match draws.iter_mut().find(|d| d.id == upload.id) {
+                    Some(draw) => {
+                        draw.set = Some(set);
+                        draw.bytes = bytes;
+                    }
+                    None => draws.push(Draw {
+                        id: upload.id,
+                        set: Some(set),
+                        rect: Arc::new(upload.rect),
+                        style: Arc::new(upload.style),
+                        dimensions: upload.dimensions,
+                        bytes: bytes,
+                    }),
+                }
+            }
+        }
+    });
+}
@@ -1,83 +1,781 @@
-use crate::entity::Rect;
+use crate::camera::Camera2D;
+use crate::canvas::{Command, Space};
+use crate::entity::{Rect, SpriteStyle, Texture};
+use crate::graphics;
+use crate::lighting::Lighting;
+use crate::material::Material;
+use crate::parallax::ParallaxLayer;
+use crate::particles::Emitter;
+use crate::postprocess::Effect;
+use crate::render::capture::FrameCopy;
+use crate::render::pipeline;
+use crate::render::post;
+use crate::render::rendertexture::TexturePass;
 use crate::render::shader;
+use crate::render::target;
 use crate::render::vkinit::VkSession;
+use crate::rendertexture::RenderTexture;
+use crate::tilemap::TileMap;
 
+use std::cmp::Ordering;
+use std::collections::HashMap;
 use std::slice::Iter;
-use std::sync::{mpsc::Receiver, Arc, Mutex};
+use std::sync::{Arc, Mutex};
 
 use vulkano::{
     buffer::{BufferUsage, CpuAccessibleBuffer},
     command_buffer::{AutoCommandBufferBuilder, DynamicState},
-    descriptor::descriptor_set::{DescriptorSet, PersistentDescriptorSet},
-    device::{Device, Queue},
+    descriptor::{
+        descriptor_set::{DescriptorSet, PersistentDescriptorSet},
+        PipelineLayoutAbstract,
+    },
+    device::Device,
     framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract, Subpass},
-    image::{Dimensions, ImmutableImage, SwapchainImage},
+    image::SwapchainImage,
     pipeline::{vertex::SingleBufferDefinition, viewport::Viewport, GraphicsPipeline},
-    sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
     swapchain,
-    swapchain::Swapchain,
-    sync::GpuFuture,
+    swapchain::{AcquireError, Swapchain, SwapchainCreationError},
+    sync::{FlushError, GpuFuture},
 };
 
-pub type DrawBuffer = Arc<Mutex<Vec<(Arc<DescriptorSet + Send + Sync>, Arc<Rect>, (u32, u32))>>>;
+pub type DrawBuffer = Arc<Mutex<Vec<Draw>>>;
+pub type SpritePipeline = Arc<
+    GraphicsPipeline<
+        SingleBufferDefinition<Vertex>,
+        Box<vulkano::descriptor::PipelineLayoutAbstract + Send + Sync>,
+        Arc<RenderPassAbstract + Send + Sync>,
+    >,
+>;
+pub type ShapePipeline = Arc<
+    GraphicsPipeline<
+        SingleBufferDefinition<ShapeVertex>,
+        Box<vulkano::descriptor::PipelineLayoutAbstract + Send + Sync>,
+        Arc<RenderPassAbstract + Send + Sync>,
+    >,
+>;
 pub type WaitBuffer = Arc<Mutex<Vec<Box<vulkano::sync::GpuFuture + Send + Sync>>>>;
 
+// A texture that's been uploaded and is ready to be drawn.
+// id is the index of the texture in Game::textures, which also breaks ties when sorting.
+// set is None while the texture is evicted, bytes is its size on the GPU.
+#[derive(Clone)]
+pub struct Draw {
+    pub id: usize,
+    pub set: Option<Arc<DescriptorSet + Send + Sync>>,
+    pub rect: Arc<Rect>,
+    pub style: Arc<SpriteStyle>,
+    pub dimensions: (u32, u32),
+    pub bytes: u64,
+}
+
+// Orders draws back to front: by layer, then by the bottom edge of the Rect for layers in
+// y_sorted, then by id. The sort is stable so the result doesn't depend on upload order.
+pub fn sort_draws(draws: &mut Vec<Draw>, y_sorted: &[i32]) {
+    draws.sort_by(|a, b| {
+        a.style
+            .layer
+            .cmp(&b.style.layer)
+            .then_with(|| {
+                if y_sorted.contains(&a.style.layer) {
+                    let bottom = |r: &Rect| r.position_y + r.height;
+                    bottom(&a.rect)
+                        .partial_cmp(&bottom(&b.rect))
+                        .unwrap_or(Ordering::Equal)
+                } else {
+                    Ordering::Equal
+                }
+            })
+            .then(a.id.cmp(&b.id))
+    });
+}
+
 #[derive(Debug, Clone)]
 pub struct Vertex {
     position: [f32; 2],
-    window_dimensions: [f32; 2],
-    image_dimensions: [f32; 2],
+    tex_coords: [f32; 2],
+    color: [f32; 4],
+    // 1.0 when the texture's colors are already multiplied by its alpha
+    premultiplied: f32,
+}
+vulkano::impl_vertex!(Vertex, position, tex_coords, color, premultiplied);
+
+// Vertex of the untextured pipeline drawing canvas shapes.
+#[derive(Debug, Clone)]
+pub struct ShapeVertex {
+    position: [f32; 2],
+    color: [f32; 4],
+}
+vulkano::impl_vertex!(ShapeVertex, position, color);
+
+impl ShapeVertex {
+    pub fn new(position: [f32; 2], color: graphics::Color) -> Self {
+        ShapeVertex {
+            position: position,
+            color: color.to_array(),
+        }
+    }
 }
-vulkano::impl_vertex!(Vertex, position, window_dimensions, image_dimensions);
 
 impl Vertex {
-    pub fn from(r: Rect, window: [u32; 2], image: (u32, u32)) -> [Vertex; 4] {
-        //let (x, y) = (r.position_x * 2.0 - 1.0, r.position_y * 2.0 - 1.0);
-        let (x, y) = (r.position_x, r.position_y);
+    // Builds the two triangles of r drawn with style in world space, the camera takes care of
+    // the rest. dimensions is the size of the texture style's region is in.
+    pub fn from(r: &Rect, style: &SpriteStyle, dimensions: (u32, u32)) -> [Vertex; 6] {
+        let t = &style.transform;
+        let premultiplied = style.blend == graphics::BlendMode::Premultiplied;
+        let tint = &style.tint;
+        let color = if premultiplied {
+            [tint.r * tint.a, tint.g * tint.a, tint.b * tint.a, tint.a]
+        } else {
+            tint.to_array()
+        };
 
-        let (i_x, i_y) = (image.0 as f32, image.1 as f32);
+        let [u0, v0, u1, v1] = match style.region {
+            Some(region) => {
+                let (w, h) = (dimensions.0 as f32, dimensions.1 as f32);
+                [
+                    region.x as f32 / w,
+                    region.y as f32 / h,
+                    (region.x + region.width) as f32 / w,
+                    (region.y + region.height) as f32 / h,
+                ]
+            }
+            None => [0.0, 0.0, 1.0, 1.0],
+        };
 
-        [
-            Vertex {
-                // Top-Left
-                position: [x, y],
-                image_dimensions: [i_x, i_y],
-                window_dimensions: [window[0] as f32, window[1] as f32],
-            },
-            Vertex {
-                // Bottom-Left
-                position: [x, y + (r.height * 2.0 / 1.0)],
-                image_dimensions: [i_x, i_y],
-                window_dimensions: [window[0] as f32, window[1] as f32],
-            },
+        let corner = |cx: f32, cy: f32| {
+            let (dx, dy) = t.apply(cx, cy, r.width, r.height);
+            let u = if t.flip_x { 1.0 - cx } else { cx };
+            let v = if t.flip_y { 1.0 - cy } else { cy };
             Vertex {
-                // Top-Right
-                position: [x + (r.width * 2.0 / 1.0), y],
-                image_dimensions: [i_x, i_y],
-                window_dimensions: [window[0] as f32, window[1] as f32],
-            },
-            Vertex {
-                // Bottom-Right
-                position: [x + (r.width * 2.0 / 1.0), y + (r.height * 2.0 / 1.0)],
-                image_dimensions: [i_x, i_y],
-                window_dimensions: [window[0] as f32, window[1] as f32],
-            },
+                position: [r.position_x + dx, r.position_y + dy],
+                tex_coords: [u0 + u * (u1 - u0), v0 + v * (v1 - v0)],
+                color: color,
+                premultiplied: if premultiplied { 1.0 } else { 0.0 },
+            }
+        };
+
+        let (tl, bl, tr, br) = (
+            corner(0.0, 0.0),
+            corner(0.0, 1.0),
+            corner(1.0, 0.0),
+            corner(1.0, 1.0),
+        );
+        [tl, bl.clone(), tr.clone(), tr, bl, br]
+    }
+
+    // Marks vertices as showing a texture that's already multiplied by its alpha, multiplying
+    // their tint by its alpha too like Vertex::from does.
+    pub fn premultiply(vertices: &mut [Vertex]) {
+        for v in vertices.iter_mut() {
+            let [r, g, b, a] = v.color;
+            v.color = [r * a, g * a, b * a, a];
+            v.premultiplied = 1.0;
+        }
+    }
+
+    // An axis-aligned quad showing the part uv = [u0, v0, u1, v1] of a texture.
+    pub fn quad(
+        x: f32,
+        y: f32,
+        w: f32,
+        h: f32,
+        uv: [f32; 4],
+        color: graphics::Color,
+    ) -> [Vertex; 6] {
+        let [u0, v0, u1, v1] = uv;
+        Vertex::quad_uv(x, y, w, h, [[u0, v0], [u0, v1], [u1, v0], [u1, v1]], color)
+    }
+
+    // Like quad, with the texture coordinates of the top-left, bottom-left, top-right and
+    // bottom-right corners given separately so the texture can be flipped or rotated.
+    pub fn quad_uv(
+        x: f32,
+        y: f32,
+        w: f32,
+        h: f32,
+        uvs: [[f32; 2]; 4],
+        color: graphics::Color,
+    ) -> [Vertex; 6] {
+        let corner = |cx: f32, cy: f32, uv: [f32; 2]| Vertex {
+            position: [x + cx * w, y + cy * h],
+            tex_coords: uv,
+            color: color.to_array(),
+            premultiplied: 0.0,
+        };
+        let (tl, bl, tr, br) = (
+            corner(0.0, 0.0, uvs[0]),
+            corner(0.0, 1.0, uvs[1]),
+            corner(1.0, 0.0, uvs[2]),
+            corner(1.0, 1.0, uvs[3]),
+        );
+        [tl, bl.clone(), tr.clone(), tr, bl, br]
+    }
+
+    // A quad covering the whole viewport, used to draw render targets.
+    pub fn fullscreen() -> [Vertex; 4] {
+        let corner = |x: f32, y: f32| Vertex {
+            position: [x * 2.0 - 1.0, y * 2.0 - 1.0],
+            tex_coords: [x, y],
+            color: [1.0; 4],
+            premultiplied: 0.0,
+        };
+        [
+            corner(0.0, 0.0),
+            corner(0.0, 1.0),
+            corner(1.0, 0.0),
+            corner(1.0, 1.0),
         ]
     }
 }
 
 impl VkSession {
-    pub fn update_swapchain(&mut self, dimensions: [u32; 2]) {
-        let (dynamic_state, framebuffers, pipeline) = recreate_dimensions_dependent(
-            self.device.clone(),
+    // Recreates the swapchain at the window's new size. On an error the old swapchain is kept
+    // and recreating can be tried again, e.g. while the window is still being resized.
+    pub fn update_swapchain(&mut self, dimensions: [u32; 2]) -> Result<(), SwapchainCreationError> {
+        // Pipelines only depend on the render pass, which keeps its format, so they're kept
+        self.framebuffers = recreate_dimensions_dependent(
             dimensions,
             &mut self.swapchain,
             &mut self.sc_images,
             &mut self.render_pass,
+        )?;
+
+        // Only the Expand scaling mode depends on the window's size
+        let res = match self.virtual_resolution {
+            Some((res, ref target))
+                if target::target_dimensions(&res, dimensions) != target.dimensions =>
+            {
+                Some(res)
+            }
+            _ => None,
+        };
+        if res.is_some() {
+            self.set_virtual_resolution(res);
+        }
+        Ok(())
+    }
+
+    // Pipeline variants for the other blend modes are built the first time they're needed.
+    pub fn sprite_pipeline(&self, blend: graphics::BlendMode) -> SpritePipeline {
+        if blend == graphics::BlendMode::Alpha {
+            return self.pipeline.clone();
+        }
+        self.blend_pipelines
+            .lock()
+            .unwrap()
+            .entry(blend)
+            .or_insert_with(|| {
+                pipeline::sprite_pipeline(self.device.clone(), &self.render_pass, blend)
+            })
+            .clone()
+    }
+
+    // Renders the scene into an offscreen target of a fixed size which is then scaled to fit
+    // the window, or straight to the window when res is None.
+    pub fn set_virtual_resolution(&mut self, res: Option<graphics::VirtualResolution>) {
+        self.virtual_resolution = res.map(|res| {
+            let target = target::RenderTarget::new(
+                self.device.clone(),
+                &self.render_pass,
+                target::target_dimensions(&res, self.swapchain.dimensions()),
+                self.swapchain.format(),
+            );
+            (res, target)
+        });
+    }
+
+    // Descriptor set with the camera's uniform for binding 0 of the pipeline's set.
+    fn camera_set<L>(
+        &self,
+        pipeline: L,
+        set: usize,
+        camera: &Camera2D,
+        dimensions: [u32; 2],
+    ) -> Arc<DescriptorSet + Send + Sync>
+    where
+        L: PipelineLayoutAbstract + Send + Sync + 'static,
+    {
+        Arc::new(
+            PersistentDescriptorSet::start(pipeline, set)
+                .add_buffer(
+                    self.camera_pool
+                        .next(shader::vs::ty::Camera {
+                            view_projection: camera.view_projection(dimensions),
+                        })
+                        .unwrap(),
+                )
+                .unwrap()
+                .build()
+                .unwrap(),
+        )
+    }
+
+    // Camera sets, viewport and visible bounds of a camera drawing to a target of the given
+    // size.
+    fn view(&self, camera: &Camera2D, dimensions: [u32; 2]) -> View {
+        let (origin, size) = camera.viewport.to_pixels(dimensions);
+        View {
+            sprite_set: self.camera_set(self.pipeline.clone(), 1, camera, dimensions),
+            shape_set: self.camera_set(self.shape_pipeline.clone(), 0, camera, dimensions),
+            state: viewport_state(origin, size),
+            bounds: visible_bounds(camera, dimensions),
+        }
+    }
+
+    // Merges sprites and canvas commands into draw calls ordered by layer. Canvas commands are
+    // drawn over sprites on the same layer. Text adds its glyphs to the glyph atlas, which the
+    // frame has to copy to the GPU before drawing, see GlyphCache::record_upload.
+    fn batches(
+        &self,
+        draws: &[Draw],
+        commands: &[Command],
+        camera: &Camera2D,
+        view: [f32; 4],
+    ) -> Vec<Batch> {
+        // Backgrounds go first, then tiles, so sprites on the same layer stand on top of them
+        let mut batches: Vec<Batch> = self
+            .parallax
+            .lock()
+            .unwrap()
+            .vertices(camera.position_x, camera.position_y, view)
+            .into_iter()
+            .map(|(layer, set, vertices)| Batch {
+                layer: layer,
+                space: Space::World,
+                kind: BatchKind::Sprite {
+                    pipeline: self.pipeline.clone(),
+                    set: set,
+                    vertices: vertices,
+                },
+            })
+            .collect();
+        batches.extend(
+            self.tilemaps
+                .lock()
+                .unwrap()
+                .draws()
+                .iter()
+                .filter(|chunk| overlaps(chunk.bounds, view))
+                .map(|chunk| Batch {
+                    layer: chunk.layer,
+                    space: Space::World,
+                    kind: BatchKind::Chunk {
+                        set: chunk.set.clone(),
+                        vertices: chunk.vertices.clone(),
+                    },
+                }),
+        );
+        let mut materials = self.materials.lock().unwrap();
+        let render_textures = self.render_textures.lock().unwrap();
+        let mut residency = self.residency.lock().unwrap();
+        // A material's parameters are uploaded once per frame, however many sprites use it
+        let mut material_sets = HashMap::new();
+        for draw in draws {
+            if !overlaps(rect_bounds(&draw.rect, &draw.style), view) {
+                continue;
+            }
+            let mut style = (*draw.style).clone();
+            // Render textures hold premultiplied colors. Sprites showing one that hasn't been
+            // drawn yet are left out.
+            let set = match style.render_texture {
+                Some(id) => match render_textures.descriptor_set(id.0) {
+                    Some(set) => {
+                        if style.blend == graphics::BlendMode::Alpha {
+                            style.blend = graphics::BlendMode::Premultiplied;
+                        }
+                        set
+                    }
+                    None => continue,
+                },
+                None => match draw.set {
+                    Some(ref set) => {
+                        residency.drawn(draw.id);
+                        set.clone()
+                    }
+                    None => {
+                        residency.want(draw.id);
+                        continue;
+                    }
+                },
+            };
+            let blend = style.blend;
+            // Render textures are shown whole
+            if style.render_texture.is_some() {
+                style.region = None;
+            }
+            let vertices = Vertex::from(&draw.rect, &style, draw.dimensions).to_vec();
+            let kind = match style.material {
+                Some(id) if materials.contains(id.0) => {
+                    let pipeline =
+                        materials.pipeline(id.0, blend, self.device.clone(), &self.render_pass);
+                    let material_set = material_sets
+                        .entry(id.0)
+                        .or_insert_with(|| materials.descriptor_set(id.0, pipeline.clone()))
+                        .clone();
+                    BatchKind::Material {
+                        pipeline: pipeline,
+                        set: set,
+                        material_set: material_set,
+                        vertices: vertices,
+                    }
+                }
+                _ => BatchKind::Sprite {
+                    pipeline: self.sprite_pipeline(blend),
+                    set: set,
+                    vertices: vertices,
+                },
+            };
+            batches.push(Batch {
+                layer: style.layer,
+                space: Space::World,
+                kind: kind,
+            });
+        }
+        drop(materials);
+        drop(render_textures);
+        drop(residency);
+        // Effects on the same layer as a sprite are drawn over it
+        batches.extend(
+            self.particles
+                .lock()
+                .unwrap()
+                .draws()
+                .iter()
+                .map(|draw| Batch {
+                    layer: draw.layer,
+                    space: Space::World,
+                    kind: BatchKind::Sprite {
+                        pipeline: self.sprite_pipeline(draw.blend),
+                        set: draw.set.clone(),
+                        vertices: draw.vertices.clone(),
+                    },
+                }),
+        );
+
+        // Every return goes through merge_shapes, which puts the batches in layer order
+        if commands.is_empty() {
+            return merge_shapes(batches);
+        }
+
+        let mut glyphs = self.glyphs.lock().unwrap();
+        let mut texts = Vec::new();
+        for command in commands {
+            match *command {
+                Command::Text {
+                    ref font,
+                    ref text,
+                    x,
+                    y,
+                    ref style,
+                    layer,
+                    space,
+                } => texts.push((layer, space, (&**font, text.as_str(), x, y, style))),
+                Command::Shape {
+                    ref triangles,
+                    color,
+                    layer,
+                    space,
+                } => batches.push(Batch {
+                    layer: layer,
+                    space: space,
+                    kind: BatchKind::Shape(
+                        triangles
+                            .iter()
+                            .map(|&position| ShapeVertex {
+                                position: position,
+                                color: color.to_array(),
+                            })
+                            .collect(),
+                    ),
+                }),
+            }
+        }
+        if texts.is_empty() {
+            return merge_shapes(batches);
+        }
+        let layouts: Vec<_> = texts.iter().map(|t| t.2).collect();
+        let vertices = glyphs.layout_frame(&layouts);
+        let set = glyphs.descriptor_set(
+            &self.pipeline,
+            self.samplers.get(graphics::Sampling {
+                wrap: graphics::Wrap::Clamp,
+                ..graphics::Sampling::default()
+            }),
+            self.queue.clone(),
+        );
+        for ((layer, space, _), vertices) in
+            texts.into_iter().zip(vertices).filter(|t| !t.1.is_empty())
+        {
+            batches.push(Batch {
+                layer: layer,
+                space: space,
+                kind: BatchKind::Sprite {
+                    pipeline: self.pipeline.clone(),
+                    set: set.clone(),
+                    vertices: vertices,
+                },
+            });
+        }
+        merge_shapes(batches)
+    }
+
+    // Brings the chunk buffers up to date with the game's tile maps, see TileMapCache.
+    pub fn update_tilemaps(
+        &self,
+        maps: &[TileMap],
+        default_sampling: graphics::Sampling,
+        wait_buffer: &WaitBuffer,
+    ) {
+        self.tilemaps.lock().unwrap().update(
+            maps,
+            &self.pipeline,
+            &self.samplers,
+            default_sampling,
+            self.queue.clone(),
+            &self.staging,
+            wait_buffer,
         );
-        self.framebuffers = framebuffers;
-        self.dynamic_state = dynamic_state;
-        self.pipeline = pipeline;
+    }
+
+    // Uploads the textures of new parallax layers, see ParallaxCache.
+    pub fn update_parallax(
+        &self,
+        layers: &[ParallaxLayer],
+        default_sampling: graphics::Sampling,
+        wait_buffer: &WaitBuffer,
+    ) {
+        self.parallax.lock().unwrap().update(
+            layers,
+            &self.pipeline,
+            &self.samplers,
+            default_sampling,
+            self.queue.clone(),
+            &self.staging,
+            wait_buffer,
+        );
+    }
+
+    // Creates the shader modules and uploads the textures of new materials, and takes in the
+    // current parameters of all of them, see MaterialCache.
+    pub fn update_materials(
+        &self,
+        materials: &[Material],
+        default_sampling: graphics::Sampling,
+        wait_buffer: &WaitBuffer,
+    ) {
+        self.materials.lock().unwrap().update(
+            materials,
+            &self.samplers,
+            default_sampling,
+            self.queue.clone(),
+            &self.staging,
+            wait_buffer,
+        );
+    }
+
+    // Takes in the game's post-processing effects, see PostChain.
+    pub fn update_post_effects(&self, effects: &[Effect], wait_buffer: &WaitBuffer) {
+        self.post
+            .lock()
+            .unwrap()
+            .update(effects, self.queue.clone(), &self.staging, wait_buffer);
+    }
+
+    // Builds the vertices of every emitter's particles, see ParticleCache.
+    pub fn update_particles(
+        &self,
+        emitters: &[Emitter],
+        default_sampling: graphics::Sampling,
+        wait_buffer: &WaitBuffer,
+    ) {
+        self.particles.lock().unwrap().update(
+            emitters,
+            &self.pipeline,
+            &self.samplers,
+            default_sampling,
+            self.queue.clone(),
+            &self.staging,
+            wait_buffer,
+        );
+    }
+
+    // Takes in the game's lights and uploads new normal maps, see LightCache.
+    pub fn update_lighting(
+        &self,
+        lighting: &Lighting,
+        textures: &[Texture],
+        default_sampling: graphics::Sampling,
+        wait_buffer: &WaitBuffer,
+    ) {
+        self.lights.lock().unwrap().update(
+            lighting,
+            textures,
+            &self.samplers,
+            default_sampling,
+            self.queue.clone(),
+            &self.staging,
+            wait_buffer,
+        );
+    }
+
+    // Bytes of sprite textures allowed on the GPU at once, None for no limit.
+    pub fn set_sprite_budget(&self, budget: Option<u64>) {
+        self.residency.lock().unwrap().set_budget(budget);
+    }
+
+    // Evicts sprite textures over the budget and reports what's left, see Residency. Returns
+    // the textures that came into view while evicted, for the game to upload again.
+    pub fn update_residency(&self, draws: &mut [Draw]) -> (Vec<usize>, graphics::SpriteMemory) {
+        self.residency.lock().unwrap().end_frame(draws)
+    }
+
+    // Creates the images of new render textures and takes in the cameras of all of them, see
+    // RenderTextureCache.
+    pub fn update_render_textures(
+        &self,
+        textures: &[RenderTexture],
+        default_sampling: graphics::Sampling,
+    ) {
+        self.render_textures.lock().unwrap().update(
+            textures,
+            self.device.clone(),
+            &self.render_pass,
+            self.swapchain.format(),
+            &self.pipeline,
+            &self.samplers,
+            default_sampling,
+        );
+    }
+
+    // Draws the world as seen by a render texture's camera into its image. Sprites showing a
+    // render texture aren't part of draws, a texture can't show itself.
+    fn draw_render_texture(
+        &self,
+        mut command_buffer: AutoCommandBufferBuilder,
+        pass: TexturePass,
+        draws: &[Draw],
+    ) -> AutoCommandBufferBuilder {
+        let view = self.view(&pass.camera, pass.dimensions);
+        let batches = self.batches(draws, &[], &pass.camera, view.bounds);
+
+        command_buffer = command_buffer
+            .begin_render_pass(
+                pass.framebuffer.clone(),
+                false,
+                vec![pass.clear_color.to_array().into()],
+            )
+            .unwrap();
+        for batch in batches
+            .into_iter()
+            .filter(|b| pass.layers.contains(b.layer))
+        {
+            command_buffer = self.draw_batch(
+                command_buffer,
+                batch,
+                &view.state,
+                &view.sprite_set,
+                &view.shape_set,
+            );
+        }
+        command_buffer.end_render_pass().unwrap()
+    }
+
+    fn draw_batch(
+        &self,
+        command_buffer: AutoCommandBufferBuilder,
+        batch: Batch,
+        state: &DynamicState,
+        camera_set: &Arc<DescriptorSet + Send + Sync>,
+        shape_set: &Arc<DescriptorSet + Send + Sync>,
+    ) -> AutoCommandBufferBuilder {
+        match batch.kind {
+            BatchKind::Sprite {
+                pipeline,
+                set,
+                vertices,
+            } => {
+                let vertex_buffer = CpuAccessibleBuffer::<[Vertex]>::from_iter(
+                    self.device.clone(),
+                    BufferUsage::all(),
+                    vertices.into_iter(),
+                )
+                .unwrap();
+                command_buffer
+                    .draw(
+                        pipeline,
+                        state,
+                        vertex_buffer,
+                        (set, camera_set.clone()),
+                        (),
+                    )
+                    .unwrap()
+            }
+            BatchKind::Material {
+                pipeline,
+                set,
+                material_set,
+                vertices,
+            } => {
+                let vertex_buffer = CpuAccessibleBuffer::<[Vertex]>::from_iter(
+                    self.device.clone(),
+                    BufferUsage::all(),
+                    vertices.into_iter(),
+                )
+                .unwrap();
+                command_buffer
+                    .draw(
+                        pipeline,
+                        state,
+                        vertex_buffer,
+                        (set, camera_set.clone(), material_set),
+                        (),
+                    )
+                    .unwrap()
+            }
+            BatchKind::Chunk { set, vertices } => command_buffer
+                .draw(
+                    self.pipeline.clone(),
+                    state,
+                    vertices,
+                    (set, camera_set.clone()),
+                    (),
+                )
+                .unwrap(),
+            BatchKind::Clear(vertices) => {
+                let vertex_buffer = CpuAccessibleBuffer::<[ShapeVertex]>::from_iter(
+                    self.device.clone(),
+                    BufferUsage::all(),
+                    vertices.into_iter(),
+                )
+                .unwrap();
+                command_buffer
+                    .draw(
+                        self.clear_pipeline.clone(),
+                        state,
+                        vertex_buffer,
+                        shape_set.clone(),
+                        (),
+                    )
+                    .unwrap()
+            }
+            BatchKind::Shape(vertices) => {
+                let vertex_buffer = CpuAccessibleBuffer::<[ShapeVertex]>::from_iter(
+                    self.device.clone(),
+                    BufferUsage::all(),
+                    vertices.into_iter(),
+                )
+                .unwrap();
+                command_buffer
+                    .draw(
+                        self.shape_pipeline.clone(),
+                        state,
+                        vertex_buffer,
+                        shape_set.clone(),
+                        (),
+                    )
+                    .unwrap()
+            }
+        }
     }
 
     pub fn present(
@@ -85,13 +783,19 @@ impl VkSession {
         mut previous_frame_end: Box<GpuFuture + Sync + Send>,
         draw_buffer: DrawBuffer,
         wait_buffer: WaitBuffer,
-    ) -> (Box<GpuFuture + Sync + Send>, bool) {
+        cameras: &[Camera2D],
+        commands: &[Command],
+        captures: Vec<String>,
+    ) -> Result<(Box<GpuFuture + Sync + Send>, Frame), String> {
+        // Vulkano 0.11 doesn't report suboptimal swapchains, those are recreated on the window's
+        // Resized events instead. Errors are the ones rendering can't recover from, a lost
+        // surface would need a new window.
         let (buffer_num, gpu_fut) =
             match swapchain::acquire_next_image(self.swapchain.clone(), None) {
-                Err(_) => {
-                    return (previous_frame_end, true);
-                }
                 Ok((b, f)) => (b, f),
+                Err(AcquireError::OutOfDate) => return Ok((previous_frame_end, Frame::OutOfDate)),
+                Err(AcquireError::Timeout) => return Ok((previous_frame_end, Frame::Skipped)),
+                Err(e) => return Err(format!("Could not acquire a swapchain image: {}", e)),
             };
         previous_frame_end.cleanup_finished();
 
@@ -99,40 +803,152 @@ impl VkSession {
             self.device.clone(),
             self.queue.family(),
         )
-        .unwrap()
-        .begin_render_pass(
-            self.framebuffers[buffer_num].clone(),
-            false,
-            vec![[0.0, 0.0, 0.0, 1.0].into()],
-        )
         .unwrap();
 
+        // With a virtual resolution or post-processing the scene is drawn offscreen and blitted
+        // to the window after
+        let mut post = self.post.lock().unwrap();
+        let dimensions = match self.virtual_resolution {
+            Some((_, ref target)) => target.dimensions,
+            None => self.swapchain.dimensions(),
+        };
+        if post.is_active() {
+            post.prepare(
+                self.device.clone(),
+                &self.render_pass,
+                dimensions,
+                self.swapchain.format(),
+            );
+        }
+        let scene = match self.virtual_resolution {
+            Some((_, ref target)) => Some(target),
+            None if post.is_active() => Some(post.scene()),
+            None => None,
+        };
+        let scene_framebuffer = match scene {
+            Some(target) => target.framebuffer.clone(),
+            None => self.framebuffers[buffer_num].clone(),
+        };
+
+        // Canvas commands in screen space ignore the cameras and cover the whole scene
+        let screen_state = viewport_state([0.0, 0.0], [dimensions[0] as f32, dimensions[1] as f32]);
+        let screen_camera =
+            Camera2D::new().looking_at(dimensions[0] as f32 / 2.0, dimensions[1] as f32 / 2.0);
+        let screen_set = self.camera_set(self.pipeline.clone(), 1, &screen_camera, dimensions);
+        let screen_shape_set =
+            self.camera_set(self.shape_pipeline.clone(), 0, &screen_camera, dimensions);
+        let views: Vec<View> = cameras
+            .iter()
+            .map(|camera| self.view(camera, dimensions))
+            .collect();
+
         let draws = draw_buffer.lock().unwrap();
 
-        for i in 0..draws.len() {
-            let vertex_buffer = CpuAccessibleBuffer::<[Vertex]>::from_iter(
-                self.device.clone(),
-                BufferUsage::all(),
-                Vertex::from(
-                    (*draws[i].1).clone(),
-                    self.swapchain.dimensions(),
-                    draws[i].2.clone(), // Image dims
-                )
+        // Render textures are drawn first so the scene can sample them
+        let passes = self.render_textures.lock().unwrap().passes();
+        if !passes.is_empty() {
+            let sub_scene: Vec<Draw> = draws
                 .iter()
-                .cloned(),
+                .filter(|d| d.style.render_texture.is_none())
+                .cloned()
+                .collect();
+            for pass in passes {
+                command_buffer = self.draw_render_texture(command_buffer, pass, &sub_scene);
+            }
+        }
+
+        // Cameras draw in order, each batch tagged with the camera it belongs to. Screen space
+        // commands are only drawn once, layered with the last camera's batches.
+        let world_commands: Vec<Command> = commands
+            .iter()
+            .filter(|c| c.space() == Space::World)
+            .cloned()
+            .collect();
+        let mut batches = Vec::new();
+        for (i, (camera, view)) in cameras.iter().zip(&views).enumerate() {
+            let commands = if i + 1 == cameras.len() {
+                commands
+            } else {
+                &world_commands[..]
+            };
+            let camera_batches = self.batches(&draws, commands, camera, view.bounds);
+            // The render pass clears the scene to the first camera's color
+            if let (true, Some(color)) = (i > 0, camera.clear_color) {
+                let [x, y, w, h] = view.bounds;
+                let corner = |cx: f32, cy: f32| ShapeVertex::new([x + cx * w, y + cy * h], color);
+                let (tl, bl, tr, br) = (
+                    corner(0.0, 0.0),
+                    corner(0.0, 1.0),
+                    corner(1.0, 0.0),
+                    corner(1.0, 1.0),
+                );
+                batches.push((
+                    i,
+                    Batch {
+                        layer: i32::min_value(),
+                        space: Space::World,
+                        kind: BatchKind::Clear(vec![tl, bl.clone(), tr.clone(), tr, bl, br]),
+                    },
+                ));
+            }
+            batches.extend(
+                camera_batches
+                    .into_iter()
+                    .filter(|b| b.space == Space::Screen || camera.layers.contains(b.layer))
+                    .map(|b| (i, b)),
+            );
+        }
+
+        command_buffer = self
+            .glyphs
+            .lock()
+            .unwrap()
+            .record_upload(command_buffer, &self.staging);
+
+        // Lights are drawn before the scene and multiplied over the world after it, screen
+        // space batches go on top so they aren't lit
+        let mut lights = self.lights.lock().unwrap();
+        let lit = lights.is_enabled();
+        if lit {
+            lights.prepare(
+                self.device.clone(),
+                &self.render_pass,
+                dimensions,
+                self.swapchain.format(),
+            );
+            command_buffer =
+                lights.draw_lights(command_buffer, &draws, &views, self.device.clone());
+            batches.sort_by_key(|b| b.1.space == Space::Screen);
+        }
+        drop(draws);
+
+        let clear_color = cameras
+            .first()
+            .and_then(|c| c.clear_color)
+            .unwrap_or(graphics::Color::BLACK);
+        command_buffer = command_buffer
+            .begin_render_pass(
+                scene_framebuffer,
+                false,
+                vec![clear_color.to_array().into()],
             )
             .unwrap();
-
-            command_buffer = command_buffer
-                .draw(
-                    self.pipeline.clone(),
-                    &self.dynamic_state,
-                    vertex_buffer,
-                    draws[i].0.clone(),
-                    (),
-                )
-                .unwrap();
+        let mut composited = !lit;
+        for (i, batch) in batches {
+            if !composited && batch.space == Space::Screen {
+                command_buffer = lights.composite(command_buffer, &screen_state);
+                composited = true;
+            }
+            let (state, camera_set, shape_set) = match batch.space {
+                Space::World => (&views[i].state, &views[i].sprite_set, &views[i].shape_set),
+                Space::Screen => (&screen_state, &screen_set, &screen_shape_set),
+            };
+            command_buffer = self.draw_batch(command_buffer, batch, state, camera_set, shape_set);
+        }
+        if !composited {
+            command_buffer = lights.composite(command_buffer, &screen_state);
         }
+        drop(lights);
         let mut awaits = wait_buffer.lock().unwrap();
 
         previous_frame_end = Box::new(previous_frame_end.join(gpu_fut));
@@ -140,10 +956,85 @@ impl VkSession {
             previous_frame_end = Box::new(previous_frame_end.join(f));
         }
 
-        let cb = command_buffer
+        command_buffer = command_buffer
             .end_render_pass()
             .map_err(|e| eprintln!("\n\n{:?}\n\n", e))
-            .unwrap()
+            .unwrap();
+
+        let result = match scene {
+            Some(scene) if post.is_active() => {
+                let (cb, result) = post.run(
+                    command_buffer,
+                    scene,
+                    &mut self.materials.lock().unwrap(),
+                    self.device.clone(),
+                    &self.render_pass,
+                    post::is_srgb(self.swapchain.format()),
+                );
+                command_buffer = cb;
+                Some(result)
+            }
+            _ => scene,
+        };
+
+        if let Some(result) = result {
+            let window = self.swapchain.dimensions();
+            let (origin, size) = match self.virtual_resolution {
+                Some((ref res, _)) => target::letterbox(res, result.dimensions, window),
+                None => ([0.0, 0.0], [window[0] as f32, window[1] as f32]),
+            };
+            let blit_state = viewport_state(origin, size);
+            let set = Arc::new(
+                PersistentDescriptorSet::start(self.blit_pipeline.clone(), 0)
+                    .add_sampled_image(result.image.clone(), self.blit_sampler.clone())
+                    .unwrap()
+                    .build()
+                    .unwrap(),
+            );
+
+            command_buffer = command_buffer
+                .begin_render_pass(
+                    self.framebuffers[buffer_num].clone(),
+                    false,
+                    vec![[0.0, 0.0, 0.0, 1.0].into()],
+                )
+                .unwrap()
+                .draw(
+                    self.blit_pipeline.clone(),
+                    &blit_state,
+                    self.fullscreen_quad.clone(),
+                    set,
+                    (),
+                )
+                .unwrap()
+                .end_render_pass()
+                .unwrap();
+        }
+
+        // The finished frame is copied out after everything else, and read back by the saving
+        // thread once the GPU is done with it
+        let copy = if captures.is_empty() {
+            None
+        } else if !self.can_capture {
+            eprintln!(
+                "Could not save {}: frames of this window can't be copied",
+                captures.join(", ")
+            );
+            None
+        } else {
+            let copy = FrameCopy::new(
+                self.device.clone(),
+                self.swapchain.dimensions(),
+                self.swapchain.format(),
+                captures,
+            );
+            command_buffer = command_buffer
+                .copy_image_to_buffer(self.sc_images[buffer_num].clone(), copy.buffer.clone())
+                .unwrap();
+            Some(copy)
+        };
+
+        let cb = command_buffer
             .build()
             .map_err(|e| eprintln!("\n\n{:?}\n\n", e))
             .unwrap();
@@ -154,123 +1045,168 @@ impl VkSession {
             .then_swapchain_present(self.queue.clone(), self.swapchain.clone(), buffer_num)
             .then_signal_fence_and_flush()
         {
+            Ok(cb) => cb,
             Err(e) => {
-                eprintln!("Skipping frame because {:?}", e);
-                return (
+                let frame = match e {
+                    FlushError::OutOfDate => Frame::OutOfDate,
+                    FlushError::SurfaceLost | FlushError::DeviceLost | FlushError::OomError(_) => {
+                        return Err(format!("Could not present a frame: {}", e));
+                    }
+                    _ => {
+                        eprintln!("Skipping frame because {:?}", e);
+                        Frame::Skipped
+                    }
+                };
+                return Ok((
                     Box::new(vulkano::sync::now(self.device.clone()))
                         as Box<GpuFuture + Send + Sync>,
-                    true,
-                );
+                    frame,
+                ));
             }
-            Ok(cb) => cb,
         };
+        let f = Arc::new(f);
+        if let Some(copy) = copy {
+            copy.save(f.clone());
+        }
 
-        return (Box::new(f) as Box<GpuFuture + Sync + Send>, false);
+        return Ok((
+            Box::new(f) as Box<GpuFuture + Sync + Send>,
+            Frame::Presented,
+        ));
     }
 }
 
-pub fn spawn_render_thread(
-    img_recv: Arc<Mutex<Receiver<(Vec<u8>, (u32, u32), Rect)>>>,
-    queue: Arc<Queue>,
-    device: Arc<Device>,
-    pipeline: Arc<
-        GraphicsPipeline<
-            SingleBufferDefinition<Vertex>,
-            Box<vulkano::descriptor::PipelineLayoutAbstract + Send + Sync>,
-            Arc<RenderPassAbstract + Send + Sync>,
-        >,
-    >,
-    draw_buffer: DrawBuffer,
-    wait_buffer: WaitBuffer,
-) {
-    std::thread::spawn(move || {
-        let sampler = default_sampler(device.clone());
-        loop {
-            let (img_data, dimensions, rect) = img_recv.lock().unwrap().recv().unwrap();
-
-            println!(
-                "Saving image as ImmutableImage buffer with res {}x{}",
-                dimensions.0, dimensions.1
-            );
-            let ft = std::time::SystemTime::now();
-            let (texture, future) = ImmutableImage::from_iter(
-                img_data.iter().cloned(),
-                Dimensions::Dim2d {
-                    width: dimensions.0,
-                    height: dimensions.1,
-                },
-                vulkano::format::Format::R8G8B8A8Srgb,
-                queue.clone(),
-            )
-            .unwrap();
-            println!("{:?}", ft.elapsed());
+// What became of the frame given to VkSession::present.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Frame {
+    // Shown, and being saved if it was to be captured
+    Presented,
+    // The swapchain has to be recreated before the next frame
+    OutOfDate,
+    Skipped,
+}
 
-            let set = Arc::new(
-                PersistentDescriptorSet::start(pipeline.clone(), 0)
-                    .add_sampled_image(texture, sampler.clone())
-                    .unwrap()
-                    .build()
-                    .unwrap(),
-            );
+// What one camera sees: its camera for the sprite pipeline's set 1 and the shape pipeline's
+// set 0, its viewport and the world space bounds it shows.
+pub struct View {
+    pub sprite_set: Arc<DescriptorSet + Send + Sync>,
+    pub shape_set: Arc<DescriptorSet + Send + Sync>,
+    pub state: DynamicState,
+    pub bounds: [f32; 4],
+}
 
-            draw_buffer
-                .lock()
-                .unwrap()
-                .push((set, Arc::new(rect), dimensions));
-            wait_buffer.lock().unwrap().push(Box::new(future));
+// Vertices drawn with a single draw call.
+struct Batch {
+    layer: i32,
+    space: Space,
+    kind: BatchKind,
+}
+
+enum BatchKind {
+    Sprite {
+        pipeline: SpritePipeline,
+        set: Arc<DescriptorSet + Send + Sync>,
+        vertices: Vec<Vertex>,
+    },
+    // Tiles in a vertex buffer that's kept between frames
+    Chunk {
+        set: Arc<DescriptorSet + Send + Sync>,
+        vertices: Arc<CpuAccessibleBuffer<[Vertex]>>,
+    },
+    Shape(Vec<ShapeVertex>),
+    // Replaces what's in a camera's viewport with its clear color
+    Clear(Vec<ShapeVertex>),
+    // A sprite drawn with a material's pipeline, which takes its parameters at set 2
+    Material {
+        pipeline: SpritePipeline,
+        set: Arc<DescriptorSet + Send + Sync>,
+        material_set: Arc<DescriptorSet + Send + Sync>,
+        vertices: Vec<Vertex>,
+    },
+}
+
+// World space bounds [x, y, width, height] of what the camera shows, loose when rotated.
+fn visible_bounds(camera: &Camera2D, dimensions: [u32; 2]) -> [f32; 4] {
+    let ([ox, oy], [w, h]) = camera.viewport.to_pixels(dimensions);
+    let corners = [
+        camera.screen_to_world(ox, oy, dimensions),
+        camera.screen_to_world(ox + w, oy, dimensions),
+        camera.screen_to_world(ox, oy + h, dimensions),
+        camera.screen_to_world(ox + w, oy + h, dimensions),
+    ];
+    let min_x = corners.iter().map(|c| c.0).fold(std::f32::MAX, f32::min);
+    let min_y = corners.iter().map(|c| c.1).fold(std::f32::MAX, f32::min);
+    let max_x = corners.iter().map(|c| c.0).fold(std::f32::MIN, f32::max);
+    let max_y = corners.iter().map(|c| c.1).fold(std::f32::MIN, f32::max);
+    [min_x, min_y, max_x - min_x, max_y - min_y]
+}
+
+// World space bounds [x, y, width, height] of a Rect with its style's transform applied.
+fn rect_bounds(r: &Rect, style: &SpriteStyle) -> [f32; 4] {
+    let corners = [(0.0, 0.0), (1.0, 0.0), (0.0, 1.0), (1.0, 1.0)]
+        .iter()
+        .map(|&(cx, cy)| style.transform.apply(cx, cy, r.width, r.height))
+        .collect::<Vec<_>>();
+    let min_x = corners.iter().map(|c| c.0).fold(std::f32::MAX, f32::min);
+    let min_y = corners.iter().map(|c| c.1).fold(std::f32::MAX, f32::min);
+    let max_x = corners.iter().map(|c| c.0).fold(std::f32::MIN, f32::max);
+    let max_y = corners.iter().map(|c| c.1).fold(std::f32::MIN, f32::max);
+    [
+        r.position_x + min_x,
+        r.position_y + min_y,
+        max_x - min_x,
+        max_y - min_y,
+    ]
+}
+
+pub fn overlaps(a: [f32; 4], b: [f32; 4]) -> bool {
+    a[0] < b[0] + b[2] && b[0] < a[0] + a[2] && a[1] < b[1] + b[3] && b[1] < a[1] + a[3]
+}
+
+// Sorts batches by layer and joins shapes that end up next to each other, they don't need
+// separate draw calls. The sort is stable so sprites keep the order sort_draws gave them.
+fn merge_shapes(mut batches: Vec<Batch>) -> Vec<Batch> {
+    batches.sort_by_key(|b| b.layer);
+    let mut merged: Vec<Batch> = Vec::with_capacity(batches.len());
+    for batch in batches {
+        if let Some(last) = merged.last_mut() {
+            if last.layer == batch.layer && last.space == batch.space {
+                if let (BatchKind::Shape(ref mut a), BatchKind::Shape(ref b)) =
+                    (&mut last.kind, &batch.kind)
+                {
+                    a.extend_from_slice(b);
+                    continue;
+                }
+            }
         }
-    });
+        merged.push(batch);
+    }
+    merged
 }
 
-fn default_sampler(device: Arc<Device>) -> Arc<Sampler> {
-    Sampler::new(
-        device,
-        Filter::Linear,
-        Filter::Linear,
-        MipmapMode::Nearest,
-        SamplerAddressMode::Repeat,
-        SamplerAddressMode::Repeat,
-        SamplerAddressMode::Repeat,
-        0.0,
-        1.0,
-        0.0,
-        0.0,
-    )
-    .unwrap()
+pub fn viewport_state(origin: [f32; 2], dimensions: [f32; 2]) -> DynamicState {
+    DynamicState {
+        line_width: None,
+        viewports: Some(vec![Viewport {
+            origin: origin,
+            dimensions: dimensions,
+            depth_range: 0.0..1.0,
+        }]),
+        scissors: None,
+    }
 }
 
 pub fn recreate_dimensions_dependent(
-    device: Arc<Device>,
     dimensions: [u32; 2],
     swapchain: &mut Arc<Swapchain<winit::Window>>,
     images: &mut Vec<Arc<SwapchainImage<winit::Window>>>,
     render_pass: &std::sync::Arc<dyn RenderPassAbstract + std::marker::Send + std::marker::Sync>,
-) -> (
-    DynamicState,
-    Vec<Arc<FramebufferAbstract + Send + Sync>>,
-    Arc<
-        GraphicsPipeline<
-            SingleBufferDefinition<Vertex>,
-            Box<vulkano::descriptor::PipelineLayoutAbstract + Send + Sync>,
-            Arc<RenderPassAbstract + Send + Sync>,
-        >,
-    >,
-) {
-    let new = swapchain.recreate_with_dimension(dimensions).unwrap();
+) -> Result<Vec<Arc<FramebufferAbstract + Send + Sync>>, SwapchainCreationError> {
+    let new = swapchain.recreate_with_dimension(dimensions)?;
     *swapchain = new.0;
     *images = new.1;
 
-    let viewport = Viewport {
-        origin: [0.0, 0.0],
-        dimensions: [dimensions[0] as f32, dimensions[1] as f32],
-        depth_range: 0.0..1.0,
-    };
-    let dynamic_state = DynamicState {
-        line_width: None,
-        viewports: Some(vec![viewport]),
-        scissors: None,
-    };
-
+    // Viewports are dynamic, every camera sets its own when drawing, see viewport_state
     let framebuffers = {
         images
             .iter()
@@ -286,22 +1222,26 @@ pub fn recreate_dimensions_dependent(
             .collect::<Vec<_>>()
     };
 
-    let vs = shader::vs::Shader::load(device.clone()).unwrap();
-    let fs = shader::fs::Shader::load(device.clone()).unwrap();
+    Ok(framebuffers)
+}
+
+// Pipeline drawing a render target, already in normalized device coordinates, to the screen.
+pub fn blit_pipeline(
+    device: Arc<Device>,
+    render_pass: &Arc<RenderPassAbstract + Send + Sync>,
+) -> SpritePipeline {
+    let vs = shader::blit_vs::Shader::load(device.clone()).unwrap();
+    let fs = shader::blit_fs::Shader::load(device.clone()).unwrap();
 
-    let pipeline = Arc::new(
+    Arc::new(
         GraphicsPipeline::start()
             .vertex_input_single_buffer::<Vertex>()
             .vertex_shader(vs.main_entry_point(), ())
             .triangle_strip()
             .viewports_dynamic_scissors_irrelevant(1)
             .fragment_shader(fs.main_entry_point(), ())
-            .blend_alpha_blending()
             .render_pass(Subpass::from(render_pass.clone(), 0).unwrap())
             .build(device.clone())
             .unwrap(),
-    );
-
-    println!("{:?}", &dimensions);
-    (dynamic_state, framebuffers, pipeline)
+    )
 }
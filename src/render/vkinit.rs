@@ -1,23 +1,40 @@
 extern crate vulkano;
 extern crate vulkano_shaders;
 
-// use crate::render::shader;
-use crate::render::vk::{recreate_dimensions_dependent, Vertex};
-use std::sync::Arc;
+use crate::graphics;
+use crate::render::capture;
+use crate::render::glyphs::GlyphCache;
+use crate::render::lighting::LightCache;
+use crate::render::material::MaterialCache;
+use crate::render::parallax::ParallaxCache;
+use crate::render::particles::ParticleCache;
+use crate::render::pipeline;
+use crate::render::post::{self, PostChain};
+use crate::render::rendertexture::RenderTextureCache;
+use crate::render::residency::Residency;
+use crate::render::sampler::SamplerCache;
+use crate::render::shader;
+use crate::render::target::RenderTarget;
+use crate::render::tilemap::TileMapCache;
+use crate::render::vk::{
+    blit_pipeline, recreate_dimensions_dependent, ShapePipeline, SpritePipeline, Vertex,
+};
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
 
 use vulkano::{
-    buffer::{BufferUsage, CpuAccessibleBuffer},
-    command_buffer::{AutoCommandBufferBuilder, DynamicState},
+    buffer::{BufferUsage, CpuAccessibleBuffer, CpuBufferPool},
+    command_buffer::AutoCommandBufferBuilder,
     descriptor::descriptor_set::PersistentDescriptorSet,
     device,
     format::Format,
     framebuffer::{Framebuffer, FramebufferAbstract, RenderPassAbstract, Subpass},
-    image::{Dimensions, ImmutableImage},
+    image::{Dimensions, ImageUsage, ImmutableImage},
     instance::{Instance, PhysicalDevice},
     pipeline::{vertex::SingleBufferDefinition, viewport::Viewport, GraphicsPipeline},
     sampler::{Filter, MipmapMode, Sampler, SamplerAddressMode},
     swapchain,
-    swapchain::{PresentMode, Surface, SurfaceTransform, Swapchain},
+    swapchain::{ColorSpace, PresentMode, Surface, SurfaceTransform, Swapchain},
     sync::GpuFuture,
 };
 
@@ -25,19 +42,40 @@ pub struct VkSession {
     pub instance: Arc<Instance>,
     pub device: Arc<device::Device>,
     pub queue: Arc<device::Queue>,
+    // Sprite textures are uploaded on this queue, the same as queue when the device has no
+    // queue family for transfers only
+    pub transfer_queue: Arc<device::Queue>,
     // Is it more effective to store dims seperately for draw buffer?
     pub swapchain: Arc<Swapchain<winit::Window>>,
     pub sc_images: Vec<Arc<vulkano::image::SwapchainImage<winit::Window>>>,
     pub render_pass: Arc<RenderPassAbstract + Send + Sync>,
     pub framebuffers: Vec<Arc<FramebufferAbstract + Send + Sync>>,
-    pub dynamic_state: DynamicState,
-    pub pipeline: Arc<
-        GraphicsPipeline<
-            SingleBufferDefinition<Vertex>,
-            Box<vulkano::descriptor::PipelineLayoutAbstract + Send + Sync>,
-            Arc<RenderPassAbstract + Send + Sync>,
-        >,
-    >,
+    pub camera_pool: CpuBufferPool<shader::vs::ty::Camera>,
+    // Staging memory for every texture uploaded on queue, i.e. all but sprite textures
+    pub staging: CpuBufferPool<u8>,
+    pub pipeline: SpritePipeline,
+    pub blend_pipelines: Mutex<HashMap<graphics::BlendMode, SpritePipeline>>,
+    pub shape_pipeline: ShapePipeline,
+    // Fills a camera's viewport with its clear color
+    pub clear_pipeline: ShapePipeline,
+    pub blit_pipeline: SpritePipeline,
+    pub blit_sampler: Arc<Sampler>,
+    pub samplers: Arc<SamplerCache>,
+    pub fullscreen_quad: Arc<CpuAccessibleBuffer<[Vertex]>>,
+    pub glyphs: Mutex<GlyphCache>,
+    pub tilemaps: Mutex<TileMapCache>,
+    pub parallax: Mutex<ParallaxCache>,
+    pub particles: Mutex<ParticleCache>,
+    pub materials: Mutex<MaterialCache>,
+    pub post: Mutex<PostChain>,
+    pub lights: Mutex<LightCache>,
+    pub render_textures: Mutex<RenderTextureCache>,
+    pub residency: Mutex<Residency>,
+    pub virtual_resolution: Option<(graphics::VirtualResolution, RenderTarget)>,
+    // Whether frames can be copied out of the swapchain and saved, see capture::Capture
+    pub can_capture: bool,
+    // What the swapchain's images are presented as
+    pub surface_format: graphics::SurfaceFormat,
 }
 
 pub fn instance() -> Arc<Instance> {
@@ -45,12 +83,16 @@ pub fn instance() -> Arc<Instance> {
     Instance::new(None, &extensions, None).expect("Could not create vulkan instance")
 }
 
-pub fn init(instance: Arc<Instance>, surface: &Arc<Surface<winit::Window>>) -> VkSession {
+pub fn init(
+    instance: Arc<Instance>,
+    surface: &Arc<Surface<winit::Window>>,
+    filter: graphics::Filter,
+) -> VkSession {
     let physical = PhysicalDevice::enumerate(&instance)
         .next()
         .expect("Device does not support Vulkan");
 
-    let (device, queue) = get_device(&physical);
+    let (device, queue, transfer_queue) = get_device(&physical);
 
     let capabilities = surface
         .capabilities(physical)
@@ -62,7 +104,16 @@ pub fn init(instance: Arc<Instance>, surface: &Arc<Surface<winit::Window>>) -> V
         .iter()
         .next()
         .unwrap();
-    let format = capabilities.supported_formats[0].0;
+    let (format, color_space) = surface_format(&capabilities.supported_formats);
+    // Frames are copied out of the swapchain to save them, when the surface allows it and
+    // they're in a format that can be written as a PNG
+    let can_capture =
+        capabilities.supported_usage_flags.transfer_source && capture::can_save(format);
+    let usage = ImageUsage {
+        color_attachment: true,
+        transfer_source: can_capture,
+        ..ImageUsage::none()
+    };
 
     let (mut swapchain, mut images) = Swapchain::new(
         device.clone(),
@@ -71,7 +122,7 @@ pub fn init(instance: Arc<Instance>, surface: &Arc<Surface<winit::Window>>) -> V
         format,
         dimensions,
         1,
-        capabilities.supported_usage_flags,
+        usage,
         &queue,
         SurfaceTransform::Identity,
         alpha,
@@ -100,32 +151,111 @@ pub fn init(instance: Arc<Instance>, surface: &Arc<Surface<winit::Window>>) -> V
         .unwrap(),
     ) as Arc<RenderPassAbstract + Send + Sync>;
 
-    let (dynamic_state, framebuffers, pipeline) = recreate_dimensions_dependent(
-        device.clone(),
+    let framebuffers = recreate_dimensions_dependent(
         images[0].dimensions(),
         &mut swapchain,
         &mut images,
         &render_pass,
-    );
+    )
+    .unwrap();
+    let pipeline =
+        pipeline::sprite_pipeline(device.clone(), &render_pass, graphics::BlendMode::Alpha);
+
+    let fullscreen_quad = CpuAccessibleBuffer::from_iter(
+        device.clone(),
+        BufferUsage::vertex_buffer(),
+        Vertex::fullscreen().iter().cloned(),
+    )
+    .unwrap();
+
+    let samplers = Arc::new(SamplerCache::new(device.clone()));
+    let post = PostChain::new(device.clone(), &render_pass, &pipeline, &samplers);
+    let lights = LightCache::new(device.clone(), &render_pass, &samplers);
 
     VkSession {
+        blend_pipelines: Mutex::new(HashMap::new()),
+        shape_pipeline: pipeline::shape_pipeline(
+            device.clone(),
+            &render_pass,
+            Some(graphics::BlendMode::Alpha),
+        ),
+        clear_pipeline: pipeline::shape_pipeline(device.clone(), &render_pass, None),
+        blit_pipeline: blit_pipeline(device.clone(), &render_pass),
+        blit_sampler: samplers.get(graphics::Sampling {
+            filter: filter,
+            wrap: graphics::Wrap::Clamp,
+            ..graphics::Sampling::default()
+        }),
+        samplers: samplers,
+        fullscreen_quad: fullscreen_quad,
+        glyphs: Mutex::new(GlyphCache::new()),
+        tilemaps: Mutex::new(TileMapCache::new()),
+        parallax: Mutex::new(ParallaxCache::new()),
+        particles: Mutex::new(ParticleCache::new()),
+        materials: Mutex::new(MaterialCache::new(device.clone())),
+        post: Mutex::new(post),
+        lights: Mutex::new(lights),
+        render_textures: Mutex::new(RenderTextureCache::new()),
+        residency: Mutex::new(Residency::new()),
+        virtual_resolution: None,
+        can_capture: can_capture,
+        surface_format: graphics::SurfaceFormat {
+            srgb: post::is_srgb(format),
+            color_space: match color_space {
+                ColorSpace::SrgbNonLinear => graphics::ColorSpace::Srgb,
+                _ => graphics::ColorSpace::Other,
+            },
+        },
         instance: instance,
-        device: device,
+        device: device.clone(),
         queue: queue,
+        transfer_queue: transfer_queue,
         swapchain: swapchain,
         sc_images: images,
         render_pass: render_pass,
         framebuffers: framebuffers,
         pipeline: pipeline,
-        dynamic_state: dynamic_state,
+        camera_pool: CpuBufferPool::uniform_buffer(device.clone()),
+        staging: CpuBufferPool::upload(device.clone()),
     }
 }
 
-fn get_device(physical: &PhysicalDevice) -> (Arc<device::Device>, Arc<device::Queue>) {
+// Picks the swapchain's format. Textures are sampled as sRGB and blended in linear space, so a
+// format which encodes to sRGB on write keeps colors as they were authored, whichever format
+// the driver happens to list first. Vulkano 0.11 only creates swapchains in the sRGB non-linear
+// color space, so HDR10 and scRGB surfaces can't be presented to. A surface without an sRGB
+// non-linear format gets its first format, which the game sees as ColorSpace::Other.
+fn surface_format(formats: &[(Format, ColorSpace)]) -> (Format, ColorSpace) {
+    let srgb: Vec<(Format, ColorSpace)> = formats
+        .iter()
+        .cloned()
+        .filter(|&(_, c)| c == ColorSpace::SrgbNonLinear)
+        .collect();
+    let preferred = [Format::B8G8R8A8Srgb, Format::R8G8B8A8Srgb];
+    preferred
+        .iter()
+        .filter_map(|&p| srgb.iter().cloned().find(|&(f, _)| f == p))
+        .chain(srgb.iter().cloned().filter(|&(f, _)| post::is_srgb(f)))
+        .chain(srgb.iter().cloned())
+        .next()
+        .unwrap_or(formats[0])
+}
+
+fn get_device(
+    physical: &PhysicalDevice,
+) -> (Arc<device::Device>, Arc<device::Queue>, Arc<device::Queue>) {
     let queue_family = physical
         .queue_families()
         .find(|&q| q.supports_graphics())
         .expect("Device does not support vulkan");
+    // Families without graphics or compute are the ones backed by the GPU's copy engines
+    let transfer_family = physical
+        .queue_families()
+        .find(|&q| q.supports_transfers() && !q.supports_graphics() && !q.supports_compute());
+    let mut families = vec![(queue_family, 0.5)];
+    if let Some(family) = transfer_family {
+        families.push((family, 0.5));
+    }
 
     let extensions = device::DeviceExtensions {
         khr_swapchain: true,
@@ -135,9 +265,11 @@ fn get_device(physical: &PhysicalDevice) -> (Arc<device::Device>, Arc<device::Qu
         *physical,
         physical.supported_features(),
         &extensions,
-        [(queue_family, 0.5)].iter().cloned(),
+        families.into_iter(),
     )
     .unwrap();
 
-    (device, queues.next().unwrap())
+    let queue = queues.next().unwrap();
+    let transfer_queue = queues.next().unwrap_or(queue.clone());
+    (device, queue, transfer_queue)
 }
@@ -0,0 +1,61 @@
+// Offscreen images the world is drawn into from a camera of their own, e.g. for minimaps,
+// split-screen, portals or previews in a menu. Sprites show a render texture with
+// SpriteStyle::with_render_texture. Render textures aren't lit or post-processed, don't show
+// canvas drawing and don't show sprites that show a render texture themselves.
+
+use crate::camera::{Camera2D, Layers};
+use crate::graphics::{Color, Sampling};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RenderTextureId(pub(crate) usize);
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderTexture {
+    // Size of the image in pixels
+    pub width: u32,
+    pub height: u32,
+    // Looks at the world like Game::camera, its viewport is relative to the texture
+    pub camera: Camera2D,
+    // The draw layers the texture shows
+    pub layers: Layers,
+    pub clear_color: Color,
+    // A disabled render texture isn't redrawn and keeps showing its last frame
+    pub enabled: bool,
+    // None uses the game's default filter and clamps at the edges. Mipmaps aren't generated
+    // for render textures.
+    pub sampling: Option<Sampling>,
+}
+
+impl RenderTexture {
+    pub fn new(width: u32, height: u32) -> Self {
+        RenderTexture {
+            width: width,
+            height: height,
+            camera: Camera2D::new(),
+            layers: Layers::All,
+            clear_color: Color::TRANSPARENT,
+            enabled: true,
+            sampling: None,
+        }
+    }
+
+    pub fn with_camera(mut self, camera: Camera2D) -> Self {
+        self.camera = camera;
+        self
+    }
+
+    pub fn with_layers(mut self, layers: Layers) -> Self {
+        self.layers = layers;
+        self
+    }
+
+    pub fn with_clear_color(mut self, color: Color) -> Self {
+        self.clear_color = color;
+        self
+    }
+
+    pub fn with_sampling(mut self, sampling: Sampling) -> Self {
+        self.sampling = Some(sampling);
+        self
+    }
+}
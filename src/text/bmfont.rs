@@ -0,0 +1,130 @@
+// Parser for the text variant of AngelCode's BMFont format (.fnt).
+// Format reference: https://www.angelcode.com/products/bmfont/doc/file_format.html
+
+use std::collections::HashMap;
+use std::path::Path;
+
+pub struct BmFont {
+    // The size the font was generated at, in pixels
+    pub size: f32,
+    pub line_height: f32,
+    pub base: f32,
+    pub chars: HashMap<u32, BmChar>,
+    pub kerning: HashMap<(u32, u32), f32>,
+    pub pages: Vec<image::RgbaImage>,
+}
+
+pub struct BmChar {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub x_offset: f32,
+    pub y_offset: f32,
+    pub x_advance: f32,
+    pub page: usize,
+}
+
+pub fn load(path: &str) -> Result<BmFont, String> {
+    let source =
+        std::fs::read_to_string(path).map_err(|e| format!("Could not open {}: {}", path, e))?;
+    let dir = Path::new(path).parent().unwrap_or(Path::new(""));
+
+    let mut font = BmFont {
+        size: 0.0,
+        line_height: 0.0,
+        base: 0.0,
+        chars: HashMap::new(),
+        kerning: HashMap::new(),
+        pages: Vec::new(),
+    };
+    let mut page_files: Vec<(usize, String)> = Vec::new();
+
+    for line in source.lines() {
+        let mut tokens = tokenize(line).into_iter();
+        let tag = match tokens.next() {
+            Some(t) => t,
+            None => continue,
+        };
+        let attrs = tokens
+            .filter_map(|t| {
+                let mut kv = t.splitn(2, '=');
+                Some((kv.next()?.to_string(), kv.next()?.to_string()))
+            })
+            .collect::<HashMap<_, _>>();
+        let num = |key: &str| -> f32 { attrs.get(key).and_then(|v| v.parse().ok()).unwrap_or(0.0) };
+
+        match tag.as_str() {
+            // A negative size means the font was generated to match character height
+            "info" => font.size = num("size").abs(),
+            "common" => {
+                font.line_height = num("lineHeight");
+                font.base = num("base");
+            }
+            "page" => {
+                let file = attrs.get("file").cloned().unwrap_or_default();
+                page_files.push((num("id") as usize, file));
+            }
+            "char" => {
+                font.chars.insert(
+                    num("id") as u32,
+                    BmChar {
+                        x: num("x") as u32,
+                        y: num("y") as u32,
+                        width: num("width") as u32,
+                        height: num("height") as u32,
+                        x_offset: num("xoffset"),
+                        y_offset: num("yoffset"),
+                        x_advance: num("xadvance"),
+                        page: num("page") as usize,
+                    },
+                );
+            }
+            "kerning" => {
+                font.kerning
+                    .insert((num("first") as u32, num("second") as u32), num("amount"));
+            }
+            _ => {}
+        }
+    }
+
+    if font.size == 0.0 {
+        font.size = font.line_height;
+    }
+    if font.size == 0.0 {
+        return Err(format!("{} is not a text BMFont file", path));
+    }
+
+    page_files.sort_by_key(|p| p.0);
+    for (_, file) in page_files {
+        let page_path = dir.join(&file);
+        let page = image::open(&page_path)
+            .map_err(|e| format!("Could not open {}: {}", page_path.display(), e))?;
+        font.pages.push(page.to_rgba());
+    }
+    Ok(font)
+}
+
+// Splits a line on spaces that aren't inside double quotes, dropping the quotes.
+fn tokenize(line: &str) -> Vec<String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+
+    for c in line.chars() {
+        match c {
+            '"' => quoted = !quoted,
+            ' ' | '\t' if !quoted => {
+                if !current.is_empty() {
+                    tokens.push(current.clone());
+                    current.clear();
+                }
+            }
+            _ => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    tokens
+}
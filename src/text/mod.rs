@@ -0,0 +1,425 @@
+// Fonts and text layout. TrueType/OpenType fonts are rasterized on demand, BMFont bitmap fonts
+// are cut out of their page images. Either way glyphs end up in the renderer's glyph atlas.
+
+mod bmfont;
+
+use crate::graphics::Color;
+
+use rusttype::{point, GlyphId, Scale};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FontId(pub(crate) usize);
+
+pub struct Font {
+    id: FontId,
+    kind: FontKind,
+}
+
+enum FontKind {
+    TrueType(rusttype::Font<'static>),
+    Bitmap(bmfont::BmFont),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Align {
+    Left,
+    Center,
+    Right,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct TextStyle {
+    // Line height in pixels, before the camera's zoom
+    pub size: f32,
+    pub color: Color,
+    pub align: Align,
+    // Lines longer than this are wrapped at word boundaries
+    pub wrap_width: Option<f32>,
+}
+
+impl TextStyle {
+    pub fn new(size: f32) -> Self {
+        TextStyle {
+            size: size,
+            color: Color::WHITE,
+            align: Align::Left,
+            wrap_width: None,
+        }
+    }
+
+    pub fn with_color(mut self, color: Color) -> Self {
+        self.color = color;
+        self
+    }
+
+    pub fn with_align(mut self, align: Align) -> Self {
+        self.align = align;
+        self
+    }
+
+    pub fn wrapped(mut self, width: f32) -> Self {
+        self.wrap_width = Some(width);
+        self
+    }
+}
+
+// Identifies a rasterized glyph in the glyph atlas.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct GlyphKey {
+    pub font: FontId,
+    pub glyph: u32,
+    // Pixel size for TrueType fonts, bitmap fonts are only stored at their own size
+    pub size: u32,
+}
+
+// A glyph quad relative to the top-left corner of the laid out text.
+#[derive(Debug, Clone, Copy)]
+pub struct PlacedGlyph {
+    pub key: GlyphKey,
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+// width is the widest line's, which is less than the style's wrap width when no line fills it.
+#[derive(Debug, Clone)]
+pub struct TextLayout {
+    pub glyphs: Vec<PlacedGlyph>,
+    pub width: f32,
+    pub height: f32,
+}
+
+// Placement of a single glyph on a line, relative to the pen position and the line's top.
+struct GlyphMetrics {
+    key: GlyphKey,
+    advance: f32,
+    bounds: Option<(f32, f32, f32, f32)>,
+}
+
+impl Font {
+    // Loads a .fnt BMFont file or a TrueType/OpenType font.
+    pub(crate) fn load(id: FontId, path: &str) -> Result<Font, String> {
+        let is_bmfont = path.to_lowercase().ends_with(".fnt");
+        let kind = if is_bmfont {
+            FontKind::Bitmap(bmfont::load(path)?)
+        } else {
+            let bytes =
+                std::fs::read(path).map_err(|e| format!("Could not open {}: {}", path, e))?;
+            let font = rusttype::Font::from_bytes(bytes)
+                .map_err(|e| format!("Could not load font {}: {}", path, e))?;
+            FontKind::TrueType(font)
+        };
+        Ok(Font { id: id, kind: kind })
+    }
+
+    pub fn id(&self) -> FontId {
+        self.id
+    }
+
+    pub fn line_height(&self, size: f32) -> f32 {
+        match self.kind {
+            FontKind::TrueType(ref font) => {
+                let v = font.v_metrics(Scale::uniform(size.round()));
+                v.ascent - v.descent + v.line_gap
+            }
+            FontKind::Bitmap(ref font) => font.line_height * size / font.size,
+        }
+    }
+
+    // Size of the text's bounding box once laid out with style.
+    pub fn measure(&self, text: &str, style: &TextStyle) -> (f32, f32) {
+        let layout = self.layout(text, style);
+        (layout.width, layout.height)
+    }
+
+    pub fn layout(&self, text: &str, style: &TextStyle) -> TextLayout {
+        let line_height = self.line_height(style.size);
+
+        // Break the text into lines of glyphs
+        let mut lines: Vec<(Vec<(GlyphMetrics, f32)>, f32)> = Vec::new();
+        for paragraph in text.split('\n') {
+            let mut line: Vec<(GlyphMetrics, f32)> = Vec::new();
+            let mut pen = 0.0;
+            let mut last_space: Option<usize> = None;
+            let mut prev: Option<char> = None;
+
+            for c in paragraph.chars() {
+                let metrics = self.glyph(c, style.size);
+                let mut x = pen + prev.map_or(0.0, |p| self.kerning(p, c, style.size));
+                let end = x + metrics.bounds.map_or(metrics.advance, |b| b.0 + b.2);
+
+                if let Some(width) = style.wrap_width {
+                    if end > width && !line.is_empty() && !c.is_whitespace() {
+                        // Move the current word to a new line, or break it if it's all there is
+                        let split = last_space.map_or(line.len(), |i| i + 1);
+                        let rest = line.split_off(split);
+                        let shift = rest.first().map_or(x, |g| g.1);
+                        let width = line_width(&line);
+                        lines.push((std::mem::replace(&mut line, Vec::new()), width));
+
+                        line.extend(rest.into_iter().map(|(g, gx)| (g, gx - shift)));
+                        x -= shift;
+                        last_space = None;
+                    }
+                }
+
+                if c.is_whitespace() {
+                    last_space = Some(line.len());
+                }
+                pen = x + metrics.advance;
+                line.push((metrics, x));
+                prev = Some(c);
+            }
+            let width = line_width(&line);
+            lines.push((line, width));
+        }
+
+        let width = lines.iter().map(|l| l.1).fold(0.0, f32::max);
+        // Wrapped lines are aligned within the wrap width
+        let align_width = style.wrap_width.unwrap_or(width);
+        let mut glyphs = Vec::new();
+        for (i, (line, used)) in lines.iter().enumerate() {
+            let offset = match style.align {
+                Align::Left => 0.0,
+                Align::Center => ((align_width - used) / 2.0).round(),
+                Align::Right => (align_width - used).round(),
+            };
+            let top = i as f32 * line_height;
+            for (metrics, x) in line {
+                if let Some((bx, by, bw, bh)) = metrics.bounds {
+                    glyphs.push(PlacedGlyph {
+                        key: metrics.key,
+                        x: offset + x.round() + bx,
+                        y: top + by,
+                        width: bw,
+                        height: bh,
+                    });
+                }
+            }
+        }
+
+        TextLayout {
+            glyphs: glyphs,
+            width: width,
+            height: lines.len() as f32 * line_height,
+        }
+    }
+
+    fn glyph(&self, c: char, size: f32) -> GlyphMetrics {
+        match self.kind {
+            FontKind::TrueType(ref font) => {
+                let scale = Scale::uniform(size.round());
+                let ascent = font.v_metrics(scale).ascent;
+                let glyph = font.glyph(c).scaled(scale);
+                let advance = glyph.h_metrics().advance_width;
+                let key = GlyphKey {
+                    font: self.id,
+                    glyph: glyph.id().0,
+                    size: size.round() as u32,
+                };
+                let bounds = glyph
+                    .positioned(point(0.0, 0.0))
+                    .pixel_bounding_box()
+                    .map(|bb| {
+                        (
+                            bb.min.x as f32,
+                            ascent.round() + bb.min.y as f32,
+                            bb.width() as f32,
+                            bb.height() as f32,
+                        )
+                    });
+                GlyphMetrics {
+                    key: key,
+                    advance: advance,
+                    bounds: bounds,
+                }
+            }
+            FontKind::Bitmap(ref font) => {
+                let scale = size / font.size;
+                let key = GlyphKey {
+                    font: self.id,
+                    glyph: c as u32,
+                    size: 0,
+                };
+                match font.chars.get(&(c as u32)) {
+                    Some(ch) => GlyphMetrics {
+                        key: key,
+                        advance: ch.x_advance * scale,
+                        bounds: if ch.width == 0 || ch.height == 0 {
+                            None
+                        } else {
+                            Some((
+                                ch.x_offset * scale,
+                                ch.y_offset * scale,
+                                ch.width as f32 * scale,
+                                ch.height as f32 * scale,
+                            ))
+                        },
+                    },
+                    None => GlyphMetrics {
+                        key: key,
+                        advance: 0.0,
+                        bounds: None,
+                    },
+                }
+            }
+        }
+    }
+
+    fn kerning(&self, prev: char, c: char, size: f32) -> f32 {
+        match self.kind {
+            FontKind::TrueType(ref font) => {
+                font.pair_kerning(Scale::uniform(size.round()), prev, c)
+            }
+            FontKind::Bitmap(ref font) => {
+                font.kerning
+                    .get(&(prev as u32, c as u32))
+                    .cloned()
+                    .unwrap_or(0.0)
+                    * size
+                    / font.size
+            }
+        }
+    }
+
+    // Renders a glyph as white RGBA8 pixels with coverage in the alpha channel, or the
+    // original page pixels for bitmap fonts.
+    pub(crate) fn rasterize(&self, key: GlyphKey) -> Option<(Vec<u8>, u32, u32)> {
+        match self.kind {
+            FontKind::TrueType(ref font) => {
+                let glyph = font
+                    .glyph(GlyphId(key.glyph))
+                    .scaled(Scale::uniform(key.size as f32))
+                    .positioned(point(0.0, 0.0));
+                let bb = glyph.pixel_bounding_box()?;
+                let (w, h) = (bb.width() as u32, bb.height() as u32);
+                let mut pixels = vec![255u8; (w * h * 4) as usize];
+                glyph.draw(|x, y, v| {
+                    pixels[((y * w + x) * 4 + 3) as usize] = (v * 255.0).round() as u8;
+                });
+                Some((pixels, w, h))
+            }
+            FontKind::Bitmap(ref font) => {
+                let ch = font.chars.get(&key.glyph)?;
+                let page = font.pages.get(ch.page)?;
+                if ch.x + ch.width > page.width() || ch.y + ch.height > page.height() {
+                    return None;
+                }
+                let mut pixels = Vec::with_capacity((ch.width * ch.height * 4) as usize);
+                for y in ch.y..ch.y + ch.height {
+                    for x in ch.x..ch.x + ch.width {
+                        pixels.extend_from_slice(&page.get_pixel(x, y).data);
+                    }
+                }
+                Some((pixels, ch.width, ch.height))
+            }
+        }
+    }
+}
+
+fn line_width(line: &[(GlyphMetrics, f32)]) -> f32 {
+    line.iter()
+        .filter(|(g, _)| g.bounds.is_some())
+        .map(|(g, x)| x + g.bounds.map_or(0.0, |b| b.0 + b.2))
+        .fold(0.0, f32::max)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashMap;
+
+    // A 10 pixel monospace bitmap font, every glyph 8 pixels wide apart from the space, with
+    // A and V kerned 2 pixels closer
+    fn font() -> Font {
+        let mut chars = HashMap::new();
+        for c in "abcdAV ".chars() {
+            let width = if c == ' ' { 0 } else { 8 };
+            chars.insert(
+                c as u32,
+                bmfont::BmChar {
+                    x: 0,
+                    y: 0,
+                    width: width,
+                    height: 10,
+                    x_offset: 0.0,
+                    y_offset: 0.0,
+                    x_advance: 10.0,
+                    page: 0,
+                },
+            );
+        }
+        let mut kerning = HashMap::new();
+        kerning.insert(('A' as u32, 'V' as u32), -2.0);
+        Font {
+            id: FontId(0),
+            kind: FontKind::Bitmap(bmfont::BmFont {
+                size: 10.0,
+                line_height: 10.0,
+                base: 8.0,
+                chars: chars,
+                kerning: kerning,
+                pages: Vec::new(),
+            }),
+        }
+    }
+
+    fn positions(layout: &TextLayout) -> Vec<(f32, f32)> {
+        layout.glyphs.iter().map(|g| (g.x, g.y)).collect()
+    }
+
+    #[test]
+    fn single_line() {
+        let layout = font().layout("ab", &TextStyle::new(10.0));
+        assert_eq!(positions(&layout), vec![(0.0, 0.0), (10.0, 0.0)]);
+        assert_eq!(font().measure("ab", &TextStyle::new(10.0)), (18.0, 10.0));
+    }
+
+    #[test]
+    fn wrap() {
+        let style = TextStyle::new(10.0).wrapped(30.0);
+        let layout = font().layout("ab cd", &style);
+        assert_eq!(
+            positions(&layout),
+            vec![(0.0, 0.0), (10.0, 0.0), (0.0, 10.0), (10.0, 10.0)]
+        );
+        // The widest line, not the wrap width
+        assert_eq!(font().measure("ab cd", &style), (18.0, 20.0));
+
+        // A word longer than the wrap width is broken
+        let layout = font().layout("abcd", &style);
+        assert_eq!(layout.glyphs.len(), 4);
+        assert_eq!(layout.glyphs[3].y, 10.0);
+    }
+
+    #[test]
+    fn align() {
+        let right = TextStyle::new(10.0).with_align(Align::Right);
+        let layout = font().layout("ab\nc", &right);
+        assert_eq!(layout.glyphs[2].x, 10.0);
+
+        let center = TextStyle::new(10.0).with_align(Align::Center);
+        let layout = font().layout("ab\nc", &center);
+        assert_eq!(layout.glyphs[2].x, 5.0);
+
+        // Wrapped lines are aligned within the wrap width
+        let wrapped = right.clone().wrapped(30.0);
+        let layout = font().layout("ab cd", &wrapped);
+        assert_eq!(layout.glyphs[0].x, 12.0);
+        assert_eq!(layout.width, 18.0);
+    }
+
+    #[test]
+    fn kerning() {
+        let style = TextStyle::new(10.0);
+        let layout = font().layout("AV", &style);
+        assert_eq!(positions(&layout), vec![(0.0, 0.0), (8.0, 0.0)]);
+        assert_eq!(font().measure("AV", &style).0, 16.0);
+
+        // Kerning scales with the text size
+        let layout = font().layout("AV", &TextStyle::new(20.0));
+        assert_eq!(layout.glyphs[1].x, 16.0);
+    }
+}
@@ -0,0 +1,333 @@
+// Grids of tiles cut out of tileset images. The renderer keeps a vertex buffer for every
+// chunk of CHUNK_SIZE x CHUNK_SIZE tiles of a layer and only rebuilds the chunks whose tiles
+// changed, so large levels cost a handful of draw calls per frame.
+
+use crate::graphics::{Color, Sampling};
+
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+pub const CHUNK_SIZE: u32 = 16;
+
+// Tells maps apart in the renderer's chunk cache.
+static NEXT_MAP_ID: AtomicUsize = AtomicUsize::new(0);
+// Tells tilesets apart in the renderer's texture cache.
+static NEXT_TILESET_ID: AtomicUsize = AtomicUsize::new(0);
+
+pub struct Tileset {
+    id: usize,
+    pub(crate) pixels: Arc<Vec<u8>>,
+    pub(crate) dimensions: (u32, u32),
+    pub tile_width: u32,
+    pub tile_height: u32,
+    // Pixels around the tiles and between neighbouring tiles in the image
+    pub margin: u32,
+    pub spacing: u32,
+    // None uses the game's default sampling
+    pub sampling: Option<Sampling>,
+}
+
+impl Tileset {
+    pub fn load(path: &str, tile_width: u32, tile_height: u32) -> Result<Tileset, String> {
+        let img = image::open(path).map_err(|e| format!("Could not open {}: {}", path, e))?;
+        let img = img.to_rgba();
+        Ok(Tileset::from_pixels(
+            img.dimensions(),
+            img.into_raw(),
+            tile_width,
+            tile_height,
+        ))
+    }
+
+    // Tileset from RGBA8 pixels.
+    pub fn from_pixels(
+        dimensions: (u32, u32),
+        pixels: Vec<u8>,
+        tile_width: u32,
+        tile_height: u32,
+    ) -> Tileset {
+        Tileset {
+            id: NEXT_TILESET_ID.fetch_add(1, Ordering::Relaxed),
+            pixels: Arc::new(pixels),
+            dimensions: dimensions,
+            tile_width: tile_width,
+            tile_height: tile_height,
+            margin: 0,
+            spacing: 0,
+            sampling: None,
+        }
+    }
+
+    pub(crate) fn id(&self) -> usize {
+        self.id
+    }
+
+    pub fn with_spacing(mut self, margin: u32, spacing: u32) -> Self {
+        self.margin = margin;
+        self.spacing = spacing;
+        self
+    }
+
+    pub fn with_sampling(mut self, sampling: Sampling) -> Self {
+        self.sampling = Some(sampling);
+        self
+    }
+
+    pub fn columns(&self) -> u32 {
+        (self.dimensions.0.saturating_sub(self.margin * 2) + self.spacing)
+            / (self.tile_width + self.spacing).max(1)
+    }
+
+    pub fn rows(&self) -> u32 {
+        (self.dimensions.1.saturating_sub(self.margin * 2) + self.spacing)
+            / (self.tile_height + self.spacing).max(1)
+    }
+
+    pub fn tile_count(&self) -> u32 {
+        self.columns() * self.rows()
+    }
+
+    // Texture coordinates [u0, v0, u1, v1] of a tile, counted row by row from the top-left.
+    pub fn uv(&self, index: u32) -> [f32; 4] {
+        let columns = self.columns().max(1);
+        let x = self.margin + (index % columns) * (self.tile_width + self.spacing);
+        let y = self.margin + (index / columns) * (self.tile_height + self.spacing);
+        let (w, h) = (self.dimensions.0 as f32, self.dimensions.1 as f32);
+        [
+            x as f32 / w,
+            y as f32 / h,
+            (x + self.tile_width) as f32 / w,
+            (y + self.tile_height) as f32 / h,
+        ]
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Tile {
+    // Index of the tileset in TileMap::tilesets
+    pub tileset: usize,
+    pub index: u32,
+    pub flip_x: bool,
+    pub flip_y: bool,
+    // Swaps the tile's x and y axes, combined with the flips this rotates tiles
+    pub flip_diagonal: bool,
+}
+
+impl Tile {
+    pub fn new(index: u32) -> Self {
+        Tile {
+            tileset: 0,
+            index: index,
+            flip_x: false,
+            flip_y: false,
+            flip_diagonal: false,
+        }
+    }
+
+    pub fn from_tileset(mut self, tileset: usize) -> Self {
+        self.tileset = tileset;
+        self
+    }
+
+    pub fn flip(mut self, horizontal: bool, vertical: bool, diagonal: bool) -> Self {
+        self.flip_x = horizontal;
+        self.flip_y = vertical;
+        self.flip_diagonal = diagonal;
+        self
+    }
+}
+
+pub struct TileLayer {
+    pub name: String,
+    // Draw layer of the tiles, see SpriteStyle::layer
+    pub layer: i32,
+    pub visible: bool,
+    pub tint: Color,
+    tiles: Vec<Option<Tile>>,
+    // Bumped whenever a tile in the chunk changes
+    revisions: Vec<u64>,
+}
+
+impl TileLayer {
+    pub(crate) fn revision(&self, chunk: usize) -> u64 {
+        self.revisions[chunk]
+    }
+}
+
+pub struct TileMap {
+    id: usize,
+    // Size in tiles
+    pub width: u32,
+    pub height: u32,
+    // Size of a grid cell in world units. Tiles larger than a cell stick out to the top and
+    // right, as they do in Tiled.
+    pub tile_width: f32,
+    pub tile_height: f32,
+    // World position of the top-left corner
+    pub position_x: f32,
+    pub position_y: f32,
+    pub tilesets: Vec<Tileset>,
+    layers: Vec<TileLayer>,
+}
+
+impl TileMap {
+    pub fn new(width: u32, height: u32, tile_width: f32, tile_height: f32) -> Self {
+        TileMap {
+            id: NEXT_MAP_ID.fetch_add(1, Ordering::Relaxed),
+            width: width,
+            height: height,
+            tile_width: tile_width,
+            tile_height: tile_height,
+            position_x: 0.0,
+            position_y: 0.0,
+            tilesets: Vec::new(),
+            layers: Vec::new(),
+        }
+    }
+
+    pub fn at(mut self, x: f32, y: f32) -> Self {
+        self.position_x = x;
+        self.position_y = y;
+        self
+    }
+
+    pub(crate) fn id(&self) -> usize {
+        self.id
+    }
+
+    // Returns the index of the tileset for Tile::from_tileset.
+    pub fn add_tileset(&mut self, tileset: Tileset) -> usize {
+        self.tilesets.push(tileset);
+        self.tilesets.len() - 1
+    }
+
+    // Adds an empty layer drawn on the given draw layer and returns its index.
+    pub fn add_layer(&mut self, name: &str, layer: i32) -> usize {
+        let chunks = self.chunks_x() * self.chunks_y();
+        self.layers.push(TileLayer {
+            name: name.to_string(),
+            layer: layer,
+            visible: true,
+            tint: Color::WHITE,
+            tiles: vec![None; (self.width * self.height) as usize],
+            revisions: vec![0; chunks as usize],
+        });
+        self.layers.len() - 1
+    }
+
+    pub fn layers(&self) -> &[TileLayer] {
+        &self.layers
+    }
+
+    pub fn layer_mut(&mut self, layer: usize) -> &mut TileLayer {
+        &mut self.layers[layer]
+    }
+
+    pub fn layer_index(&self, name: &str) -> Option<usize> {
+        self.layers.iter().position(|l| l.name == name)
+    }
+
+    pub fn tile(&self, layer: usize, x: u32, y: u32) -> Option<Tile> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        self.layers[layer].tiles[(y * self.width + x) as usize]
+    }
+
+    pub fn set_tile(&mut self, layer: usize, x: u32, y: u32, tile: Option<Tile>) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let chunk = self.chunk_of(x, y);
+        let layer = &mut self.layers[layer];
+        let slot = &mut layer.tiles[(y * self.width + x) as usize];
+        if *slot != tile {
+            *slot = tile;
+            layer.revisions[chunk] += 1;
+        }
+    }
+
+    // Fills a layer from tiles listed row by row, e.g. straight from a level file.
+    pub fn set_tiles(&mut self, layer: usize, tiles: Vec<Option<Tile>>) -> Result<(), String> {
+        if tiles.len() != (self.width * self.height) as usize {
+            return Err(format!(
+                "Expected {} tiles for a {}x{} map, got {}",
+                self.width * self.height,
+                self.width,
+                self.height,
+                tiles.len()
+            ));
+        }
+        let layer = &mut self.layers[layer];
+        layer.tiles = tiles;
+        for revision in layer.revisions.iter_mut() {
+            *revision += 1;
+        }
+        Ok(())
+    }
+
+    // Grid cell containing a world position.
+    pub fn world_to_tile(&self, x: f32, y: f32) -> Option<(u32, u32)> {
+        let tx = ((x - self.position_x) / self.tile_width).floor();
+        let ty = ((y - self.position_y) / self.tile_height).floor();
+        if tx < 0.0 || ty < 0.0 || tx >= self.width as f32 || ty >= self.height as f32 {
+            None
+        } else {
+            Some((tx as u32, ty as u32))
+        }
+    }
+
+    pub fn chunks_x(&self) -> u32 {
+        (self.width + CHUNK_SIZE - 1) / CHUNK_SIZE
+    }
+
+    pub fn chunks_y(&self) -> u32 {
+        (self.height + CHUNK_SIZE - 1) / CHUNK_SIZE
+    }
+
+    fn chunk_of(&self, x: u32, y: u32) -> usize {
+        ((y / CHUNK_SIZE) * self.chunks_x() + x / CHUNK_SIZE) as usize
+    }
+
+    // Tiles of a chunk with their grid position.
+    pub(crate) fn chunk_tiles(
+        &self,
+        layer: usize,
+        chunk: usize,
+    ) -> impl Iterator<Item = (u32, u32, Tile)> + '_ {
+        let cx = chunk as u32 % self.chunks_x() * CHUNK_SIZE;
+        let cy = chunk as u32 / self.chunks_x() * CHUNK_SIZE;
+        let (x_end, y_end) = (
+            (cx + CHUNK_SIZE).min(self.width),
+            (cy + CHUNK_SIZE).min(self.height),
+        );
+        let layer = &self.layers[layer];
+        let width = self.width;
+        (cy..y_end).flat_map(move |y| {
+            (cx..x_end)
+                .filter_map(move |x| layer.tiles[(y * width + x) as usize].map(|tile| (x, y, tile)))
+        })
+    }
+
+    // World space bounds [x, y, width, height] of a chunk, including tiles sticking out of it.
+    pub(crate) fn chunk_bounds(&self, chunk: usize) -> [f32; 4] {
+        let cx = (chunk as u32 % self.chunks_x() * CHUNK_SIZE) as f32;
+        let cy = (chunk as u32 / self.chunks_x() * CHUNK_SIZE) as f32;
+        let overhang_x = self
+            .tilesets
+            .iter()
+            .map(|t| t.tile_width as f32 - self.tile_width)
+            .fold(0.0, f32::max);
+        let overhang_y = self
+            .tilesets
+            .iter()
+            .map(|t| t.tile_height as f32 - self.tile_height)
+            .fold(0.0, f32::max);
+        [
+            self.position_x + cx * self.tile_width,
+            self.position_y + cy * self.tile_height - overhang_y,
+            CHUNK_SIZE as f32 * self.tile_width + overhang_x,
+            CHUNK_SIZE as f32 * self.tile_height + overhang_y,
+        ]
+    }
+}